@@ -0,0 +1,106 @@
+//! Times `Machine::run` against `Machine::run_threaded` on tight loops.
+//! Run with `cargo bench`.
+
+use sick_as::{parse_instruction, BufferIo, Instruction, Machine, MachineConfig, Threaded};
+use std::time::{Duration, Instant};
+
+/// How many times each program runs per engine. The fastest one counts.
+const RUNS: usize = 10;
+
+/// Counts m2 down from 255, 255 times over. The inner loop is three
+/// instructions with a `bak` by a literal.
+const NESTED: &str = "
+set 255 -> m1
+set 255 -> m2
+sub m2, 1 -> m2
+not m2 -> m3
+bak 2, m3
+sub m1, 1 -> m1
+not m1 -> m3
+bak 6, m3
+bye 0
+";
+
+/// Same thing, but jumping back by a count in memory, so neither engine can
+/// know where it lands ahead of time.
+const DYNAMIC: &str = "
+set 2 -> m9
+set 3 -> m8
+set 255 -> m1
+set 255 -> m2
+sub m2, 1 -> m2
+not m2 -> m3
+bak m9, m3
+sub m1, 1 -> m1
+not m1 -> m3
+bak m8, m3
+bye 0
+";
+
+/// Counting m4 all the way round with `add`, 256 times over.
+const ADD_LOOP: &str = "
+set 1 -> m5
+add m4, m5 -> m4
+not m4 -> m7
+bak 2, m7
+add m6, m5 -> m6
+not m6 -> m7
+bak 5, m7
+bye m4
+";
+
+fn parse(source: &str) -> Vec<Instruction> {
+    source
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| parse_instruction(line.trim()).expect("Benchmarks should parse"))
+        .collect()
+}
+
+/// The fastest of `RUNS` runs, and how many steps each one took.
+fn time(mut run: impl FnMut() -> u64) -> (Duration, u64) {
+    let mut best = Duration::MAX;
+    let mut steps = 0;
+    for _ in 0..RUNS {
+        let started = Instant::now();
+        steps = run();
+        best = best.min(started.elapsed());
+    }
+    (best, steps)
+}
+
+fn main() {
+    println!(
+        "{:<10} {:>10} {:>12} {:>12} {:>8}",
+        "program", "steps", "step ns", "threaded ns", "speedup"
+    );
+
+    for (name, source) in [("nested", NESTED), ("dynamic", DYNAMIC), ("add", ADD_LOOP)] {
+        let instructions = parse(source);
+        let program = Threaded::compile(&instructions);
+
+        let (naive, steps) = time(|| {
+            let mut machine = Machine::with_io(MachineConfig::default(), BufferIo::default());
+            machine.run(&instructions).expect("Benchmarks should run");
+            machine.steps()
+        });
+        let (threaded, threaded_steps) = time(|| {
+            let mut machine = Machine::with_io(MachineConfig::default(), BufferIo::default());
+            machine
+                .run_threaded(&program)
+                .expect("Benchmarks should run");
+            machine.steps()
+        });
+        assert_eq!(steps, threaded_steps, "The engines disagree on {}", name);
+
+        let per_step = |time: Duration| time.as_nanos() as f64 / steps as f64;
+        println!(
+            "{:<10} {:>10} {:>12.2} {:>12.2} {:>7.2}x",
+            name,
+            steps,
+            per_step(naive),
+            per_step(threaded),
+            naive.as_secs_f64() / threaded.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,7 @@
+Reads two digits and prints their sum as long as it's a single digit
+
+,>,                      read both
+>++++++[<-------->-]<    take 48 off the second
+[<+>-]                   add it to the first
+<.                       print
+>++++++++++.             newline
@@ -0,0 +1,6 @@
+A to Z and a newline
+
+++++++++[>++++++++<-]>+  cell 1 is 65 which is 'A'
+>++++++++++++++++++++++++++ cell 2 counts 26 letters
+[<.+>-]
+++++++++++.
@@ -0,0 +1,6 @@
+Prints 0 to 9 and a newline
+
+++++++++[>++++++<-]>     cell 1 is 48 which is '0'
+>++++++++++              cell 2 counts down from 10
+[<.+>-]
+++++++++++.              and cell 2 is 0 again so make it a newline
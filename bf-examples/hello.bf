@@ -0,0 +1,5 @@
+Hello World from the old Wikipedia example
+
+++++++++++[>+++++++>++++++++++>+++>+<<<<-]
+>++.>+.+++++++..+++.>++.<<+++++++++++++++.
+>.+++.------.--------.>+.>.
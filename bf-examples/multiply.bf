@@ -0,0 +1,4 @@
+Nested loops: 6 * 7 * 2 = 84 which is 'T'
+
+++++++[>+++++++[>++<-]<-]>>.
+[-]++++++++++.
@@ -0,0 +1,3 @@
+Hello World from Wikipedia which moves the pointer inside loops
+
+++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.
@@ -0,0 +1,294 @@
+use super::{is_value, split_word, Item};
+use crate::source::SourceLine;
+
+/// A block that hasn't hit its `.end` yet.
+struct Open {
+    /// The directive that opened it, for errors.
+    name: &'static str,
+    at: SourceLine,
+    /// Where a `.while` goes back to. `None` for the ifs.
+    top: Option<String>,
+    /// Where to go when the condition doesn't hold, which is the `.else`
+    /// for an if that has one.
+    skip: String,
+    /// Past the `.else` part, once there is one.
+    end: Option<String>,
+}
+
+/// Everything needed while lowering, so it doesn't all get passed around.
+struct Lowerer {
+    out: Vec<Item>,
+    open: Vec<Open>,
+    /// For making up label names.
+    labels: usize,
+}
+
+impl Lowerer {
+    fn label(&mut self) -> String {
+        self.labels += 1;
+        format!("@{}", self.labels)
+    }
+
+    /// Adds a line of code, pretending it came from `at`.
+    fn emit(&mut self, at: &SourceLine, code: String) {
+        self.out.push(Item {
+            code,
+            at: at.clone(),
+        });
+    }
+
+    fn open(&mut self, item: &Item, name: &'static str, check: &str) -> Result<(), String> {
+        if !is_value(check) {
+            return Err(item.at.error(format!(
+                "`{}` needs something to check, like `{} m1`",
+                name, name
+            )));
+        }
+
+        let skip = self.label();
+        let mut top = None;
+        match name {
+            // Goes past the end once it's 0
+            ".while" => {
+                let label = self.label();
+                self.emit(&item.at, format!("{}:", label));
+                self.emit(&item.at, format!("fwd {}, {}", skip, check));
+                top = Some(label);
+            }
+            // `fwd` only jumps on 0, so it takes two to skip on anything else
+            ".ifz" => {
+                let then = self.label();
+                self.emit(&item.at, format!("fwd {}, {}", then, check));
+                self.emit(&item.at, format!("fwd {}, 0", skip));
+                self.emit(&item.at, format!("{}:", then));
+            }
+            ".ifnz" => self.emit(&item.at, format!("fwd {}, {}", skip, check)),
+            _ => unreachable!(),
+        }
+
+        self.open.push(Open {
+            name,
+            at: item.at.clone(),
+            top,
+            skip,
+            end: None,
+        });
+        Ok(())
+    }
+
+    fn otherwise(&mut self, item: &Item) -> Result<(), String> {
+        let block = self.open.pop();
+        let end = self.label();
+
+        match block {
+            None => Err(item.at.error("`.else` without an `.ifz` or `.ifnz`")),
+            Some(Open { name: ".while", .. }) => {
+                Err(item.at.error("`.else` only goes with `.ifz` or `.ifnz`"))
+            }
+            Some(Open {
+                name,
+                at,
+                end: Some(_),
+                ..
+            }) => Err(item.at.error(format!(
+                "The `{}` at {}:{} already has an `.else`",
+                name, at.file, at.line
+            ))),
+            Some(mut block) => {
+                self.emit(&item.at, format!("fwd {}, 0", end));
+                self.emit(&item.at, format!("{}:", block.skip));
+                block.end = Some(end);
+                self.open.push(block);
+                Ok(())
+            }
+        }
+    }
+
+    fn end(&mut self, item: &Item) -> Result<(), String> {
+        let block = self
+            .open
+            .pop()
+            .ok_or_else(|| item.at.error("`.end` without a block to close"))?;
+
+        if let Some(top) = block.top {
+            self.emit(&item.at, format!("bak {}, 0", top));
+        }
+        let last = block.end.unwrap_or(block.skip);
+        self.emit(&item.at, format!("{}:", last));
+        Ok(())
+    }
+}
+
+/// Turns `.while`, `.ifz`, `.ifnz`, `.else` and `.end` into plain `fwd`s and
+/// `bak`s, with made up labels for the labels pass to work out.
+pub fn lower(items: Vec<Item>) -> Result<Vec<Item>, String> {
+    let mut lowerer = Lowerer {
+        out: Vec::with_capacity(items.len()),
+        open: Vec::new(),
+        labels: 0,
+    };
+
+    for item in items {
+        let (word, rest) = split_word(&item.code);
+        match (word, rest) {
+            (".while", check) => lowerer.open(&item, ".while", check)?,
+            (".ifz", check) => lowerer.open(&item, ".ifz", check)?,
+            (".ifnz", check) => lowerer.open(&item, ".ifnz", check)?,
+            (".else", "") => lowerer.otherwise(&item)?,
+            (".end", "") => lowerer.end(&item)?,
+            (".else" | ".end", rest) => {
+                return Err(item.at.error(format!("Unexpected characters: `{}`", rest)))
+            }
+            _ => lowerer.out.push(item),
+        }
+    }
+
+    match lowerer.open.last() {
+        Some(block) => Err(block
+            .at
+            .error(format!("`{}` without an `.end`", block.name))),
+        None => Ok(lowerer.out),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::io::BufferIo;
+    use crate::machine::{Machine, MachineConfig};
+    use crate::source::{read_str, strip_comment, SourceConfig};
+
+    fn lines(src: &str) -> Vec<SourceLine> {
+        read_str("test.sick", src, &SourceConfig::default()).unwrap()
+    }
+
+    fn lower_str(src: &str) -> Result<Vec<String>, String> {
+        let items = lines(src)
+            .into_iter()
+            .filter_map(|at| {
+                let code = strip_comment(&at.text).to_owned();
+                (!code.is_empty()).then_some(Item { code, at })
+            })
+            .collect();
+        lower(items).map(|items| items.into_iter().map(|item| item.code).collect())
+    }
+
+    const NESTED: &str = "\
+set 3 -> m1
+.while m1
+    .ifz m2
+        out 65
+    .else
+        out 66
+    .end
+    not m2 -> m2
+    sub m1, 1 -> m1
+.end
+.ifnz m1
+    out 67
+.end
+bye 0
+";
+
+    #[test]
+    fn nested() {
+        assert_eq!(
+            lower_str(NESTED).unwrap(),
+            [
+                "set 3 -> m1",
+                "@2:",
+                "fwd @1, m1",
+                "fwd @4, m2",
+                "fwd @3, 0",
+                "@4:",
+                "out 65",
+                "fwd @5, 0",
+                "@3:",
+                "out 66",
+                "@5:",
+                "not m2 -> m2",
+                "sub m1, 1 -> m1",
+                "bak @2, 0",
+                "@1:",
+                "fwd @6, m1",
+                "out 67",
+                "@6:",
+                "bye 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn counts() {
+        let program = assemble(lines(NESTED)).unwrap();
+        let text: Vec<String> = program
+            .instructions
+            .iter()
+            .map(|inst| inst.to_string())
+            .collect();
+        assert_eq!(
+            text,
+            [
+                "set 3 -> m1",
+                "fwd 9, m1",
+                "fwd 2, m2",
+                "fwd 3, 0",
+                "out 65",
+                "fwd 2, 0",
+                "out 66",
+                "not m2 -> m2",
+                "sub m1, 1 -> m1",
+                "bak 8, 0",
+                "fwd 2, m1",
+                "out 67",
+                "bye 0",
+            ]
+        );
+
+        let mut machine = Machine::with_io(MachineConfig::default(), BufferIo::new(b""));
+        assert_eq!(machine.run(&program.instructions), Ok(0));
+        assert_eq!(machine.io.output, b"ABA");
+    }
+
+    #[test]
+    fn errors() {
+        let err = |src: &str| lower_str(src).unwrap_err();
+        assert_eq!(
+            err("nop\n.end"),
+            "test.sick:2: `.end` without a block to close"
+        );
+        assert_eq!(
+            err(".else"),
+            "test.sick:1: `.else` without an `.ifz` or `.ifnz`"
+        );
+        assert_eq!(
+            err(".while m1\n.else\n.end"),
+            "test.sick:2: `.else` only goes with `.ifz` or `.ifnz`"
+        );
+        assert_eq!(
+            err(".ifz m1\n.else\n.else\n.end"),
+            "test.sick:3: The `.ifz` at test.sick:1 already has an `.else`"
+        );
+        assert_eq!(
+            err(".while m1\n    .ifnz m2\n    .end\nnop"),
+            "test.sick:1: `.while` without an `.end`"
+        );
+        assert_eq!(
+            err(".while m1\n    .ifnz m2\n.end"),
+            "test.sick:1: `.while` without an `.end`"
+        );
+        assert_eq!(
+            err(".ifz\n.end"),
+            "test.sick:1: `.ifz` needs something to check, like `.ifz m1`"
+        );
+        assert_eq!(
+            err(".ifnz m300\n.end"),
+            "test.sick:1: `.ifnz` needs something to check, like `.ifnz m1`"
+        );
+        assert_eq!(
+            err(".ifz m1\n.end m1"),
+            "test.sick:2: Unexpected characters: `m1`"
+        );
+    }
+}
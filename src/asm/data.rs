@@ -0,0 +1,202 @@
+use super::{split_word, string, Item};
+use crate::machine::MEMORY_SIZE;
+use crate::program::Data;
+use crate::source::SourceLine;
+use std::collections::BTreeMap;
+
+/// The address and bytes from the rest of a `.data` line.
+fn parse(args: &str) -> Result<(usize, Vec<u8>), String> {
+    let (target, mut rest) = args
+        .split_once('=')
+        .ok_or("`.data` needs an address and values, like `.data m10 = 1, 2, 3`")?;
+    let target = target.trim();
+    let start = target
+        .strip_prefix('m')
+        .and_then(|addr| addr.parse::<u8>().ok())
+        .ok_or_else(|| format!("`{}` isn't a memory address", target))?;
+
+    let mut bytes = Vec::new();
+    loop {
+        rest = rest.trim_start();
+        if rest.starts_with('"') {
+            let (string, after) = string(rest)?;
+            bytes.extend(string);
+            rest = after;
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            let val = rest[..end].trim();
+            bytes.push(
+                val.parse()
+                    .map_err(|_| format!("`{}` isn't a number from 0 to 255", val))?,
+            );
+            rest = &rest[end..];
+        }
+
+        rest = rest.trim_start();
+        match rest.strip_prefix(',') {
+            Some(after) => rest = after,
+            None if rest.is_empty() => break,
+            None => return Err(format!("Unexpected characters: `{}`", rest)),
+        }
+    }
+
+    Ok((start as usize, bytes))
+}
+
+/// Takes out the `.data` lines, and gives back what they put in memory as
+/// (address, value) in order of address.
+pub fn extract(items: Vec<Item>) -> Result<(Vec<Item>, Data), String> {
+    // Address -> (value, the line that set it)
+    let mut cells: BTreeMap<usize, (u8, SourceLine)> = BTreeMap::new();
+    let mut rest = Vec::new();
+
+    for item in items {
+        let (word, args) = split_word(&item.code);
+        if word != ".data" {
+            rest.push(item);
+            continue;
+        }
+
+        let (start, bytes) = parse(args).map_err(|msg| item.at.error(msg))?;
+        if start == 0 {
+            return Err(item
+                .at
+                .error("`.data` can't set m0, it's the instruction pointer"));
+        }
+        if start + bytes.len() > MEMORY_SIZE {
+            return Err(item.at.error(format!(
+                "The data runs past m{}, the end of memory",
+                MEMORY_SIZE - 1
+            )));
+        }
+
+        for (addr, val) in (start..).zip(bytes) {
+            if let Some((_, other)) = cells.get(&addr) {
+                return Err(item.at.error(format!(
+                    "m{} is already set by the `.data` at {}:{}",
+                    addr, other.file, other.line
+                )));
+            }
+            cells.insert(addr, (val, item.at.clone()));
+        }
+    }
+
+    let data = cells
+        .into_iter()
+        .map(|(addr, (val, _))| (addr as u8, val))
+        .collect();
+    Ok((rest, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::{read_str, strip_comment, SourceConfig};
+
+    fn extract_str(src: &str) -> Result<(Vec<String>, Data), String> {
+        let items = read_str("test.sick", src, &SourceConfig::default())
+            .unwrap()
+            .into_iter()
+            .filter_map(|at| {
+                let code = strip_comment(&at.text).to_owned();
+                (!code.is_empty()).then_some(Item { code, at })
+            })
+            .collect();
+        extract(items)
+            .map(|(items, data)| (items.into_iter().map(|item| item.code).collect(), data))
+    }
+
+    fn data(src: &str) -> Result<Data, String> {
+        extract_str(src).map(|(_, data)| data)
+    }
+
+    #[test]
+    fn numbers_and_strings() {
+        assert_eq!(
+            extract_str("nop\n.data m10 = 1, 2,3 # comment\nbye 0"),
+            Ok((
+                vec!["nop".to_owned(), "bye 0".to_owned()],
+                vec![(10, 1), (11, 2), (12, 3)]
+            ))
+        );
+        assert_eq!(
+            data(".data m20 = \"a,b\", 0, \"\\n\\\"\""),
+            Ok(vec![
+                (20, b'a'),
+                (21, b','),
+                (22, b'b'),
+                (23, 0),
+                (24, b'\n'),
+                (25, b'"')
+            ])
+        );
+        // In order of address, not of where they are
+        assert_eq!(
+            data(".data m30 = 3\n.data m5 = 255"),
+            Ok(vec![(5, 255), (30, 3)])
+        );
+    }
+
+    #[test]
+    fn overlap() {
+        assert_eq!(
+            data(".data m10 = \"abc\"\nnop\n.data m12 = 1"),
+            Err("test.sick:3: m12 is already set by the `.data` at test.sick:1".to_owned())
+        );
+        assert_eq!(
+            data(".data m10 = 1\n.data m9 = 1, 2"),
+            Err("test.sick:2: m10 is already set by the `.data` at test.sick:1".to_owned())
+        );
+        // Right next to each other is fine
+        assert_eq!(
+            data(".data m10 = 1\n.data m11 = 2").map(|data| data.len()),
+            Ok(2)
+        );
+    }
+
+    #[test]
+    fn edges_of_memory() {
+        assert_eq!(
+            data(".data m0 = 5"),
+            Err("test.sick:1: `.data` can't set m0, it's the instruction pointer".to_owned())
+        );
+        assert_eq!(data(".data m254 = 7"), Ok(vec![(254, 7)]));
+        assert_eq!(
+            data(".data m253 = 1, 2, 3"),
+            Err("test.sick:1: The data runs past m254, the end of memory".to_owned())
+        );
+        assert_eq!(
+            data(".data m255 = 1"),
+            Err("test.sick:1: The data runs past m254, the end of memory".to_owned())
+        );
+        assert_eq!(
+            data(".data m256 = 1"),
+            Err("test.sick:1: `m256` isn't a memory address".to_owned())
+        );
+    }
+
+    #[test]
+    fn bad_values() {
+        let err = |src: &str| data(src).unwrap_err();
+        assert_eq!(
+            err(".data m10"),
+            "test.sick:1: `.data` needs an address and values, like `.data m10 = 1, 2, 3`"
+        );
+        assert_eq!(
+            err(".data 10 = 1"),
+            "test.sick:1: `10` isn't a memory address"
+        );
+        assert_eq!(
+            err(".data m10 = 256"),
+            "test.sick:1: `256` isn't a number from 0 to 255"
+        );
+        assert_eq!(
+            err(".data m10 = \"a\" 1"),
+            "test.sick:1: Unexpected characters: `1`"
+        );
+        assert_eq!(
+            err(".data m10 = \"\\q\""),
+            "test.sick:1: Unknown escape `\\q`"
+        );
+    }
+}
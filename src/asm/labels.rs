@@ -0,0 +1,136 @@
+use super::{is_label, is_name, label, split_word, Item};
+use crate::source::SourceLine;
+use std::collections::HashMap;
+
+/// The labels other files get to see, and the jumps to labels from other
+/// files. Both are by instruction index.
+#[derive(Debug, Default)]
+pub struct Symbols {
+    pub exports: Vec<(String, usize)>,
+    pub imports: Vec<(usize, String)>,
+}
+
+/// The name from an `.export` or `.import` line.
+fn symbol<'a>(word: &str, rest: &'a str) -> Result<&'a str, String> {
+    match is_name(rest) {
+        true => Ok(rest),
+        false => Err(format!(
+            "`{}` needs the name of a label, like `{} loop`",
+            word, word
+        )),
+    }
+}
+
+/// Takes out the `name:` lines, and swaps every label used as the count of a
+/// `bak` or `fwd` for how far away it is. Jumps to an `.import`ed label get a
+/// count of 0 for the linker to fill in.
+pub fn resolve(items: Vec<Item>) -> Result<(Vec<Item>, Symbols), String> {
+    // Name -> (the instruction it's in front of, where it was defined)
+    let mut labels: HashMap<String, (usize, SourceLine)> = HashMap::new();
+    let mut exports: Vec<(String, SourceLine)> = Vec::new();
+    let mut imports: HashMap<String, SourceLine> = HashMap::new();
+    let mut code = Vec::new();
+
+    for item in items {
+        match split_word(&item.code) {
+            (".export", rest) => {
+                let name = symbol(".export", rest).map_err(|msg| item.at.error(msg))?;
+                exports.push((name.to_owned(), item.at));
+                continue;
+            }
+            (".import", rest) => {
+                let name = symbol(".import", rest).map_err(|msg| item.at.error(msg))?;
+                imports.insert(name.to_owned(), item.at);
+                continue;
+            }
+            _ => {}
+        }
+
+        let name = match label(&item.code) {
+            Some(name) => name.to_owned(),
+            None => {
+                code.push(item);
+                continue;
+            }
+        };
+
+        if let Some((_, other)) = labels.get(&name) {
+            return Err(item.at.error(format!(
+                "`{}` is already defined at {}:{}",
+                name, other.file, other.line
+            )));
+        }
+        labels.insert(name, (code.len(), item.at));
+    }
+
+    for (name, at) in &imports {
+        if let Some((_, other)) = labels.get(name) {
+            return Err(at.error(format!(
+                "`{}` is imported, but it's defined here at {}:{}",
+                name, other.file, other.line
+            )));
+        }
+    }
+
+    let mut symbols = Symbols::default();
+    for (name, at) in exports {
+        let idx = labels
+            .get(&name)
+            .ok_or_else(|| at.error(format!("Can't export `{}`, it isn't a label", name)))?
+            .0;
+        if symbols.exports.iter().any(|(other, _)| *other == name) {
+            return Err(at.error(format!("`{}` is already exported", name)));
+        }
+        symbols.exports.push((name, idx));
+    }
+
+    for (idx, item) in code.iter_mut().enumerate() {
+        let (word, rest) = split_word(&item.code);
+        if word != "bak" && word != "fwd" {
+            continue;
+        }
+        let (count, check) = rest.split_once(',').unwrap_or((rest, ""));
+        let count = count.trim();
+        if !is_label(count) {
+            continue;
+        }
+
+        if imports.contains_key(count) {
+            symbols.imports.push((idx, count.to_owned()));
+            item.code = format!("{} 0, {}", word, check.trim());
+            continue;
+        }
+
+        let target = labels
+            .get(count)
+            .ok_or_else(|| item.at.error(format!("Unknown label `{}`", count)))?
+            .0;
+
+        let distance = match word {
+            "bak" => idx.checked_sub(target).ok_or_else(|| {
+                item.at.error(format!(
+                    "`{}` is ahead of this `bak`, it needs a `fwd`",
+                    count
+                ))
+            })?,
+            _ => target.checked_sub(idx).ok_or_else(|| {
+                item.at.error(format!(
+                    "`{}` is behind this `fwd`, it needs a `bak`",
+                    count
+                ))
+            })?,
+        };
+        if distance > u8::MAX as usize {
+            return Err(item.at.error(format!(
+                "`{}` is {} instructions away, a jump can only go {}",
+                count,
+                distance,
+                u8::MAX
+            )));
+        }
+
+        item.code = format!("{} {}, {}", word, distance, check.trim());
+    }
+
+    Ok((code, symbols))
+}
@@ -0,0 +1,394 @@
+use super::{is_name, label, split_word, Item, MNEMONICS};
+use crate::source::SourceLine;
+use std::collections::{HashMap, HashSet};
+
+/// How deep macros can call each other before it's probably a macro calling
+/// itself forever.
+const MAX_DEPTH: usize = 64;
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Item>,
+    at: SourceLine,
+}
+
+/// Everything needed while expanding, so it doesn't all get passed around.
+struct Expander {
+    macros: HashMap<String, Macro>,
+    /// How many expansions so far, for giving labels unique names.
+    expansions: usize,
+    out: Vec<Item>,
+}
+
+/// The name and parameters from the rest of a `.macro` line.
+fn definition(rest: &str) -> Result<(String, Vec<String>), String> {
+    let (name, params) = split_word(rest);
+    if !is_name(name) {
+        return Err(format!("`{}` can't be the name of a macro", name));
+    }
+    if MNEMONICS.contains(&name) || name == "prn" {
+        return Err(format!(
+            "`{}` is an instruction, macros need their own name",
+            name
+        ));
+    }
+
+    let params: Vec<&str> = match params {
+        "" => Vec::new(),
+        _ => params.split(',').map(str::trim).collect(),
+    };
+
+    let mut seen = HashSet::new();
+    let mut out = Vec::new();
+    for param in params {
+        if !is_name(param) {
+            return Err(format!("`{}` can't be the name of a parameter", param));
+        }
+        if !seen.insert(param) {
+            return Err(format!("`{}` is in there twice", param));
+        }
+        out.push(param.to_owned());
+    }
+
+    Ok((name.to_owned(), out))
+}
+
+/// Calls `f` on every run of name characters outside quotes, and keeps
+/// whatever it gives back. The bool is whether there's a `\` in front.
+fn map_words(
+    code: &str,
+    mut f: impl FnMut(&str, bool) -> Result<Option<String>, String>,
+) -> Result<String, String> {
+    let mut out = String::new();
+    let mut quoted = false;
+    let mut rest = code;
+
+    while let Some(c) = rest.chars().next() {
+        // Strings go through untouched, escapes and all
+        if quoted {
+            let len = match c {
+                '\\' => 1 + rest[1..].chars().next().map_or(0, char::len_utf8),
+                _ => c.len_utf8(),
+            };
+            quoted = c != '"';
+            out.push_str(&rest[..len]);
+            rest = &rest[len..];
+            continue;
+        }
+
+        let escaped = c == '\\';
+        let word = &rest[escaped as usize..];
+        let len = word
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(word.len());
+        if len == 0 {
+            quoted = c == '"';
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+
+        let taken = len + escaped as usize;
+        match f(&word[..len], escaped)? {
+            Some(new) => out.push_str(&new),
+            None => out.push_str(&rest[..taken]),
+        }
+        rest = &rest[taken..];
+    }
+
+    Ok(out)
+}
+
+impl Expander {
+    fn is_call(&self, code: &str) -> bool {
+        self.macros.contains_key(split_word(code).0)
+    }
+
+    fn call(&mut self, item: &Item, depth: usize) -> Result<(), String> {
+        let (name, rest) = split_word(&item.code);
+        if depth >= MAX_DEPTH {
+            // The whole chain of calls would be a bit much, so just the first
+            let mut first = &item.at;
+            while let Some((_, call)) = &first.expanded_from {
+                first = call;
+            }
+            return Err(first.error(format!(
+                "Macros are nested more than {} deep, does `{}` call itself?",
+                MAX_DEPTH, name
+            )));
+        }
+
+        self.expansions += 1;
+        let suffix = format!("@{}", self.expansions);
+
+        let mac = &self.macros[name];
+        // `->` works as a comma too, so calls can look like instructions
+        let rest = rest.replace("->", ",");
+        let args: Vec<&str> = match rest.as_str() {
+            "" => Vec::new(),
+            _ => rest.split(',').map(str::trim).collect(),
+        };
+        if args.len() != mac.params.len() {
+            return Err(item.at.error(format!(
+                "`{}` takes {} arguments, got {} (it's defined at {}:{})",
+                name,
+                mac.params.len(),
+                args.len(),
+                mac.at.file,
+                mac.at.line
+            )));
+        }
+        if args.iter().any(|arg| arg.is_empty()) {
+            return Err(item.at.error("Empty argument"));
+        }
+
+        let locals: HashSet<&str> = mac
+            .body
+            .iter()
+            .filter_map(|line| label(&line.code))
+            .collect();
+
+        let mut expanded = Vec::new();
+        for line in &mac.body {
+            let at = SourceLine {
+                expanded_from: Some((name.to_owned(), Box::new(item.at.clone()))),
+                ..line.at.clone()
+            };
+
+            let code = map_words(&line.code, |word, escaped| match escaped {
+                true => match mac.params.iter().position(|param| param == word) {
+                    Some(idx) => Ok(Some(args[idx].to_owned())),
+                    None => Err(format!("`\\{}` isn't one of `{}`'s parameters", word, name)),
+                },
+                false => Ok(locals.contains(word).then(|| format!("{}{}", word, suffix))),
+            })
+            .map_err(|msg| at.error(msg))?;
+
+            expanded.push(Item { code, at });
+        }
+
+        for item in expanded {
+            match self.is_call(&item.code) {
+                true => self.call(&item, depth + 1)?,
+                false => self.out.push(item),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Takes out the `.macro` definitions and puts their bodies wherever
+/// they're called. Parameters are used as `\name` in the body, and labels
+/// defined in a body are only seen by that one expansion.
+pub fn expand(items: Vec<Item>) -> Result<Vec<Item>, String> {
+    let mut expander = Expander {
+        macros: HashMap::new(),
+        expansions: 0,
+        out: Vec::new(),
+    };
+
+    let mut items = items.into_iter();
+    while let Some(item) = items.next() {
+        let (word, rest) = split_word(&item.code);
+        match word {
+            ".macro" => {
+                let (name, params) = definition(rest).map_err(|msg| item.at.error(msg))?;
+                if let Some(other) = expander.macros.get(&name) {
+                    return Err(item.at.error(format!(
+                        "`{}` is already defined at {}:{}",
+                        name, other.at.file, other.at.line
+                    )));
+                }
+
+                let mut body = Vec::new();
+                loop {
+                    let line = items
+                        .next()
+                        .ok_or_else(|| item.at.error("`.macro` without an `.endm`"))?;
+                    match split_word(&line.code) {
+                        (".endm", "") => break,
+                        (".endm", rest) => {
+                            return Err(line.at.error(format!("Unexpected characters: `{}`", rest)))
+                        }
+                        (".macro", _) => {
+                            return Err(line
+                                .at
+                                .error("Macros can't be defined inside other macros"))
+                        }
+                        _ => body.push(line),
+                    }
+                }
+
+                expander.macros.insert(
+                    name,
+                    Macro {
+                        params,
+                        body,
+                        at: item.at,
+                    },
+                );
+            }
+            ".endm" => return Err(item.at.error("`.endm` without a `.macro`")),
+            _ if expander.is_call(&item.code) => expander.call(&item, 0)?,
+            _ => expander.out.push(item),
+        }
+    }
+
+    Ok(expander.out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::source::{read_str, strip_comment, SourceConfig};
+
+    fn lines(src: &str) -> Vec<SourceLine> {
+        read_str("test.sick", src, &SourceConfig::default()).unwrap()
+    }
+
+    fn expand_str(src: &str) -> Result<Vec<String>, String> {
+        let items = lines(src)
+            .into_iter()
+            .filter_map(|at| {
+                let code = strip_comment(&at.text).to_owned();
+                (!code.is_empty()).then_some(Item { code, at })
+            })
+            .collect();
+        expand(items).map(|items| items.into_iter().map(|item| item.code).collect())
+    }
+
+    const OR: &str = "\
+.macro or a, b, tgt
+    not \\a -> m250
+    not \\b -> m251
+    and m250, m251 -> m250
+    not m250 -> \\tgt
+.endm
+";
+
+    #[test]
+    fn parameters() {
+        assert_eq!(
+            expand_str(&format!("{}or m1, 7 -> m3\nor m4, m5, m6", OR)),
+            Ok(vec![
+                "not m1 -> m250".to_owned(),
+                "not 7 -> m251".to_owned(),
+                "and m250, m251 -> m250".to_owned(),
+                "not m250 -> m3".to_owned(),
+                "not m4 -> m250".to_owned(),
+                "not m5 -> m251".to_owned(),
+                "and m250, m251 -> m250".to_owned(),
+                "not m250 -> m6".to_owned(),
+            ])
+        );
+    }
+
+    #[test]
+    fn only_escaped_names_outside_strings() {
+        let src = ".macro say a\n    prn \"\\a a\"\n    out \\a\n    a:\n.endm\nsay 65";
+        assert_eq!(
+            expand_str(src),
+            Ok(vec![
+                "prn \"\\a a\"".to_owned(),
+                "out 65".to_owned(),
+                "a@1:".to_owned(),
+            ])
+        );
+    }
+
+    #[test]
+    fn local_labels() {
+        let src = "\
+.macro count_down cell
+loop:
+    sub \\cell, 1 -> \\cell
+    fwd done, \\cell
+    bak loop, 0
+done:
+.endm
+set 3 -> m1
+set 2 -> m2
+count_down m1
+count_down m2
+bye 0
+";
+        let expanded = expand_str(src).unwrap();
+        assert_eq!(expanded[2], "loop@1:");
+        assert_eq!(expanded[4], "fwd done@1, m1");
+        assert_eq!(expanded[7], "loop@2:");
+        assert_eq!(expanded[9], "fwd done@2, m2");
+
+        // Each one jumps around itself, not the other one
+        let program = assemble(lines(src)).unwrap();
+        let text: Vec<String> = program
+            .instructions
+            .iter()
+            .map(|inst| inst.to_string())
+            .collect();
+        assert_eq!(
+            text,
+            [
+                "set 3 -> m1",
+                "set 2 -> m2",
+                "sub m1, 1 -> m1",
+                "fwd 2, m1",
+                "bak 2, 0",
+                "sub m2, 1 -> m2",
+                "fwd 2, m2",
+                "bak 2, 0",
+                "bye 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_point_at_the_body_and_the_call() {
+        let src = "\
+.macro outer x
+    inner \\x
+.endm
+.macro inner y
+    add \\y -> m1
+.endm
+nop
+outer 5
+";
+        assert_eq!(
+            assemble(lines(src)).unwrap_err(),
+            "test.sick:5: Error while parsing `add` instruction near `-> m1` \
+             (in `inner` from test.sick:2, in `outer` from test.sick:8)"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let err = |src: &str| expand_str(src).unwrap_err();
+        assert_eq!(
+            err(&format!("{}\nor m1, m2", OR)),
+            "test.sick:8: `or` takes 3 arguments, got 2 (it's defined at test.sick:1)"
+        );
+        assert_eq!(
+            err(".macro m a\n    out \\b\n.endm\nm 1"),
+            "test.sick:2: `\\b` isn't one of `m`'s parameters (in `m` from test.sick:4)"
+        );
+        assert_eq!(
+            err(".macro loop\n    loop\n.endm\nloop"),
+            "test.sick:4: Macros are nested more than 64 deep, does `loop` call itself?"
+        );
+        assert_eq!(
+            err(".macro m\nnop"),
+            "test.sick:1: `.macro` without an `.endm`"
+        );
+        assert_eq!(err("nop\n.endm"), "test.sick:2: `.endm` without a `.macro`");
+        assert_eq!(
+            err(".macro m a, a\n.endm"),
+            "test.sick:1: `a` is in there twice"
+        );
+        assert_eq!(
+            err(".macro m\n.endm\n.macro m\n.endm"),
+            "test.sick:3: `m` is already defined at test.sick:1"
+        );
+    }
+}
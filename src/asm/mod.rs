@@ -0,0 +1,254 @@
+use crate::bytecode::encode_instruction;
+use crate::link::Object;
+use crate::parser::{parse_instruction, Line};
+use crate::program::Program;
+use crate::source::{strip_comment, SourceLine};
+
+mod blocks;
+mod data;
+mod labels;
+mod macros;
+mod prn;
+
+/// Every instruction name, which nothing else gets to be called.
+const MNEMONICS: [&str; 14] = [
+    "set", "and", "xor", "not", "add", "sub", "out", "num", "cin", "nin", "bak", "fwd", "bye",
+    "nop",
+];
+
+/// A line on its way to being an instruction. `code` is what it says after
+/// whatever's been done to it so far, and `at` is where it started out.
+#[derive(Debug, Clone)]
+pub struct Item {
+    pub code: String,
+    pub at: SourceLine,
+}
+
+/// The first word, and the rest trimmed.
+fn split_word(code: &str) -> (&str, &str) {
+    match code.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (code, ""),
+    }
+}
+
+/// Reads a string in quotes off the front of `text` and gives back its
+/// bytes, and whatever's after it. Characters past 255 don't fit in a cell,
+/// anything else is written as its code point, same as `out` prints it.
+fn string(text: &str) -> Result<(Vec<u8>, &str), String> {
+    let body = text
+        .strip_prefix('"')
+        .ok_or_else(|| "Expected a string in quotes".to_owned())?;
+    let mut chars = body.char_indices();
+    let mut out = Vec::new();
+
+    while let Some((idx, c)) = chars.next() {
+        let c = match c {
+            '"' => return Ok((out, &body[idx + 1..])),
+            '\\' => match chars.next().map(|(_, c)| c) {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('r') => '\r',
+                Some('0') => '\0',
+                Some('\\') => '\\',
+                Some('"') => '"',
+                Some('x') => {
+                    let digits: String = chars.by_ref().take(2).map(|(_, c)| c).collect();
+                    match u8::from_str_radix(&digits, 16) {
+                        Ok(byte) if digits.len() == 2 => out.push(byte),
+                        _ => return Err("`\\x` needs two hex digits after it".to_owned()),
+                    }
+                    continue;
+                }
+                Some(other) => return Err(format!("Unknown escape `\\{}`", other)),
+                None => break,
+            },
+            c => c,
+        };
+        let byte = u8::try_from(c as u32).map_err(|_| format!("`{}` doesn't fit in a byte", c))?;
+        out.push(byte);
+    }
+
+    Err("The string never ends, it needs a `\"`".to_owned())
+}
+
+/// Something that could be the name of a label, macro or parameter. Things
+/// like `m12` are memory, so they don't count.
+fn is_name(word: &str) -> bool {
+    let mut chars = word.chars();
+    let first_ok = matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_');
+    let memory =
+        word.len() > 1 && word.starts_with('m') && word[1..].bytes().all(|b| b.is_ascii_digit());
+
+    first_ok && chars.all(|c| c.is_ascii_alphanumeric() || c == '_') && !memory
+}
+
+/// Labels from inside a macro get a `@n` on the end so each expansion has
+/// its own, and the ones made up for blocks are just `@n`.
+fn is_label(word: &str) -> bool {
+    match word.split_once('@') {
+        Some((name, n)) => {
+            (name.is_empty() || is_name(name))
+                && !n.is_empty()
+                && n.bytes().all(|b| b.is_ascii_digit())
+        }
+        None => is_name(word),
+    }
+}
+
+/// A literal or a memory address, like `5` or `m5`.
+fn is_value(word: &str) -> bool {
+    let num = word.strip_prefix('m').unwrap_or(word);
+    num.bytes().all(|b| b.is_ascii_digit()) && num.parse::<u8>().is_ok()
+}
+
+/// The name from a `name:` line.
+fn label(code: &str) -> Option<&str> {
+    code.strip_suffix(':')
+        .map(str::trim_end)
+        .filter(|name| is_label(name))
+}
+
+/// Whether a line is something for the assembler rather than the parser,
+/// going by looks alone. The formatter and linter use this to leave those
+/// lines be.
+pub fn is_asm(code: &str) -> bool {
+    let (word, rest) = split_word(code);
+    let count = rest.split(',').next().unwrap_or_default().trim();
+
+    code.starts_with('.')
+        || label(code).is_some()
+        || !MNEMONICS.contains(&word)
+        || (matches!(word, "bak" | "fwd") && is_label(count))
+}
+
+/// Turns the lines of a program into instructions, running the macros and
+/// working out the labels and data on the way. Where each instruction came
+/// from ends up in the program's source map.
+pub fn assemble(lines: Vec<SourceLine>) -> Result<Program, String> {
+    let object = assemble_object(lines)?;
+    match object.imports.first() {
+        Some((idx, name)) => Err(object.program.source_map[*idx].error(format!(
+            "`{}` is imported, so this needs to be assembled with `-c` and linked",
+            name
+        ))),
+        None => Ok(object.program),
+    }
+}
+
+/// `assemble`, but jumps to `.import`ed labels are left for the linker.
+pub fn assemble_object(lines: Vec<SourceLine>) -> Result<Object, String> {
+    let items = lines
+        .into_iter()
+        .filter_map(|at| {
+            let code = strip_comment(&at.text).to_owned();
+            (!code.is_empty()).then_some(Item { code, at })
+        })
+        .collect();
+
+    let items = macros::expand(items)?;
+    let (items, data) = data::extract(items)?;
+    let items = blocks::lower(items)?;
+    let items = prn::expand(items)?;
+    let (items, symbols) = labels::resolve(items)?;
+
+    let mut instructions = Vec::with_capacity(items.len());
+    let mut from = Vec::with_capacity(items.len());
+    for item in items {
+        instructions.push(parse_instruction(&item.code).map_err(|msg| item.at.error(msg))?);
+        from.push(item.at);
+    }
+
+    Ok(Object {
+        program: Program {
+            instructions,
+            data,
+            source_map: from,
+        },
+        exports: symbols.exports,
+        imports: symbols.imports,
+    })
+}
+
+/// The assembled program as lines for the formatter, to see what everything
+/// turned into. The first instruction from each line of source gets that line
+/// as a comment, unless it's just that instruction anyway.
+pub fn to_lines(program: &Program) -> Vec<Line> {
+    let mut lines: Vec<Line> = program
+        .data_directives()
+        .into_iter()
+        .map(|text| Line::Asm(text, None))
+        .collect();
+    if !lines.is_empty() {
+        lines.push(Line::Blank);
+    }
+
+    let mut last = None;
+    for (inst, at) in program.instructions.iter().zip(&program.source_map) {
+        let code = strip_comment(&at.text);
+        let same = parse_instruction(code).as_ref() == Ok(inst);
+        let comment = (last != Some(at) && !same).then(|| format!(" {}", code));
+        last = Some(at);
+        lines.push(Line::Instruction(*inst, comment));
+    }
+
+    lines
+}
+
+/// A table of every instruction's index, its bytes in the bytecode, and the
+/// line it came from, for when a jump doesn't land where it should. Like
+/// `to_lines`, lines that turn into a few instructions are only shown next to
+/// the first one.
+pub fn listing(program: &Program) -> String {
+    let rows: Vec<(String, String)> = program
+        .instructions
+        .iter()
+        .map(|inst| {
+            let bytes: Vec<String> = encode_instruction(inst)
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            (bytes.join(" "), inst.to_string())
+        })
+        .collect();
+
+    let idx_width = program
+        .instructions
+        .len()
+        .saturating_sub(1)
+        .to_string()
+        .len();
+    let bytes_width = rows.iter().map(|(bytes, _)| bytes.len()).max().unwrap_or(0);
+    let inst_width = rows.iter().map(|(_, inst)| inst.len()).max().unwrap_or(0);
+
+    let mut out = String::new();
+    let mut last = None;
+    for (idx, ((bytes, inst), at)) in rows.iter().zip(&program.source_map).enumerate() {
+        let source = match last != Some(at) {
+            true => format!(
+                "{}:{}  {}{}",
+                at.file,
+                at.line,
+                strip_comment(&at.text),
+                at.calls()
+            ),
+            false => String::new(),
+        };
+        last = Some(at);
+
+        let line = format!(
+            "{:>idx_width$}  {:<bytes_width$}  {:<inst_width$}  {}",
+            idx,
+            bytes,
+            inst,
+            source,
+            idx_width = idx_width,
+            bytes_width = bytes_width,
+            inst_width = inst_width
+        );
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+
+    out
+}
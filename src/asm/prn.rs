@@ -0,0 +1,254 @@
+use super::{label, split_word, string, Item};
+
+/// The bytes from a `prn` line, or `None` if it's something else.
+fn prn(item: &Item) -> Option<Result<Vec<u8>, String>> {
+    let (word, rest) = split_word(&item.code);
+    if word != "prn" {
+        return None;
+    }
+
+    Some(
+        string(rest).and_then(|(bytes, rest)| match (bytes.is_empty(), rest.trim()) {
+            (true, _) => Err("Nothing to print".to_owned()),
+            (false, "") => Ok(bytes),
+            (false, rest) => Err(format!("Unexpected characters: `{}`", rest)),
+        }),
+    )
+}
+
+/// Whether a line ends up as an instruction. Labels don't, and neither do
+/// `.export` and `.import`, which get taken out with the labels later.
+fn is_instruction(item: &Item) -> bool {
+    label(&item.code).is_none() && !matches!(split_word(&item.code).0, ".export" | ".import")
+}
+
+/// Turns every `prn "text"` into an `out` per byte.
+///
+/// A `prn` counts as one instruction to any `bak` or `fwd` with a literal
+/// count, the same as it looks in the source, so those get stretched to
+/// still land where they did. Counts from memory can't be fixed up, and
+/// labels get worked out afterwards anyway.
+pub fn expand(items: Vec<Item>) -> Result<Vec<Item>, String> {
+    let strings = items
+        .iter()
+        .map(|item| prn(item).transpose().map_err(|msg| item.at.error(msg)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Where each instruction in the source starts once it's expanded. Labels
+    // and such aren't instructions, so they're left out.
+    let mut starts = vec![0];
+    for (item, bytes) in items.iter().zip(&strings) {
+        if is_instruction(item) {
+            let size = bytes.as_ref().map_or(1, Vec::len);
+            starts.push(starts.last().expect("Starts with 0") + size);
+        }
+    }
+
+    let mut out = Vec::with_capacity(*starts.last().expect("Starts with 0"));
+    // Which instruction in the source we're on
+    let mut idx = 0usize;
+    for (mut item, bytes) in items.into_iter().zip(strings) {
+        if !is_instruction(&item) {
+            out.push(item);
+            continue;
+        }
+
+        if let Some(bytes) = bytes {
+            for byte in bytes {
+                out.push(Item {
+                    code: format!("out {}", byte),
+                    at: item.at.clone(),
+                });
+            }
+            idx += 1;
+            continue;
+        }
+
+        let (word, rest) = split_word(&item.code);
+        let (count, check) = rest.split_once(',').unwrap_or((rest, ""));
+        let count = count.trim().parse::<usize>().ok();
+        if let (Some(count), "bak" | "fwd") = (count, word) {
+            let target = match word {
+                "bak" => idx.checked_sub(count),
+                _ => Some(idx + count).filter(|target| *target < starts.len()),
+            };
+            // Jumps that wrap around are left alone
+            if let Some(target) = target {
+                let stretched = starts[idx].abs_diff(starts[target]);
+                if stretched > u8::MAX as usize {
+                    return Err(item.at.error(format!(
+                        "With the `prn`s expanded, this jump goes {} instructions, but it can only go {}",
+                        stretched,
+                        u8::MAX
+                    )));
+                }
+                if stretched != count {
+                    item.code = format!("{} {}, {}", word, stretched, check.trim());
+                }
+            }
+        }
+
+        out.push(item);
+        idx += 1;
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble_object;
+    use crate::io::BufferIo;
+    use crate::machine::{Machine, MachineConfig};
+    use crate::source::{read_str, strip_comment, SourceConfig};
+
+    fn expand_str(src: &str) -> Result<Vec<String>, String> {
+        let items = read_str("test.sick", src, &SourceConfig::default())
+            .unwrap()
+            .into_iter()
+            .filter_map(|at| {
+                let code = strip_comment(&at.text).to_owned();
+                (!code.is_empty()).then_some(Item { code, at })
+            })
+            .collect();
+        expand(items).map(|items| items.into_iter().map(|item| item.code).collect())
+    }
+
+    /// Just the bytes a `prn` turns into.
+    fn bytes(src: &str) -> Result<Vec<u8>, String> {
+        expand_str(src).map(|codes| {
+            codes
+                .iter()
+                .map(|code| code.strip_prefix("out ").unwrap().parse().unwrap())
+                .collect()
+        })
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(bytes(r#"prn "a\n\"\\b""#), Ok(b"a\n\"\\b".to_vec()));
+        assert_eq!(
+            bytes(r#"prn "\t\r\0\x41\xff""#),
+            Ok(b"\t\r\0A\xff".to_vec())
+        );
+        // A `#` in a string isn't a comment
+        assert_eq!(bytes(r##"prn "#1" # comment"##), Ok(b"#1".to_vec()));
+        // Past 127 goes as the code point, same as `out`
+        assert_eq!(bytes(r#"prn "é""#), Ok(vec![0xe9]));
+    }
+
+    #[test]
+    fn bad_strings() {
+        let err = |src: &str| bytes(src).unwrap_err();
+        assert_eq!(err(r#"prn "\q""#), "test.sick:1: Unknown escape `\\q`");
+        assert_eq!(
+            err(r#"prn "\x4""#),
+            "test.sick:1: `\\x` needs two hex digits after it"
+        );
+        assert_eq!(
+            err(r#"prn "\xzz""#),
+            "test.sick:1: `\\x` needs two hex digits after it"
+        );
+        assert_eq!(
+            err(r#"prn "abc"#),
+            "test.sick:1: The string never ends, it needs a `\"`"
+        );
+        assert_eq!(
+            err(r#"prn "abc\""#),
+            "test.sick:1: The string never ends, it needs a `\"`"
+        );
+        assert_eq!(err(r#"prn "€""#), "test.sick:1: `€` doesn't fit in a byte");
+        assert_eq!(err(r#"prn """#), "test.sick:1: Nothing to print");
+        assert_eq!(
+            err(r#"prn "a" b"#),
+            "test.sick:1: Unexpected characters: `b`"
+        );
+        assert_eq!(err("prn abc"), "test.sick:1: Expected a string in quotes");
+    }
+
+    #[test]
+    fn stretches_jumps() {
+        assert_eq!(
+            expand_str("fwd 2, m1\nprn \"abc\"\nout 1\nbak 3, m2"),
+            Ok(vec![
+                "fwd 4, m1".to_owned(),
+                "out 97".to_owned(),
+                "out 98".to_owned(),
+                "out 99".to_owned(),
+                "out 1".to_owned(),
+                "bak 5, m2".to_owned(),
+            ])
+        );
+        // Labels don't count, and jumps that don't go over a `prn` stay as
+        // they were, spacing and all
+        assert_eq!(
+            expand_str("prn \"ab\"\nfwd  1,m1\nhere:\nbak 2, 0"),
+            Ok(vec![
+                "out 97".to_owned(),
+                "out 98".to_owned(),
+                "fwd  1,m1".to_owned(),
+                "here:".to_owned(),
+                "bak 3, 0".to_owned(),
+            ])
+        );
+    }
+
+    #[test]
+    fn exports_and_imports_dont_count() {
+        let src = "fwd 3, 0\n.export here\n.import there\nhere:\nout 65\nprn \"xyz\"\nbye 7";
+        assert_eq!(
+            expand_str(src),
+            Ok(vec![
+                "fwd 5, 0".to_owned(),
+                ".export here".to_owned(),
+                ".import there".to_owned(),
+                "here:".to_owned(),
+                "out 65".to_owned(),
+                "out 120".to_owned(),
+                "out 121".to_owned(),
+                "out 122".to_owned(),
+                "bye 7".to_owned(),
+            ])
+        );
+
+        // The jump lands on the `bye`, past the whole string
+        let src = "fwd 3, 0\n.export here\nhere:\nout 65\nprn \"xyz\"\nbye 7";
+        let object = assemble_object(read_str("test.sick", src, &SourceConfig::default()).unwrap());
+        let program = object.unwrap().program;
+        let mut machine = Machine::with_io(MachineConfig::default(), BufferIo::new(b""));
+        assert_eq!(machine.run(&program.instructions), Ok(7));
+        assert_eq!(machine.io.output, b"");
+    }
+
+    #[test]
+    fn leaves_some_jumps_alone() {
+        // Wrapping round, past the end, by memory, and to labels
+        let src = "bak 1, 0\nprn \"ab\"\nfwd 9, 0\nfwd m1, 0\nfwd there, 0\nthere:";
+        assert_eq!(
+            expand_str(src),
+            Ok(vec![
+                "bak 1, 0".to_owned(),
+                "out 97".to_owned(),
+                "out 98".to_owned(),
+                "fwd 9, 0".to_owned(),
+                "fwd m1, 0".to_owned(),
+                "fwd there, 0".to_owned(),
+                "there:".to_owned(),
+            ])
+        );
+    }
+
+    #[test]
+    fn stretched_too_far() {
+        let long = "x".repeat(200);
+        let src = format!("fwd 3, 0\nprn \"{}\"\nprn \"{}\"\nbye 0", long, long);
+        assert_eq!(
+            expand_str(&src),
+            Err(
+                "test.sick:1: With the `prn`s expanded, this jump goes 401 instructions, but it can only go 255"
+                    .to_owned()
+            )
+        );
+    }
+}
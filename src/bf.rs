@@ -0,0 +1,458 @@
+use crate::io::BufferIo;
+use crate::machine::{Machine, MachineConfig, RunError, MEMORY_SIZE};
+use crate::parser::{Instruction, Line, Value};
+
+/// Where the tape starts. `m0` is the IP, so cell 0 of the tape is `m1`.
+const TAPE_START: usize = 1;
+
+/// How long a program gets in `check` before it's called stuck.
+const CHECK_STEPS: u64 = 10_000_000;
+
+/// What a program exits with when the pointer goes off the tape. Brainfuck
+/// has no way to exit with anything but 0, so it can't be mistaken for
+/// anything else.
+pub const OFF_THE_TAPE: u8 = 2;
+
+/// Where the current cell lives once the pointer is only known at runtime.
+const CUR: u8 = 254;
+/// Twice the pointer, so it can go straight into a table with 2 instructions
+/// per cell.
+const PTR: u8 = 253;
+/// How far back the table has to jump to get to where it was called from.
+const RET: u8 = 252;
+/// How far to move the pointer, doubled like `PTR`.
+const STEP: u8 = 251;
+/// How far the next `fwd` into a table goes.
+const JUMP: u8 = 250;
+const SCRATCH: u8 = 249;
+
+/// The bits of the tables that don't depend on the tape size: the `bye`s
+/// before them, moving the pointer and checking it, and the jump back.
+const TABLE_EXTRA: usize = 2 + 6 + 1;
+
+/// Most programs leave the pointer where it was at the end of every loop, so
+/// the pointer only has to exist while compiling and every cell is a plain
+/// address. The ones that don't, like anything scanning for a zero with
+/// `[>]`, get a runtime pointer instead. Sick has nothing like `mem[mem[p]]`,
+/// so then the current cell is kept in `CUR`, and moving means jumping into a
+/// table to put it back and another to get the new one, like `read_line` in
+/// `std:input.sick` does.
+struct Compiler {
+    out: Vec<(Instruction, String)>,
+    /// Where the pointer is right now, as a cell number. `None` when it's
+    /// only known at runtime.
+    ptr: Option<usize>,
+    /// Open loops: where the `fwd` is, where the pointer was, and where the
+    /// `[` is in the source.
+    loops: Vec<(usize, Option<usize>, (usize, usize))>,
+    /// Where every runtime move is, to point at the tables once they're there.
+    moves: Vec<usize>,
+    /// The most a single move goes, which has to be less than the tape.
+    longest_move: usize,
+}
+
+impl Compiler {
+    fn cell(&self) -> Value {
+        let addr = match self.ptr {
+            Some(ptr) => (TAPE_START + ptr) as u8,
+            None => CUR,
+        };
+        Value::Memory { addr }
+    }
+
+    fn push(&mut self, inst: Instruction, source: &str) {
+        self.out.push((inst, source.to_owned()));
+    }
+
+    /// Moves the pointer at runtime. The jumps get filled in by `tables`.
+    fn move_by(&mut self, by: isize, source: &str) {
+        self.moves.push(self.out.len());
+        self.longest_move = self.longest_move.max(by.unsigned_abs());
+        let step = (by * 2).rem_euclid(256) as u8;
+        self.push(set(Value::Literal { val: step }, STEP), source);
+        self.push(set(Value::Literal { val: 0 }, RET), "");
+        self.push(set(Value::Literal { val: 0 }, JUMP), "");
+        self.push(jump_by(JUMP), "");
+    }
+
+    /// Adds the tables after the program and points every move at them.
+    ///
+    /// ```text
+    ///         bye 0                            (the end of the program)
+    /// trap:   bye OFF_THE_TAPE
+    /// write:  set CUR -> cell, fwd to move     (for each cell)
+    /// move:   add PTR, STEP -> PTR
+    ///         and PTR, mask -> SCRATCH         (off the tape if that's not 0)
+    ///         fwd 2, SCRATCH
+    ///         bak to trap, 0
+    ///         add PTR, (to read) -> JUMP
+    ///         fwd JUMP, 0
+    /// read:   set cell -> CUR, fwd to back     (for each cell)
+    /// back:   bak RET, 0
+    /// ```
+    fn tables(&mut self, at: (usize, usize)) -> Result<(), String> {
+        // The biggest power of 2 that fits, so checking it is a single `and`
+        let room = 255usize.saturating_sub(self.out.len() + TABLE_EXTRA) / 4;
+        let cells = match room {
+            0 => {
+                return Err(format!(
+                    "{}:{}: The program is too long to fit a runtime tape pointer",
+                    at.0, at.1
+                ))
+            }
+            _ => 1 << room.min(64).ilog2(),
+        };
+        if self.longest_move >= cells {
+            return Err(format!(
+                "{}:{}: The pointer moves {} at once, but there's only room for {} cells",
+                at.0, at.1, self.longest_move, cells
+            ));
+        }
+
+        let trap = self.out.len() + 1;
+        let write = trap + 1;
+        let mover = write + 2 * cells;
+        let read = mover + 6;
+        let back = read + 2 * cells;
+
+        self.push(bye(), "");
+        self.push(
+            Instruction::Bye {
+                code: Value::Literal { val: OFF_THE_TAPE },
+            },
+            "",
+        );
+        for cell in 0..cells {
+            let here = write + 2 * cell;
+            let tape = (TAPE_START + cell) as u8;
+            self.push(set(Value::Memory { addr: CUR }, tape), "");
+            self.push(fwd(mover - (here + 1)), "");
+        }
+
+        let ptr = Value::Memory { addr: PTR };
+        self.push(
+            Instruction::Add {
+                left: ptr,
+                right: Value::Memory { addr: STEP },
+                tgt: ptr,
+            },
+            "",
+        );
+        self.push(
+            Instruction::And {
+                left: ptr,
+                right: Value::Literal {
+                    val: (256 - 2 * cells) as u8,
+                },
+                tgt: Value::Memory { addr: SCRATCH },
+            },
+            "",
+        );
+        self.push(
+            Instruction::Fwd {
+                count: Value::Literal { val: 2 },
+                check: Value::Memory { addr: SCRATCH },
+            },
+            "",
+        );
+        self.push(
+            Instruction::Bak {
+                count: Value::Literal {
+                    val: (mover + 3 - trap) as u8,
+                },
+                check: Value::Literal { val: 0 },
+            },
+            "",
+        );
+        self.push(
+            Instruction::Add {
+                left: ptr,
+                right: Value::Literal {
+                    val: (read - (mover + 5)) as u8,
+                },
+                tgt: Value::Memory { addr: JUMP },
+            },
+            "",
+        );
+        self.push(jump_by(JUMP), "");
+
+        for cell in 0..cells {
+            let here = read + 2 * cell;
+            let tape = (TAPE_START + cell) as u8;
+            self.push(set(Value::Memory { addr: tape }, CUR), "");
+            self.push(fwd(back - (here + 1)), "");
+        }
+        self.push(
+            Instruction::Bak {
+                count: Value::Memory { addr: RET },
+                check: Value::Literal { val: 0 },
+            },
+            "",
+        );
+
+        for start in std::mem::take(&mut self.moves) {
+            let call = start + 3;
+            self.out[start + 1].0 = set(
+                Value::Literal {
+                    val: (back - (call + 1)) as u8,
+                },
+                RET,
+            );
+            self.out[start + 2].0 = Instruction::Add {
+                left: ptr,
+                right: Value::Literal {
+                    val: (write - call) as u8,
+                },
+                tgt: Value::Memory { addr: JUMP },
+            };
+        }
+        Ok(())
+    }
+}
+
+fn set(src: Value, tgt: u8) -> Instruction {
+    Instruction::Set {
+        src,
+        tgt: Value::Memory { addr: tgt },
+    }
+}
+
+fn fwd(count: usize) -> Instruction {
+    Instruction::Fwd {
+        count: Value::Literal { val: count as u8 },
+        check: Value::Literal { val: 0 },
+    }
+}
+
+fn jump_by(addr: u8) -> Instruction {
+    Instruction::Fwd {
+        count: Value::Memory { addr },
+        check: Value::Literal { val: 0 },
+    }
+}
+
+fn bye() -> Instruction {
+    Instruction::Bye {
+        code: Value::Literal { val: 0 },
+    }
+}
+
+/// Whether any loop ends with the pointer somewhere else than it started.
+fn moves_in_loops(code: &[(usize, char)]) -> bool {
+    let mut ptr = 0isize;
+    let mut loops = Vec::new();
+    for (_, c) in code {
+        match c {
+            '>' => ptr += 1,
+            '<' => ptr -= 1,
+            '[' => loops.push(ptr),
+            ']' if loops.pop().is_some_and(|start| start != ptr) => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Where a byte offset is in the source, as a line and column from 1.
+fn position(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let col = before.len() - before.rfind('\n').map_or(0, |idx| idx + 1) + 1;
+    (line, col)
+}
+
+/// Compiles Brainfuck into sick, with the bit of Brainfuck each instruction
+/// came from. Runs of `+`, `-`, `>` and `<` get squashed together, and `[-]`
+/// becomes a plain `set`.
+pub fn compile(source: &str) -> Result<Vec<(Instruction, String)>, String> {
+    let tape = MEMORY_SIZE - TAPE_START;
+    let code: Vec<(usize, char)> = source
+        .char_indices()
+        .filter(|(_, c)| "+-<>[].,".contains(*c))
+        .collect();
+
+    let mut compiler = Compiler {
+        out: Vec::new(),
+        ptr: (!moves_in_loops(&code)).then_some(0),
+        loops: Vec::new(),
+        moves: Vec::new(),
+        longest_move: 0,
+    };
+
+    let mut idx = 0;
+    while idx < code.len() {
+        let (offset, c) = code[idx];
+        let at = position(source, offset);
+
+        // How many of the same thing in a row
+        let run = code[idx..]
+            .iter()
+            .take_while(|(_, other)| *other == c)
+            .count();
+
+        match c {
+            '+' | '-' => {
+                let cell = compiler.cell();
+                let val = Value::Literal {
+                    val: (run % 256) as u8,
+                };
+                let inst = match c {
+                    '+' => Instruction::Add {
+                        left: cell,
+                        right: val,
+                        tgt: cell,
+                    },
+                    _ => Instruction::Sub {
+                        left: cell,
+                        right: val,
+                        tgt: cell,
+                    },
+                };
+                compiler.push(inst, &c.to_string().repeat(run));
+                idx += run;
+            }
+            '>' | '<' if compiler.ptr.is_none() => {
+                let by = if c == '>' {
+                    run as isize
+                } else {
+                    -(run as isize)
+                };
+                compiler.move_by(by, &c.to_string().repeat(run));
+                idx += run;
+            }
+            '>' => {
+                let ptr = compiler.ptr.expect("Checked above") + run;
+                if ptr >= tape {
+                    return Err(format!(
+                        "{}:{}: The tape pointer goes past cell {}, the last one sick has room for",
+                        at.0,
+                        at.1,
+                        tape - 1
+                    ));
+                }
+                compiler.ptr = Some(ptr);
+                idx += run;
+            }
+            '<' => {
+                let ptr = compiler.ptr.expect("Checked above").checked_sub(run);
+                compiler.ptr = Some(ptr.ok_or_else(|| {
+                    format!("{}:{}: The tape pointer goes left of cell 0", at.0, at.1)
+                })?);
+                idx += run;
+            }
+            '.' => {
+                let src = compiler.cell();
+                compiler.push(Instruction::Out { src }, ".");
+                idx += 1;
+            }
+            ',' => {
+                let tgt = compiler.cell();
+                compiler.push(Instruction::Cin { tgt }, ",");
+                idx += 1;
+            }
+            '[' => {
+                // Clearing a cell is common enough to be worth spotting
+                let rest: String = code[idx..].iter().take(3).map(|(_, c)| c).collect();
+                if rest == "[-]" || rest == "[+]" {
+                    let tgt = compiler.cell();
+                    let inst = Instruction::Set {
+                        src: Value::Literal { val: 0 },
+                        tgt,
+                    };
+                    compiler.push(inst, &rest);
+                    idx += 3;
+                    continue;
+                }
+
+                // The count gets filled in at the `]`
+                compiler.loops.push((compiler.out.len(), compiler.ptr, at));
+                let check = compiler.cell();
+                let inst = Instruction::Fwd {
+                    count: Value::Literal { val: 0 },
+                    check,
+                };
+                compiler.push(inst, "[");
+                idx += 1;
+            }
+            ']' => {
+                let (start, ptr, _) = compiler
+                    .loops
+                    .pop()
+                    .ok_or_else(|| format!("{}:{}: `]` without a `[`", at.0, at.1))?;
+                // Otherwise it would've been a runtime pointer
+                debug_assert_eq!(ptr, compiler.ptr);
+
+                // Back to the `[` to check again, which skips past here once
+                // the cell is 0
+                let here = compiler.out.len();
+                compiler.push(
+                    Instruction::Bak {
+                        count: Value::Literal {
+                            val: (here - start) as u8,
+                        },
+                        check: Value::Literal { val: 0 },
+                    },
+                    "]",
+                );
+                compiler.out[start].0 = Instruction::Fwd {
+                    count: Value::Literal {
+                        val: (here + 1 - start) as u8,
+                    },
+                    check: compiler.cell(),
+                };
+                idx += 1;
+            }
+            _ => unreachable!(),
+        }
+
+        // The IP is a byte, so that's as far as a program can go. Checking as
+        // we go keeps every count above from wrapping.
+        if compiler.out.len() >= 256 {
+            return Err(format!(
+                "{}:{}: The program is too long, sick can only run 256 instructions",
+                at.0, at.1
+            ));
+        }
+    }
+
+    if let Some((_, _, open)) = compiler.loops.last() {
+        return Err(format!("{}:{}: `[` without a `]`", open.0, open.1));
+    }
+
+    match compiler.ptr {
+        Some(_) => compiler.push(bye(), ""),
+        None => compiler.tables(position(source, source.len()))?,
+    }
+    Ok(compiler.out)
+}
+
+/// The compiled program as lines for the formatter, each with the Brainfuck
+/// it came from as a comment.
+pub fn to_lines(compiled: &[(Instruction, String)]) -> Vec<Line> {
+    compiled
+        .iter()
+        .map(|(inst, source)| {
+            let comment = (!source.is_empty()).then(|| format!(" {}", source));
+            Line::Instruction(*inst, comment)
+        })
+        .collect()
+}
+
+/// Compiles the program and runs it on `input`, giving back what it printed.
+/// Output goes through `out`, so bytes past 127 come out as UTF-8.
+pub fn run(source: &str, input: &[u8]) -> Result<Vec<u8>, String> {
+    let instructions: Vec<Instruction> =
+        compile(source)?.into_iter().map(|(inst, _)| inst).collect();
+
+    let config = MachineConfig {
+        max_steps: Some(CHECK_STEPS),
+        ..Default::default()
+    };
+    let mut machine = Machine::with_io(config, BufferIo::new(input));
+    match machine.run(&instructions) {
+        Ok(OFF_THE_TAPE) => Err("The pointer went off the end of the tape".to_owned()),
+        Ok(_) => Ok(machine.io.output),
+        Err(RunError::Fault(msg)) => Err(msg),
+        Err(err) => Err(err.to_string()),
+    }
+}
@@ -0,0 +1,409 @@
+use crate::link;
+use crate::machine::MEMORY_SIZE;
+use crate::parser::{get_program, Instruction, Value};
+use crate::program::{Data, Program};
+use crate::source::SourceConfig;
+use std::fs;
+
+/*
+* Layout of a .sbc file: (all multi-byte numbers are little endian)
+* magic     4 bytes  0x7f 'S' 'B' 'C'
+* version   1 byte   VERSION
+* count     4 bytes  number of instructions
+* body      count instructions, each an opcode byte followed by its operands
+* cells     1 byte   number of memory cells set by `.data` (not in version 1)
+* data      cells pairs of address and value
+* checksum  4 bytes  CRC-32 of everything before it
+*
+* Every operand is two bytes, a tag (0 for memory, 1 for literal) and then
+* the address or value.
+*/
+
+pub const MAGIC: &[u8; 4] = b"\x7fSBC";
+pub const VERSION: u8 = 2;
+
+const TAG_MEMORY: u8 = 0;
+const TAG_LITERAL: u8 = 1;
+
+/// The opcode byte for each instruction, in the same order as the docs.
+fn opcode(inst: &Instruction) -> u8 {
+    match inst {
+        Instruction::Set { .. } => 0x00,
+        Instruction::And { .. } => 0x01,
+        Instruction::Xor { .. } => 0x02,
+        Instruction::Not { .. } => 0x03,
+        Instruction::Add { .. } => 0x04,
+        Instruction::Sub { .. } => 0x05,
+        Instruction::Out { .. } => 0x06,
+        Instruction::Num { .. } => 0x07,
+        Instruction::Cin { .. } => 0x08,
+        Instruction::Nin { .. } => 0x09,
+        Instruction::Bak { .. } => 0x0a,
+        Instruction::Fwd { .. } => 0x0b,
+        Instruction::Bye { .. } => 0x0c,
+        Instruction::Nop => 0x0d,
+    }
+}
+
+/// Plain bit-by-bit CRC-32, it's not like these files are big.
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn encode_value(out: &mut Vec<u8>, val: &Value) {
+    match val {
+        Value::Memory { addr } => out.extend([TAG_MEMORY, *addr]),
+        Value::Literal { val } => out.extend([TAG_LITERAL, *val]),
+    }
+}
+
+/// The opcode and operands for one instruction, as it goes in the body.
+pub fn encode_instruction(inst: &Instruction) -> Vec<u8> {
+    let mut out = vec![opcode(inst)];
+    match inst {
+        Instruction::Set { src, tgt } | Instruction::Not { src, tgt } => {
+            encode_value(&mut out, src);
+            encode_value(&mut out, tgt);
+        }
+        Instruction::And { left, right, tgt }
+        | Instruction::Xor { left, right, tgt }
+        | Instruction::Add { left, right, tgt }
+        | Instruction::Sub { left, right, tgt } => {
+            encode_value(&mut out, left);
+            encode_value(&mut out, right);
+            encode_value(&mut out, tgt);
+        }
+        Instruction::Out { src } | Instruction::Num { src } => encode_value(&mut out, src),
+        Instruction::Cin { tgt } | Instruction::Nin { tgt } => encode_value(&mut out, tgt),
+        Instruction::Bak { count, check } | Instruction::Fwd { count, check } => {
+            encode_value(&mut out, count);
+            encode_value(&mut out, check);
+        }
+        Instruction::Bye { code } => encode_value(&mut out, code),
+        Instruction::Nop => {}
+    }
+    out
+}
+
+/// The count of cells, then an address and value for each.
+pub(crate) fn encode_data(out: &mut Vec<u8>, data: &Data) {
+    // There's never more than a byte's worth, m0 can't be set
+    out.push(data.len() as u8);
+    for (addr, val) in data {
+        out.extend([*addr, *val]);
+    }
+}
+
+pub fn encode(program: &Program) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend(MAGIC);
+    out.push(VERSION);
+    out.extend((program.instructions.len() as u32).to_le_bytes());
+
+    for inst in &program.instructions {
+        out.extend(encode_instruction(inst));
+    }
+
+    encode_data(&mut out, &program.data);
+
+    let checksum = crc32(&out);
+    out.extend(checksum.to_le_bytes());
+    out
+}
+
+/// Walks through the body one byte at a time.
+pub(crate) struct Reader<'a> {
+    pub(crate) bytes: &'a [u8],
+    pub(crate) pos: usize,
+}
+
+impl Reader<'_> {
+    pub(crate) fn byte(&mut self) -> Result<u8, String> {
+        let byte = *self
+            .bytes
+            .get(self.pos)
+            .ok_or("Bytecode ends in the middle of an instruction")?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        match (self.byte()?, self.byte()?) {
+            (TAG_MEMORY, addr) => Ok(Value::Memory { addr }),
+            (TAG_LITERAL, val) => Ok(Value::Literal { val }),
+            (tag, _) => Err(format!(
+                "Unknown operand tag {:#04x} at byte {}",
+                tag,
+                self.pos - 2
+            )),
+        }
+    }
+
+    /// Like `value`, but it has to be a memory address.
+    fn target(&mut self) -> Result<Value, String> {
+        match self.value()? {
+            Value::Literal { .. } => Err(format!(
+                "Target at byte {} should be a memory address",
+                self.pos - 2
+            )),
+            tgt => Ok(tgt),
+        }
+    }
+
+    pub(crate) fn instruction(&mut self) -> Result<Instruction, String> {
+        let inst = match self.byte()? {
+            0x00 => Instruction::Set {
+                src: self.value()?,
+                tgt: self.target()?,
+            },
+            0x01 => Instruction::And {
+                left: self.value()?,
+                right: self.value()?,
+                tgt: self.target()?,
+            },
+            0x02 => Instruction::Xor {
+                left: self.value()?,
+                right: self.value()?,
+                tgt: self.target()?,
+            },
+            0x03 => Instruction::Not {
+                src: self.value()?,
+                tgt: self.target()?,
+            },
+            0x04 => Instruction::Add {
+                left: self.value()?,
+                right: self.value()?,
+                tgt: self.target()?,
+            },
+            0x05 => Instruction::Sub {
+                left: self.value()?,
+                right: self.value()?,
+                tgt: self.target()?,
+            },
+            0x06 => Instruction::Out { src: self.value()? },
+            0x07 => Instruction::Num { src: self.value()? },
+            0x08 => Instruction::Cin {
+                tgt: self.target()?,
+            },
+            0x09 => Instruction::Nin {
+                tgt: self.target()?,
+            },
+            0x0a => Instruction::Bak {
+                count: self.value()?,
+                check: self.value()?,
+            },
+            0x0b => Instruction::Fwd {
+                count: self.value()?,
+                check: self.value()?,
+            },
+            0x0c => Instruction::Bye {
+                code: self.value()?,
+            },
+            0x0d => Instruction::Nop,
+            op => {
+                return Err(format!(
+                    "Unknown opcode {:#04x} at byte {}",
+                    op,
+                    self.pos - 1
+                ))
+            }
+        };
+        Ok(inst)
+    }
+
+    /// What `encode_data` wrote.
+    pub(crate) fn data(&mut self) -> Result<Data, String> {
+        let cells = self.byte().map_err(|_| "Bytecode ends before the data")?;
+        let mut data = Vec::new();
+        for _ in 0..cells {
+            let cell = (self.byte(), self.byte());
+            match cell {
+                (Ok(0), Ok(_)) => return Err("The data sets m0".to_owned()),
+                (Ok(addr), Ok(val)) if (addr as usize) < MEMORY_SIZE => data.push((addr, val)),
+                (Ok(addr), Ok(_)) => {
+                    return Err(format!("The data sets m{}, which doesn't exist", addr))
+                }
+                _ => return Err("Bytecode ends in the middle of the data".to_owned()),
+            }
+        }
+        Ok(data)
+    }
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Program, String> {
+    // Magic, version, count and checksum
+    if bytes.len() < 13 || !bytes.starts_with(MAGIC) {
+        return Err("Not a sick bytecode file".to_owned());
+    }
+    // Version 1 is the same, just without data
+    let version = bytes[4];
+    if version != 1 && version != VERSION {
+        return Err(format!(
+            "Unsupported bytecode version {}, expected 1 to {}",
+            version, VERSION
+        ));
+    }
+
+    let (body, checksum) = bytes.split_at(bytes.len() - 4);
+    let checksum = u32::from_le_bytes(checksum.try_into().expect("Split off 4 bytes"));
+    if crc32(body) != checksum {
+        return Err("Checksum mismatch, the bytecode file is corrupted".to_owned());
+    }
+
+    let count = u32::from_le_bytes(body[5..9].try_into().expect("Checked the length"));
+    let mut reader = Reader {
+        bytes: body,
+        pos: 9,
+    };
+
+    let mut instructions = Vec::new();
+    for _ in 0..count {
+        instructions.push(reader.instruction()?);
+    }
+
+    let data = match version {
+        1 => Vec::new(),
+        _ => reader.data()?,
+    };
+    if reader.pos != body.len() {
+        return Err(format!(
+            "{} leftover bytes after the last instruction",
+            body.len() - reader.pos
+        ));
+    }
+
+    Ok(Program {
+        instructions,
+        data,
+        ..Default::default()
+    })
+}
+
+/// Loads a program from either bytecode or source, going by the magic bytes.
+pub fn load(path: &str) -> Result<Program, String> {
+    load_with(path, &SourceConfig::default())
+}
+
+/// `load`, but with somewhere to look for includes.
+pub fn load_with(path: &str, config: &SourceConfig) -> Result<Program, String> {
+    let bytes = fs::read(path).map_err(|_| "Could not read file".to_owned())?;
+
+    if bytes.starts_with(MAGIC) {
+        decode(&bytes)
+    } else if bytes.starts_with(link::MAGIC) {
+        Err(format!(
+            "{} is an object, link it first with `sick-as link`",
+            path
+        ))
+    } else {
+        get_program(path, config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_instruction;
+
+    fn program() -> Program {
+        Program {
+            instructions: ["set 7 -> m1", "add m1, 2 -> m2", "bak 1, m3", "bye 0"]
+                .into_iter()
+                .map(|line| parse_instruction(line).unwrap())
+                .collect(),
+            data: vec![(10, 1), (11, 2)],
+            ..Default::default()
+        }
+    }
+
+    /// Swaps the checksum for a right one, so what's wrong gets past it.
+    fn reseal(mut bytes: Vec<u8>) -> Vec<u8> {
+        bytes.truncate(bytes.len() - 4);
+        let checksum = crc32(&bytes);
+        bytes.extend(checksum.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        assert_eq!(decode(&encode(&program())), Ok(program()));
+        assert_eq!(decode(&encode(&Program::default())), Ok(Program::default()));
+    }
+
+    #[test]
+    fn flipped_checksum_byte() {
+        let mut bytes = encode(&program());
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        assert_eq!(
+            decode(&bytes),
+            Err("Checksum mismatch, the bytecode file is corrupted".to_owned())
+        );
+    }
+
+    #[test]
+    fn version_1() {
+        // Same thing with the data cut out
+        let mut bytes = encode(&program());
+        bytes[4] = 1;
+        let cells = bytes.len() - 4 - 5;
+        bytes.drain(cells..cells + 5);
+
+        let mut want = program();
+        want.data.clear();
+        assert_eq!(decode(&reseal(bytes)), Ok(want));
+    }
+
+    #[test]
+    fn unknown_version() {
+        let mut bytes = encode(&program());
+        bytes[4] = 3;
+        assert_eq!(
+            decode(&reseal(bytes)),
+            Err("Unsupported bytecode version 3, expected 1 to 2".to_owned())
+        );
+    }
+
+    #[test]
+    fn truncated() {
+        let bytes = encode(&program());
+        // Where the data starts, after the header, `set`, `add`, `bak` and `bye`
+        let cells = 9 + 5 + 7 + 5 + 3;
+
+        // Too short to even have a header and checksum
+        for len in 0..13 {
+            assert_eq!(
+                decode(&bytes[..len]),
+                Err("Not a sick bytecode file".to_owned())
+            );
+        }
+        // Cut off without fixing the checksum
+        assert_eq!(
+            decode(&bytes[..bytes.len() - 1]),
+            Err("Checksum mismatch, the bytecode file is corrupted".to_owned())
+        );
+
+        // Cut everywhere after the count, with a checksum that fits
+        for len in 9..cells + 5 {
+            let mut cut = bytes[..len].to_vec();
+            cut.extend([0; 4]);
+            let want = match len {
+                _ if len < cells => "Bytecode ends in the middle of an instruction",
+                _ if len == cells => "Bytecode ends before the data",
+                _ => "Bytecode ends in the middle of the data",
+            };
+            assert_eq!(decode(&reseal(cut)), Err(want.to_owned()), "{} bytes", len);
+        }
+    }
+}
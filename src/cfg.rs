@@ -0,0 +1,404 @@
+use crate::parser::{Instruction, Value};
+use std::fmt::Write;
+
+/// A run of instructions that always execute together, `start..end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Block {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Where an edge goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// Index into `Cfg::blocks`.
+    Block(usize),
+    /// Lands on an instruction that doesn't exist.
+    OutOfBounds(usize),
+    /// `bye`.
+    Exit,
+    /// A jump by a memory value, or a write to `m0`. Could be anywhere.
+    Unknown,
+}
+
+/// When an edge gets taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    Always,
+    /// The cell is 0.
+    Zero(u8),
+    /// The cell isn't 0.
+    NonZero(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    pub from: usize,
+    pub to: Target,
+    pub when: Condition,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Cfg {
+    pub blocks: Vec<Block>,
+    pub edges: Vec<Edge>,
+}
+
+/// Does this instruction end a block?
+fn ends_block(inst: &Instruction) -> bool {
+    matches!(
+        inst,
+        Instruction::Bak { .. } | Instruction::Fwd { .. } | Instruction::Bye { .. }
+    ) || inst.write() == Some(0)
+}
+
+impl Cfg {
+    pub fn build(instructions: &[Instruction]) -> Self {
+        if instructions.is_empty() {
+            return Cfg::default();
+        }
+
+        // Anything jumped to, or right after something that jumps, starts a block
+        let mut leaders = vec![false; instructions.len()];
+        leaders[0] = true;
+        // The IP wraps after 255 instead of going on to 256, so nothing falls
+        // through into it
+        if let Some(leader) = leaders.get_mut(256) {
+            *leader = true;
+        }
+        for (idx, inst) in instructions.iter().enumerate() {
+            if let Some(target) = inst.jump_target(idx) {
+                if target < instructions.len() {
+                    leaders[target] = true;
+                }
+            }
+            if ends_block(inst) && idx + 1 < instructions.len() {
+                leaders[idx + 1] = true;
+            }
+        }
+
+        let starts: Vec<usize> = (0..instructions.len()).filter(|i| leaders[*i]).collect();
+        let blocks: Vec<Block> = starts
+            .iter()
+            .enumerate()
+            .map(|(n, start)| Block {
+                start: *start,
+                end: starts.get(n + 1).copied().unwrap_or(instructions.len()),
+            })
+            .collect();
+
+        // Falling off 255 goes back to 0
+        let target = |idx: usize| match starts.binary_search(&(idx % 256)) {
+            Ok(block) => Target::Block(block),
+            Err(_) => Target::OutOfBounds(idx),
+        };
+
+        let mut edges = Vec::new();
+        for (from, block) in blocks.iter().enumerate() {
+            let last = block.end - 1;
+            let mut edge = |to, when| edges.push(Edge { from, to, when });
+
+            match &instructions[last] {
+                Instruction::Bye { .. } => edge(Target::Exit, Condition::Always),
+                inst @ (Instruction::Bak { check, .. } | Instruction::Fwd { check, .. }) => {
+                    let jump = match inst.jump_target(last) {
+                        Some(idx) => target(idx),
+                        None => Target::Unknown,
+                    };
+                    match check {
+                        Value::Literal { val: 0 } => edge(jump, Condition::Always),
+                        Value::Literal { .. } => edge(target(last + 1), Condition::Always),
+                        Value::Memory { addr } => {
+                            edge(jump, Condition::Zero(*addr));
+                            edge(target(last + 1), Condition::NonZero(*addr));
+                        }
+                    }
+                }
+                inst if inst.write() == Some(0) => edge(Target::Unknown, Condition::Always),
+                _ => edge(target(block.end), Condition::Always),
+            }
+        }
+
+        Cfg { blocks, edges }
+    }
+
+    fn block_lines(&self, instructions: &[Instruction], block: &Block) -> Vec<String> {
+        (block.start..block.end)
+            .map(|idx| format!("{}: {}", idx, instructions[idx]))
+            .collect()
+    }
+
+    /// Name of the node an edge points at, and what it should say on it.
+    fn node(to: &Target) -> (String, String) {
+        match to {
+            Target::Block(n) => (format!("b{}", n), String::new()),
+            Target::OutOfBounds(idx) => (format!("oob{}", idx), format!("past the end ({})", idx)),
+            Target::Exit => ("exit".to_owned(), "bye".to_owned()),
+            Target::Unknown => ("unknown".to_owned(), "?".to_owned()),
+        }
+    }
+
+    fn label(when: &Condition) -> Option<String> {
+        match when {
+            Condition::Always => None,
+            Condition::Zero(addr) => Some(format!("m{} == 0", addr)),
+            Condition::NonZero(addr) => Some(format!("m{} != 0", addr)),
+        }
+    }
+
+    /// Nodes that aren't blocks, each only once.
+    fn extra_nodes(&self) -> Vec<(String, String)> {
+        let mut nodes: Vec<(String, String)> = self
+            .edges
+            .iter()
+            .filter(|edge| !matches!(edge.to, Target::Block(_)))
+            .map(|edge| Cfg::node(&edge.to))
+            .collect();
+        nodes.sort();
+        nodes.dedup();
+        nodes
+    }
+
+    pub fn to_dot(&self, instructions: &[Instruction]) -> String {
+        let mut out = String::new();
+        out.push_str("digraph sick {\n");
+        out.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+
+        for (n, block) in self.blocks.iter().enumerate() {
+            let mut label = String::new();
+            for line in self.block_lines(instructions, block) {
+                // \l left-aligns the line
+                label.push_str(&line.replace('\\', "\\\\").replace('"', "\\\""));
+                label.push_str("\\l");
+            }
+            writeln!(out, "    b{} [label=\"{}\"];", n, label).expect("Writing to a string");
+        }
+        for (name, label) in self.extra_nodes() {
+            writeln!(out, "    {} [label=\"{}\", shape=ellipse];", name, label)
+                .expect("Writing to a string");
+        }
+
+        for edge in &self.edges {
+            let (to, _) = Cfg::node(&edge.to);
+            let mut attrs = Vec::new();
+            if let Some(label) = Cfg::label(&edge.when) {
+                attrs.push(format!("label=\"{}\"", label));
+            }
+            if edge.to == Target::Unknown {
+                attrs.push("style=dashed".to_owned());
+            }
+
+            if attrs.is_empty() {
+                writeln!(out, "    b{} -> {};", edge.from, to)
+            } else {
+                writeln!(out, "    b{} -> {} [{}];", edge.from, to, attrs.join(", "))
+            }
+            .expect("Writing to a string");
+        }
+
+        out.push_str("}\n");
+        out
+    }
+
+    pub fn to_mermaid(&self, instructions: &[Instruction]) -> String {
+        let mut out = String::new();
+        out.push_str("flowchart TD\n");
+
+        for (n, block) in self.blocks.iter().enumerate() {
+            let label = self
+                .block_lines(instructions, block)
+                .join("<br/>")
+                .replace('"', "#quot;");
+            writeln!(out, "    b{}[\"{}\"]", n, label).expect("Writing to a string");
+        }
+        for (name, label) in self.extra_nodes() {
+            writeln!(out, "    {}([\"{}\"])", name, label).expect("Writing to a string");
+        }
+
+        for edge in &self.edges {
+            let (to, _) = Cfg::node(&edge.to);
+            let arrow = if edge.to == Target::Unknown {
+                "-.->"
+            } else {
+                "-->"
+            };
+
+            match Cfg::label(&edge.when) {
+                Some(label) => writeln!(out, "    b{} {}|{}| {}", edge.from, arrow, label, to),
+                None => writeln!(out, "    b{} {} {}", edge.from, arrow, to),
+            }
+            .expect("Writing to a string");
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_instruction;
+
+    fn parse(lines: &[&str]) -> Vec<Instruction> {
+        lines
+            .iter()
+            .map(|line| parse_instruction(line).unwrap())
+            .collect()
+    }
+
+    fn edges(cfg: &Cfg) -> Vec<(usize, Target, Condition)> {
+        cfg.edges
+            .iter()
+            .map(|edge| (edge.from, edge.to, edge.when))
+            .collect()
+    }
+
+    #[test]
+    fn splits_on_literal_jumps() {
+        let program = parse(&[
+            "set 3 -> m1",
+            "sub m1, 1 -> m1",
+            "fwd 2, m1",
+            "bak 2, 0",
+            "out 65",
+            "bye 0",
+        ]);
+        let cfg = Cfg::build(&program);
+
+        let blocks: Vec<(usize, usize)> = cfg.blocks.iter().map(|b| (b.start, b.end)).collect();
+        assert_eq!(blocks, [(0, 1), (1, 3), (3, 4), (4, 6)]);
+        assert_eq!(
+            edges(&cfg),
+            [
+                (0, Target::Block(1), Condition::Always),
+                (1, Target::Block(3), Condition::Zero(1)),
+                (1, Target::Block(2), Condition::NonZero(1)),
+                (2, Target::Block(1), Condition::Always),
+                (3, Target::Exit, Condition::Always),
+            ]
+        );
+    }
+
+    #[test]
+    fn literal_checks() {
+        // A check that's never 0 never jumps, and one that's always 0 always does
+        let cfg = Cfg::build(&parse(&["fwd 2, 7", "fwd 2, 0", "bye 1", "bye 2"]));
+        assert_eq!(
+            edges(&cfg),
+            [
+                (0, Target::Block(1), Condition::Always),
+                (1, Target::Block(3), Condition::Always),
+                (2, Target::Exit, Condition::Always),
+                (3, Target::Exit, Condition::Always),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_edges() {
+        let cfg = Cfg::build(&parse(&[
+            "fwd m1, m2",
+            "add m1, 1 -> m0",
+            "out 65",
+            "bak m3, 0",
+        ]));
+        let blocks: Vec<(usize, usize)> = cfg.blocks.iter().map(|b| (b.start, b.end)).collect();
+        assert_eq!(blocks, [(0, 1), (1, 2), (2, 4)]);
+        assert_eq!(
+            edges(&cfg),
+            [
+                (0, Target::Unknown, Condition::Zero(2)),
+                (0, Target::Block(1), Condition::NonZero(2)),
+                (1, Target::Unknown, Condition::Always),
+                (2, Target::Unknown, Condition::Always),
+            ]
+        );
+    }
+
+    #[test]
+    fn past_the_end() {
+        let cfg = Cfg::build(&parse(&["fwd 5, m1", "nop"]));
+        assert_eq!(
+            edges(&cfg),
+            [
+                (0, Target::OutOfBounds(5), Condition::Zero(1)),
+                (0, Target::Block(1), Condition::NonZero(1)),
+                (1, Target::OutOfBounds(2), Condition::Always),
+            ]
+        );
+        assert!(Cfg::build(&[]).blocks.is_empty());
+    }
+
+    #[test]
+    fn ip_wraps_after_255() {
+        let mut program = parse(&["out 65"]);
+        program.resize(256, Instruction::Nop);
+        program.push(parse_instruction("bye 0").unwrap());
+        let cfg = Cfg::build(&program);
+
+        let blocks: Vec<(usize, usize)> = cfg.blocks.iter().map(|b| (b.start, b.end)).collect();
+        assert_eq!(blocks, [(0, 256), (256, 257)]);
+        assert_eq!(edges(&cfg)[0], (0, Target::Block(0), Condition::Always));
+    }
+
+    /// Has a bit of everything: both kinds of conditional edge, an unknown
+    /// one, an exit and a jump past the end.
+    fn everything() -> Vec<Instruction> {
+        parse(&[
+            "set 2 -> m1",
+            "fwd 3, m1",
+            "out 65",
+            "set 5 -> m0",
+            "fwd 9, m2",
+            "bye 0",
+        ])
+    }
+
+    #[test]
+    fn dot() {
+        let program = everything();
+        assert_eq!(
+            Cfg::build(&program).to_dot(&program),
+            r#"digraph sick {
+    node [shape=box, fontname="monospace"];
+    b0 [label="0: set 2 -> m1\l1: fwd 3, m1\l"];
+    b1 [label="2: out 65\l3: set 5 -> m0\l"];
+    b2 [label="4: fwd 9, m2\l"];
+    b3 [label="5: bye 0\l"];
+    exit [label="bye", shape=ellipse];
+    oob13 [label="past the end (13)", shape=ellipse];
+    unknown [label="?", shape=ellipse];
+    b0 -> b2 [label="m1 == 0"];
+    b0 -> b1 [label="m1 != 0"];
+    b1 -> unknown [style=dashed];
+    b2 -> oob13 [label="m2 == 0"];
+    b2 -> b3 [label="m2 != 0"];
+    b3 -> exit;
+}
+"#
+        );
+    }
+
+    #[test]
+    fn mermaid() {
+        let program = everything();
+        assert_eq!(
+            Cfg::build(&program).to_mermaid(&program),
+            r#"flowchart TD
+    b0["0: set 2 -> m1<br/>1: fwd 3, m1"]
+    b1["2: out 65<br/>3: set 5 -> m0"]
+    b2["4: fwd 9, m2"]
+    b3["5: bye 0"]
+    exit(["bye"])
+    oob13(["past the end (13)"])
+    unknown(["?"])
+    b0 -->|m1 == 0| b2
+    b0 -->|m1 != 0| b1
+    b1 -.-> unknown
+    b2 -->|m2 == 0| oob13
+    b2 -->|m2 != 0| b3
+    b3 --> exit
+"#
+        );
+    }
+}
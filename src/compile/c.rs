@@ -0,0 +1,250 @@
+use crate::parser::{Instruction, Value};
+use crate::program::Program;
+use std::fmt::Write;
+use std::process::Command;
+
+/// Everything that goes before the program itself. Errors look the same as
+/// the interpreter's, and `out` writes characters past 127 as UTF-8 like it
+/// does too.
+const PRELUDE: &str = r#"#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+static uint8_t mem[256];
+
+static void fail(const char *msg) {
+    fflush(stdout);
+    fprintf(stderr, "Error: \"%s\"\n", msg);
+    exit(1);
+}
+
+static inline void out(uint8_t c) {
+    if (c < 128) {
+        putchar(c);
+    } else {
+        putchar(0xc0 | (c >> 6));
+        putchar(0x80 | (c & 0x3f));
+    }
+    fflush(stdout);
+}
+
+static inline void num(uint8_t n) {
+    printf("%u", n);
+    fflush(stdout);
+}
+
+static inline uint8_t cin(void) {
+    int c = getchar();
+    if (c == EOF) {
+        fail("EOF while reading input");
+    }
+    return (uint8_t)c;
+}
+
+static inline int is_space(int c) {
+    return c == ' ' || c == '\t' || c == '\r' || c == '\v' || c == '\f';
+}
+
+/* Reads a whole line, which has to be a number from 0 to 255 */
+static inline uint8_t nin(void) {
+    int c = getchar();
+    int digits = 0, ok = 1;
+    unsigned val = 0;
+
+    while (is_space(c)) c = getchar();
+    if (c == '+') c = getchar();
+    while (c >= '0' && c <= '9') {
+        val = val * 10 + (c - '0');
+        if (val > 255) ok = 0;
+        digits++;
+        c = getchar();
+    }
+    while (is_space(c)) c = getchar();
+    if (c != '\n' && c != EOF) {
+        ok = 0;
+        while (c != '\n' && c != EOF) c = getchar();
+    }
+
+    if (!ok || digits == 0) {
+        fail("Invalid unsigned 8-bit integer");
+    }
+    return (uint8_t)val;
+}
+
+int main(void) {
+"#;
+
+fn value(val: &Value) -> String {
+    match val {
+        Value::Memory { addr } => format!("mem[{}]", addr),
+        Value::Literal { val } => format!("{}", val),
+    }
+}
+
+/// Saves a result. Writing to `m0` moves the IP, so that goes through the
+/// dispatcher instead of falling through.
+fn store(out: &mut String, idx: usize, tgt: &Value, expr: String) {
+    match tgt {
+        Value::Memory { addr: 0 } => {
+            writeln!(out, "    mem[0] = (uint8_t)(({}) + 1);", expr).expect("Writing to a string");
+            out.push_str("    goto dispatch;\n");
+        }
+        tgt => {
+            writeln!(out, "    {} = (uint8_t)({});", value(tgt), expr)
+                .expect("Writing to a string");
+            writeln!(out, "    mem[0] = {};", (idx + 1) as u8).expect("Writing to a string");
+        }
+    }
+}
+
+/// `bak`/`fwd`. Literal counts go straight to the label, memory ones need
+/// the dispatcher.
+fn jump(out: &mut String, idx: usize, inst: &Instruction, len: usize) {
+    let (count, check, op) = match inst {
+        Instruction::Bak { count, check } => (count, check, '-'),
+        Instruction::Fwd { count, check } => (count, check, '+'),
+        _ => unreachable!(),
+    };
+
+    let mut taken = String::new();
+    match inst.jump_target(idx) {
+        Some(target) => {
+            write!(taken, "mem[0] = {};", target).expect("Writing to a string");
+            if target < len {
+                write!(taken, " goto i{};", target).expect("Writing to a string");
+            } else {
+                taken.push_str(" goto dispatch;");
+            }
+        }
+        None => write!(
+            taken,
+            "mem[0] = (uint8_t)(mem[0] {} {}); goto dispatch;",
+            op,
+            value(count)
+        )
+        .expect("Writing to a string"),
+    }
+
+    match check {
+        Value::Literal { val: 0 } => writeln!(out, "    {}", taken),
+        Value::Literal { .. } => Ok(()),
+        Value::Memory { addr } => writeln!(out, "    if (mem[{}] == 0) {{ {} }}", addr, taken),
+    }
+    .expect("Writing to a string");
+    writeln!(out, "    mem[0] = {};", (idx + 1) as u8).expect("Writing to a string");
+}
+
+/// Turns the program into a single C file. Every instruction gets a label,
+/// and anything that moves the IP by a runtime value goes through a `switch`.
+/// The IP is a byte, so like in the interpreter nothing past instruction 255
+/// can ever run, and going past 255 wraps back round to 0 through the
+/// `switch`. That's why `(idx + 1) as u8` is fine.
+pub fn compile(program: &Program) -> String {
+    let instructions = &program.instructions;
+    let mut out = PRELUDE.to_owned();
+
+    // Idk what the user is doing but whatever
+    if instructions.is_empty() {
+        out.push_str("    return 0;\n}\n");
+        return out;
+    }
+
+    for (addr, val) in &program.data {
+        writeln!(out, "    mem[{}] = {};", addr, val).expect("Writing to a string");
+    }
+
+    let len = instructions.len().min(256);
+    for (idx, inst) in instructions.iter().enumerate().take(256) {
+        writeln!(out, "i{}: /* {} */", idx, inst).expect("Writing to a string");
+
+        match inst {
+            Instruction::Set { src, tgt } => store(&mut out, idx, tgt, value(src)),
+            Instruction::And { left, right, tgt } => store(
+                &mut out,
+                idx,
+                tgt,
+                format!("{} & {}", value(left), value(right)),
+            ),
+            Instruction::Xor { left, right, tgt } => store(
+                &mut out,
+                idx,
+                tgt,
+                format!("{} ^ {}", value(left), value(right)),
+            ),
+            Instruction::Not { src, tgt } => {
+                store(&mut out, idx, tgt, format!("{} == 0", value(src)))
+            }
+            Instruction::Add { left, right, tgt } => store(
+                &mut out,
+                idx,
+                tgt,
+                format!("{} + {}", value(left), value(right)),
+            ),
+            Instruction::Sub { left, right, tgt } => store(
+                &mut out,
+                idx,
+                tgt,
+                format!("{} - {}", value(left), value(right)),
+            ),
+            Instruction::Cin { tgt } => store(&mut out, idx, tgt, "cin()".to_owned()),
+            Instruction::Nin { tgt } => store(&mut out, idx, tgt, "nin()".to_owned()),
+            Instruction::Out { src } | Instruction::Num { src } => {
+                let func = match inst {
+                    Instruction::Out { .. } => "out",
+                    _ => "num",
+                };
+                writeln!(out, "    {}({});", func, value(src)).expect("Writing to a string");
+                writeln!(out, "    mem[0] = {};", (idx + 1) as u8).expect("Writing to a string");
+            }
+            Instruction::Bak { .. } | Instruction::Fwd { .. } => jump(&mut out, idx, inst, len),
+            Instruction::Bye { code } => {
+                writeln!(out, "    fflush(stdout);\n    return {};", value(code))
+                    .expect("Writing to a string")
+            }
+            Instruction::Nop => {
+                writeln!(out, "    mem[0] = {};", (idx + 1) as u8).expect("Writing to a string")
+            }
+        }
+    }
+
+    // Falling off the end lands here too, and goes to the default case
+    out.push_str("    goto dispatch;\ndispatch:\n    switch (mem[0]) {\n");
+    for idx in 0..len {
+        writeln!(out, "    case {}: goto i{};", idx, idx).expect("Writing to a string");
+    }
+    out.push_str("    default: {\n");
+    out.push_str("        char msg[64];\n");
+    out.push_str(
+        "        snprintf(msg, sizeof msg, \"Instruction pointer out of bounds: %u\", mem[0]);\n",
+    );
+    out.push_str("        fail(msg);\n");
+    out.push_str("    }\n    }\n    return 0;\n}\n");
+
+    out
+}
+
+/// Builds the generated file with `cc` and runs it against the interpreter on
+/// every input. Gives back how many inputs were actually compared, since ones
+/// that run for too long get skipped.
+pub fn test(program: &Program, inputs: &[Vec<u8>]) -> Result<usize, String> {
+    let dir = super::scratch_dir()?;
+
+    std::fs::write(dir.join("program.c"), compile(program))
+        .map_err(|_| "Could not write program.c".to_owned())?;
+
+    let binary = dir.join("program");
+    let built = Command::new("cc")
+        .args(["-O2", "-w", "-o"])
+        .arg(&binary)
+        .arg(dir.join("program.c"))
+        .status()
+        .map_err(|_| "Could not run cc".to_owned())?;
+    if !built.success() {
+        return Err("The generated code didn't compile".to_owned());
+    }
+
+    let compared = super::compare(program, inputs, || Command::new(&binary))?;
+
+    let _ = std::fs::remove_dir_all(&dir);
+    Ok(compared)
+}
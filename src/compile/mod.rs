@@ -0,0 +1,190 @@
+use crate::io::BufferIo;
+use crate::machine::{Machine, MachineConfig, RunError, MEMORY_SIZE};
+use crate::parser::Instruction;
+use crate::program::Program;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub mod c;
+pub mod rust;
+pub mod wasm;
+pub mod x86;
+
+/// How long the interpreter gets on each input in `--test` before we give up
+/// on comparing.
+const TEST_STEPS: u64 = 10_000_000;
+
+/// What `compile` can turn a program into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    C,
+    Rust,
+    /// GNU as source for x86-64 Linux.
+    X86,
+    /// A static x86-64 Linux executable, no assembler needed.
+    Elf,
+    /// WebAssembly text.
+    Wat,
+    /// WebAssembly, already assembled.
+    Wasm,
+}
+
+impl std::str::FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "c" => Ok(Target::C),
+            "rust" => Ok(Target::Rust),
+            "x86-64" => Ok(Target::X86),
+            "elf" => Ok(Target::Elf),
+            "wat" => Ok(Target::Wat),
+            "wasm" => Ok(Target::Wasm),
+            _ => Err(format!("Unknown target `{}`", s)),
+        }
+    }
+}
+
+/// The interpreter panics on `m255`, and there's no sensible way to copy
+/// that, so the backends refuse it up front.
+pub fn check_memory(instructions: &[Instruction]) -> Result<(), String> {
+    for (idx, inst) in instructions.iter().enumerate() {
+        let mut addrs = inst.reads();
+        addrs.extend(inst.write());
+        if addrs.iter().any(|addr| *addr as usize >= MEMORY_SIZE) {
+            return Err(format!(
+                "Instruction {} uses m{}, which doesn't exist",
+                idx, MEMORY_SIZE
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Everything but `elf` and `wasm` is text, but they all come out as bytes so they can
+/// be written the same way.
+pub fn compile(program: &Program, target: Target) -> Result<Vec<u8>, String> {
+    check_memory(&program.instructions)?;
+
+    Ok(match target {
+        Target::C => c::compile(program).into_bytes(),
+        Target::Rust => rust::compile(program).into_bytes(),
+        Target::X86 => x86::assembly(program).into_bytes(),
+        Target::Elf => x86::elf(program),
+        Target::Wat => wasm::wat(program).into_bytes(),
+        Target::Wasm => wasm::wasm(program),
+    })
+}
+
+/// Builds the program for the target and checks it against the interpreter on
+/// every input.
+pub fn test(program: &Program, target: Target, inputs: &[Vec<u8>]) -> Result<usize, String> {
+    check_memory(&program.instructions)?;
+
+    match target {
+        Target::C => c::test(program, inputs),
+        Target::Rust => rust::test(program, inputs),
+        Target::X86 => x86::test(program, true, inputs),
+        Target::Elf => x86::test(program, false, inputs),
+        Target::Wat => wasm::test(program, true, inputs),
+        Target::Wasm => wasm::test(program, false, inputs),
+    }
+}
+
+/// What a run did: output, exit code and what went to stderr.
+type Outcome = (Vec<u8>, i32, String);
+
+fn interpret(program: &Program, input: &[u8]) -> Option<Outcome> {
+    let instructions = &program.instructions;
+    let config = MachineConfig {
+        max_steps: Some(TEST_STEPS),
+        ..Default::default()
+    };
+    let mut machine = Machine::with_io(config, BufferIo::new(input));
+    machine.memory = program.memory();
+
+    let (code, err) = if instructions.is_empty() {
+        (0, String::new())
+    } else {
+        match machine.run(instructions) {
+            Ok(code) => (code as i32, String::new()),
+            Err(RunError::Fault(msg)) => (1, format!("Error: {:?}\n", msg)),
+            Err(_) => return None,
+        }
+    };
+
+    Some((machine.io.output, code, err))
+}
+
+/// A temporary directory for building and running things in `--test`. Each
+/// call gets its own, in case more than one test runs at once.
+fn scratch_dir() -> Result<PathBuf, String> {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "sick-as-{}-{}",
+        std::process::id(),
+        COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::create_dir_all(&dir).map_err(|_| "Could not make a temporary directory")?;
+    Ok(dir)
+}
+
+/// Runs the compiled program on every input and checks it against the
+/// interpreter. Gives back how many inputs were actually compared, since ones
+/// that run for too long get skipped.
+fn compare(
+    program: &Program,
+    inputs: &[Vec<u8>],
+    command: impl Fn() -> Command,
+) -> Result<usize, String> {
+    let mut compared = 0;
+    for (n, input) in inputs.iter().enumerate() {
+        let expected = match interpret(program, input) {
+            Some(outcome) => outcome,
+            None => {
+                println!("input {}: skipped, ran for too long", n);
+                continue;
+            }
+        };
+
+        let mut child = command()
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|_| "Could not run the generated program".to_owned())?;
+        // It might exit before reading everything, that's fine
+        let _ = child
+            .stdin
+            .take()
+            .expect("Asked for stdin")
+            .write_all(input);
+        let result = child
+            .wait_with_output()
+            .map_err(|_| "Could not run the generated program".to_owned())?;
+        let got = (
+            result.stdout,
+            result.status.code().unwrap_or(-1),
+            String::from_utf8_lossy(&result.stderr).into_owned(),
+        );
+
+        if got != expected {
+            return Err(format!(
+                "input {}: interpreter gave {:?} and exit {} ({}), generated code gave {:?} and exit {} ({})",
+                n,
+                String::from_utf8_lossy(&expected.0),
+                expected.1,
+                expected.2.trim(),
+                String::from_utf8_lossy(&got.0),
+                got.1,
+                got.2.trim()
+            ));
+        }
+        println!("input {}: ok", n);
+        compared += 1;
+    }
+
+    Ok(compared)
+}
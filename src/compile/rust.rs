@@ -0,0 +1,228 @@
+use crate::parser::{Instruction, Value};
+use crate::program::Program;
+use std::fmt::Write;
+use std::process::Command;
+
+const PRELUDE: &str = "\
+//! Generated by sick-as, don't edit by hand.
+
+use std::num::Wrapping;
+
+/// Where `out`, `num`, `cin` and `nin` go. Same as `sick_as::SickIo`.
+pub trait SickIo {
+    fn out(&mut self, val: u8);
+    fn num(&mut self, val: u8);
+    fn cin(&mut self) -> Result<u8, String>;
+    fn nin(&mut self) -> Result<u8, String>;
+}
+
+/// Runs the program and gives back the exit code from `bye`. If the
+/// interpreter would have stopped with an error, this panics with the same
+/// message instead.
+";
+
+/// A `main` that hooks the module up to the terminal and reports errors like
+/// the interpreter does, so the two can be compared.
+const HARNESS: &str = r#"mod program;
+
+use program::SickIo;
+use std::io::{BufRead, Read, Write};
+
+struct Std;
+
+impl SickIo for Std {
+    fn out(&mut self, val: u8) {
+        print!("{}", val as char);
+        std::io::stdout().flush().unwrap();
+    }
+
+    fn num(&mut self, val: u8) {
+        print!("{}", val);
+        std::io::stdout().flush().unwrap();
+    }
+
+    fn cin(&mut self) -> Result<u8, String> {
+        match std::io::stdin().lock().bytes().next() {
+            Some(val) => Ok(val.unwrap()),
+            None => Err("EOF while reading input".to_owned()),
+        }
+    }
+
+    fn nin(&mut self) -> Result<u8, String> {
+        let mut buf = String::new();
+        std::io::stdin().lock().read_line(&mut buf).unwrap();
+        buf.trim()
+            .parse()
+            .map_err(|_| "Invalid unsigned 8-bit integer".to_owned())
+    }
+}
+
+fn main() {
+    std::panic::set_hook(Box::new(|_| {}));
+    match std::panic::catch_unwind(|| program::run(&mut Std)) {
+        Ok(code) => std::process::exit(code as i32),
+        Err(msg) => {
+            let msg = match msg.downcast::<String>() {
+                Ok(msg) => *msg,
+                Err(msg) => msg.downcast::<&str>().map(|msg| msg.to_string()).unwrap(),
+            };
+            eprintln!("Error: {:?}", msg);
+            std::process::exit(1)
+        }
+    }
+}
+"#;
+
+fn value(val: &Value) -> String {
+    match val {
+        Value::Memory { addr } => format!("mem[{}]", addr),
+        Value::Literal { val } => format!("Wrapping({}u8)", val),
+    }
+}
+
+/// Same as `value`, but as a plain `u8`.
+fn raw(val: &Value) -> String {
+    match val {
+        Value::Memory { addr } => format!("mem[{}].0", addr),
+        Value::Literal { val } => format!("{}", val),
+    }
+}
+
+/// The body of one `match` arm.
+fn arm(out: &mut String, inst: &Instruction) {
+    const IN: &str = "                ";
+
+    let store = |out: &mut String, tgt: &Value, expr: String| {
+        writeln!(out, "{}{} = {};", IN, value(tgt), expr).expect("Writing to a string");
+        writeln!(out, "{}mem[0] += 1;", IN).expect("Writing to a string");
+    };
+
+    match inst {
+        Instruction::Set { src, tgt } => store(out, tgt, value(src)),
+        Instruction::And { left, right, tgt } => {
+            store(out, tgt, format!("{} & {}", value(left), value(right)))
+        }
+        Instruction::Xor { left, right, tgt } => {
+            store(out, tgt, format!("{} ^ {}", value(left), value(right)))
+        }
+        Instruction::Not { src, tgt } => {
+            store(out, tgt, format!("Wrapping(({} == 0) as u8)", raw(src)))
+        }
+        Instruction::Add { left, right, tgt } => {
+            store(out, tgt, format!("{} + {}", value(left), value(right)))
+        }
+        Instruction::Sub { left, right, tgt } => {
+            store(out, tgt, format!("{} - {}", value(left), value(right)))
+        }
+        Instruction::Cin { tgt } => store(
+            out,
+            tgt,
+            "Wrapping(io.cin().unwrap_or_else(|msg| panic!(\"{}\", msg)))".to_owned(),
+        ),
+        Instruction::Nin { tgt } => store(
+            out,
+            tgt,
+            "Wrapping(io.nin().unwrap_or_else(|msg| panic!(\"{}\", msg)))".to_owned(),
+        ),
+        Instruction::Out { src } => {
+            writeln!(out, "{}io.out({});", IN, raw(src)).expect("Writing to a string");
+            writeln!(out, "{}mem[0] += 1;", IN).expect("Writing to a string");
+        }
+        Instruction::Num { src } => {
+            writeln!(out, "{}io.num({});", IN, raw(src)).expect("Writing to a string");
+            writeln!(out, "{}mem[0] += 1;", IN).expect("Writing to a string");
+        }
+        Instruction::Bak { count, check } | Instruction::Fwd { count, check } => {
+            let op = match inst {
+                Instruction::Bak { .. } => '-',
+                _ => '+',
+            };
+            match check {
+                Value::Literal { val: 0 } => writeln!(out, "{}mem[0] {}= {};", IN, op, value(count)),
+                Value::Literal { .. } => writeln!(out, "{}mem[0] += 1;", IN),
+                Value::Memory { addr } => writeln!(
+                    out,
+                    "{}if mem[{}].0 == 0 {{\n{}    mem[0] {}= {};\n{}}} else {{\n{}    mem[0] += 1;\n{}}}",
+                    IN,
+                    addr,
+                    IN,
+                    op,
+                    value(count),
+                    IN,
+                    IN,
+                    IN
+                ),
+            }
+            .expect("Writing to a string");
+        }
+        Instruction::Bye { code } => {
+            writeln!(out, "{}return {};", IN, raw(code)).expect("Writing to a string")
+        }
+        Instruction::Nop => writeln!(out, "{}mem[0] += 1;", IN).expect("Writing to a string"),
+    }
+}
+
+/// Turns the program into a Rust module with a `run` function. It's a `match`
+/// on the instruction pointer in a loop, so jumps of any kind just work.
+pub fn compile(program: &Program) -> String {
+    let instructions = &program.instructions;
+    let mut out = PRELUDE.to_owned();
+
+    // Idk what the user is doing but whatever
+    if instructions.is_empty() {
+        out.push_str("pub fn run(_io: &mut impl SickIo) -> u8 {\n    0\n}\n");
+        return out;
+    }
+
+    out.push_str("pub fn run(io: &mut impl SickIo) -> u8 {\n");
+    out.push_str("    #[allow(unused_mut)]\n");
+    out.push_str("    let mut mem = [Wrapping(0u8); 256];\n");
+    for (addr, val) in &program.data {
+        writeln!(out, "    mem[{}] = Wrapping({});", addr, val).expect("Writing to a string");
+    }
+    out.push('\n');
+    out.push_str("    loop {\n        match mem[0].0 {\n");
+
+    for (idx, inst) in instructions.iter().enumerate().take(256) {
+        writeln!(out, "            // {}", inst).expect("Writing to a string");
+        writeln!(out, "            {} => {{", idx).expect("Writing to a string");
+        arm(&mut out, inst);
+        out.push_str("            }\n");
+    }
+
+    if instructions.len() < 256 {
+        out.push_str("            ip => panic!(\"Instruction pointer out of bounds: {}\", ip),\n");
+    }
+    out.push_str("        }\n    }\n}\n");
+
+    out
+}
+
+/// Builds the generated module with `rustc` and runs it against the
+/// interpreter on every input. Gives back how many inputs were actually
+/// compared, since ones that run for too long get skipped.
+pub fn test(program: &Program, inputs: &[Vec<u8>]) -> Result<usize, String> {
+    let dir = super::scratch_dir()?;
+
+    let write = |name: &str, text: &str| {
+        std::fs::write(dir.join(name), text).map_err(|_| format!("Could not write {}", name))
+    };
+    write("program.rs", &compile(program))?;
+    write("main.rs", HARNESS)?;
+
+    let binary = dir.join("program");
+    let built = Command::new("rustc")
+        .args(["--edition", "2021", "-O", "-A", "warnings", "-o"])
+        .arg(&binary)
+        .arg(dir.join("main.rs"))
+        .status()
+        .map_err(|_| "Could not run rustc".to_owned())?;
+    if !built.success() {
+        return Err("The generated code didn't compile".to_owned());
+    }
+
+    let compared = super::compare(program, inputs, || Command::new(&binary))?;
+
+    let _ = std::fs::remove_dir_all(&dir);
+    Ok(compared)
+}
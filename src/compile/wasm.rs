@@ -0,0 +1,522 @@
+use crate::parser::{Instruction, Value};
+use crate::program::Program;
+use std::fmt::Write;
+use std::path::Path;
+use std::process::Command;
+
+/*
+* The module imports `out`, `num`, `cin`, `nin` and `oob` from "sick" and
+* exports `run`, which gives back the exit code from `bye`, and its memory.
+* Wasm memory comes in 64KiB pages so there's one of those, but only the first
+* 256 bytes get used, m0 at 0 up to m255 at 255.
+*
+* `run` is a loop around a `br_table` on m0 with a block per instruction, so
+* anything that moves the IP just sets m0 and goes round again. Everything
+* else falls through into the next instruction.
+*/
+
+/// Runs a module under node with the same stdin, stdout and errors as the
+/// interpreter, for `--test`.
+const NODE_HARNESS: &str = r#"const fs = require("fs");
+
+let input = [];
+try {
+    input = [...fs.readFileSync(0)];
+} catch (e) {}
+
+class SickError extends Error {}
+const fail = (msg) => {
+    throw new SickError(msg);
+};
+
+const imports = {
+    sick: {
+        out: (c) => fs.writeSync(1, Buffer.from(String.fromCharCode(c), "utf8")),
+        num: (n) => fs.writeSync(1, String(n)),
+        cin: () => (input.length ? input.shift() : fail("EOF while reading input")),
+        nin: () => {
+            const line = [];
+            while (input.length) {
+                const c = input.shift();
+                line.push(c);
+                if (c === 10) break;
+            }
+            const text = Buffer.from(line).toString("utf8").trim();
+            if (!/^\+?[0-9]+$/.test(text) || Number(text) > 255) {
+                fail("Invalid unsigned 8-bit integer");
+            }
+            return Number(text);
+        },
+        oob: (ip) => fail("Instruction pointer out of bounds: " + ip),
+    },
+};
+
+const wasm = new WebAssembly.Module(fs.readFileSync(process.argv[2]));
+try {
+    process.exitCode = new WebAssembly.Instance(wasm, imports).exports.run();
+} catch (e) {
+    if (!(e instanceof SickError)) throw e;
+    fs.writeSync(2, "Error: " + JSON.stringify(e.message) + "\n");
+    process.exitCode = 1;
+}
+"#;
+
+/// What the module asks the host for, in import order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Import {
+    Out,
+    Num,
+    Cin,
+    Nin,
+    Oob,
+}
+
+const IMPORTS: [Import; 5] = [
+    Import::Out,
+    Import::Num,
+    Import::Cin,
+    Import::Nin,
+    Import::Oob,
+];
+
+impl Import {
+    fn name(self) -> &'static str {
+        match self {
+            Import::Out => "out",
+            Import::Num => "num",
+            Import::Cin => "cin",
+            Import::Nin => "nin",
+            Import::Oob => "oob",
+        }
+    }
+
+    /// Takes an i32 if true, gives one back if not.
+    fn takes_arg(self) -> bool {
+        matches!(self, Import::Out | Import::Num | Import::Oob)
+    }
+}
+
+/// The handful of wasm instructions this needs. Each one knows how to print
+/// itself as WAT and how to encode itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Op {
+    Block(String),
+    Loop(String),
+    If,
+    End,
+    Const(i32),
+    Load8,
+    Store8,
+    Add,
+    Sub,
+    And,
+    Xor,
+    Eqz,
+    Call(Import),
+    Br(String),
+    BrTable(Vec<String>, String),
+    Return,
+    Unreachable,
+    /// Only shows up in the text.
+    Comment(String),
+}
+
+impl Op {
+    fn text(&self) -> String {
+        match self {
+            Op::Block(label) => format!("block ${}", label),
+            Op::Loop(label) => format!("loop ${}", label),
+            Op::If => "if".to_owned(),
+            Op::End => "end".to_owned(),
+            Op::Const(val) => format!("i32.const {}", val),
+            Op::Load8 => "i32.load8_u".to_owned(),
+            Op::Store8 => "i32.store8".to_owned(),
+            Op::Add => "i32.add".to_owned(),
+            Op::Sub => "i32.sub".to_owned(),
+            Op::And => "i32.and".to_owned(),
+            Op::Xor => "i32.xor".to_owned(),
+            Op::Eqz => "i32.eqz".to_owned(),
+            Op::Call(import) => format!("call ${}", import.name()),
+            Op::Br(label) => format!("br ${}", label),
+            Op::BrTable(labels, default) => {
+                let mut text = "br_table".to_owned();
+                for label in labels.iter().chain([default]) {
+                    write!(text, " ${}", label).expect("Writing to a string");
+                }
+                text
+            }
+            Op::Return => "return".to_owned(),
+            Op::Unreachable => "unreachable".to_owned(),
+            Op::Comment(text) => format!(";; {}", text),
+        }
+    }
+
+    /// `labels` is every enclosing block, innermost last, for working out how
+    /// far out a branch goes.
+    fn encode(&self, out: &mut Vec<u8>, labels: &mut Vec<Option<String>>) {
+        let depth = |labels: &[Option<String>], label: &str| {
+            let pos = labels
+                .iter()
+                .rposition(|l| l.as_deref() == Some(label))
+                .expect("Every label is defined");
+            (labels.len() - 1 - pos) as u32
+        };
+
+        match self {
+            Op::Block(label) => {
+                out.extend([0x02, 0x40]);
+                labels.push(Some(label.clone()));
+            }
+            Op::Loop(label) => {
+                out.extend([0x03, 0x40]);
+                labels.push(Some(label.clone()));
+            }
+            Op::If => {
+                out.extend([0x04, 0x40]);
+                labels.push(None);
+            }
+            Op::End => {
+                out.push(0x0b);
+                labels.pop();
+            }
+            Op::Const(val) => {
+                out.push(0x41);
+                signed(out, *val);
+            }
+            // Alignment and offset are both 0
+            Op::Load8 => out.extend([0x2d, 0, 0]),
+            Op::Store8 => out.extend([0x3a, 0, 0]),
+            Op::Add => out.push(0x6a),
+            Op::Sub => out.push(0x6b),
+            Op::And => out.push(0x71),
+            Op::Xor => out.push(0x73),
+            Op::Eqz => out.push(0x45),
+            Op::Call(import) => {
+                out.push(0x10);
+                let idx = IMPORTS.iter().position(|i| i == import).expect("Imported");
+                unsigned(out, idx as u32);
+            }
+            Op::Br(label) => {
+                out.push(0x0c);
+                unsigned(out, depth(labels, label));
+            }
+            Op::BrTable(targets, default) => {
+                out.push(0x0e);
+                unsigned(out, targets.len() as u32);
+                for label in targets.iter().chain([default]) {
+                    unsigned(out, depth(labels, label));
+                }
+            }
+            Op::Return => out.push(0x0f),
+            Op::Unreachable => out.push(0x00),
+            Op::Comment(_) => {}
+        }
+    }
+}
+
+/// LEB128, the way wasm wants every number.
+fn unsigned(out: &mut Vec<u8>, mut val: u32) {
+    loop {
+        let byte = (val & 0x7f) as u8;
+        val >>= 7;
+        if val == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn signed(out: &mut Vec<u8>, mut val: i32) {
+    loop {
+        let byte = (val & 0x7f) as u8;
+        val >>= 7;
+        let done = (val == 0 && byte & 0x40 == 0) || (val == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn load(val: &Value) -> Vec<Op> {
+    match val {
+        Value::Memory { addr } => vec![Op::Const(*addr as i32), Op::Load8],
+        Value::Literal { val } => vec![Op::Const(*val as i32)],
+    }
+}
+
+/// Sets the IP to the next instruction, like the interpreter does after
+/// everything that isn't a jump.
+fn next(ops: &mut Vec<Op>, idx: usize) {
+    ops.extend([Op::Const(0), Op::Const((idx + 1) as u8 as i32), Op::Store8]);
+}
+
+/// Saves whatever `val` leaves on the stack. `store8` cuts it down to a byte,
+/// which is the wrapping arithmetic done for free. Writing to `m0` moves the
+/// IP, so that goes back round to the dispatcher.
+fn store(ops: &mut Vec<Op>, idx: usize, tgt: &Value, val: Vec<Op>) {
+    let addr = match tgt {
+        Value::Memory { addr } => *addr,
+        Value::Literal { .. } => unreachable!(),
+    };
+
+    ops.push(Op::Const(addr as i32));
+    ops.extend(val);
+    if addr == 0 {
+        ops.extend([
+            Op::Const(1),
+            Op::Add,
+            Op::Store8,
+            Op::Br("dispatch".to_owned()),
+        ]);
+    } else {
+        ops.push(Op::Store8);
+        next(ops, idx);
+    }
+}
+
+fn binary(left: &Value, right: &Value, op: Op) -> Vec<Op> {
+    let mut ops = load(left);
+    ops.extend(load(right));
+    ops.push(op);
+    ops
+}
+
+fn instruction(ops: &mut Vec<Op>, idx: usize, inst: &Instruction) {
+    match inst {
+        Instruction::Set { src, tgt } => store(ops, idx, tgt, load(src)),
+        Instruction::And { left, right, tgt } => store(ops, idx, tgt, binary(left, right, Op::And)),
+        Instruction::Xor { left, right, tgt } => store(ops, idx, tgt, binary(left, right, Op::Xor)),
+        Instruction::Add { left, right, tgt } => store(ops, idx, tgt, binary(left, right, Op::Add)),
+        Instruction::Sub { left, right, tgt } => store(ops, idx, tgt, binary(left, right, Op::Sub)),
+        Instruction::Not { src, tgt } => {
+            let mut val = load(src);
+            val.push(Op::Eqz);
+            store(ops, idx, tgt, val)
+        }
+        Instruction::Cin { tgt } => store(ops, idx, tgt, vec![Op::Call(Import::Cin)]),
+        Instruction::Nin { tgt } => store(ops, idx, tgt, vec![Op::Call(Import::Nin)]),
+        Instruction::Out { src } | Instruction::Num { src } => {
+            ops.extend(load(src));
+            ops.push(Op::Call(match inst {
+                Instruction::Out { .. } => Import::Out,
+                _ => Import::Num,
+            }));
+            next(ops, idx);
+        }
+        Instruction::Bak { count, check } | Instruction::Fwd { count, check } => {
+            let mut taken = vec![Op::Const(0)];
+            match inst.jump_target(idx) {
+                Some(target) => taken.push(Op::Const(target as i32)),
+                None => {
+                    taken.extend(load(&Value::Memory { addr: 0 }));
+                    taken.extend(load(count));
+                    taken.push(match inst {
+                        Instruction::Bak { .. } => Op::Sub,
+                        _ => Op::Add,
+                    });
+                }
+            }
+            taken.extend([Op::Store8, Op::Br("dispatch".to_owned())]);
+
+            match check {
+                Value::Literal { val: 0 } => ops.extend(taken),
+                Value::Literal { .. } => {}
+                Value::Memory { .. } => {
+                    ops.extend(load(check));
+                    ops.extend([Op::Eqz, Op::If]);
+                    ops.extend(taken);
+                    ops.push(Op::End);
+                }
+            }
+            next(ops, idx);
+        }
+        Instruction::Bye { code } => {
+            ops.extend(load(code));
+            ops.push(Op::Return);
+        }
+        Instruction::Nop => next(ops, idx),
+    }
+}
+
+/// The body of `run`.
+fn body(program: &Program) -> Vec<Op> {
+    let instructions = &program.instructions;
+    // Idk what the user is doing but whatever
+    if instructions.is_empty() {
+        return vec![Op::Const(0)];
+    }
+
+    let len = instructions.len().min(256);
+    let names: Vec<String> = (0..len).map(|idx| format!("i{}", idx)).collect();
+
+    let mut ops = Vec::new();
+    for (addr, val) in &program.data {
+        ops.extend([Op::Const(*addr as i32), Op::Const(*val as i32), Op::Store8]);
+    }
+    ops.extend([Op::Loop("dispatch".to_owned()), Op::Block("oob".to_owned())]);
+    ops.extend(names.iter().rev().map(|name| Op::Block(name.clone())));
+    ops.extend(load(&Value::Memory { addr: 0 }));
+    ops.push(Op::BrTable(names.clone(), "oob".to_owned()));
+
+    for (idx, inst) in instructions.iter().enumerate().take(len) {
+        ops.push(Op::End);
+        ops.push(Op::Comment(format!("{}: {}", idx, inst)));
+        instruction(&mut ops, idx, inst);
+    }
+
+    // Off the end, which might wrap back round to 0
+    ops.push(Op::Br("dispatch".to_owned()));
+    ops.push(Op::End);
+    ops.extend(load(&Value::Memory { addr: 0 }));
+    ops.extend([
+        Op::Call(Import::Oob),
+        Op::Unreachable,
+        Op::End,
+        Op::Unreachable,
+    ]);
+
+    ops
+}
+
+/// Turns the program into a WebAssembly text module.
+pub fn wat(program: &Program) -> String {
+    let mut out = "(module\n".to_owned();
+
+    for import in IMPORTS {
+        let sig = if import.takes_arg() {
+            "(param i32)"
+        } else {
+            "(result i32)"
+        };
+        writeln!(
+            out,
+            "  (import \"sick\" \"{0}\" (func ${0} {1}))",
+            import.name(),
+            sig
+        )
+        .expect("Writing to a string");
+    }
+    out.push_str("  (memory (export \"memory\") 1)\n");
+    out.push_str("  (func (export \"run\") (result i32)\n");
+
+    let mut indent = 2;
+    for op in body(program) {
+        if op == Op::End {
+            indent -= 1;
+        }
+        writeln!(out, "{}{}", "  ".repeat(indent), op.text()).expect("Writing to a string");
+        if matches!(op, Op::Block(_) | Op::Loop(_) | Op::If) {
+            indent += 1;
+        }
+    }
+
+    out.push_str("  )\n)\n");
+    out
+}
+
+/// A section with its id and size in front.
+fn section(out: &mut Vec<u8>, id: u8, contents: Vec<u8>) {
+    out.push(id);
+    unsigned(out, contents.len() as u32);
+    out.extend(contents);
+}
+
+fn name(out: &mut Vec<u8>, name: &str) {
+    unsigned(out, name.len() as u32);
+    out.extend(name.as_bytes());
+}
+
+/// The same module, already in the binary format.
+pub fn wasm(program: &Program) -> Vec<u8> {
+    let mut out = b"\0asm".to_vec();
+    out.extend(1u32.to_le_bytes());
+
+    // Types: 0 is (i32) -> (), 1 is () -> i32
+    section(&mut out, 1, vec![2, 0x60, 1, 0x7f, 0, 0x60, 0, 1, 0x7f]);
+
+    let mut imports = Vec::new();
+    unsigned(&mut imports, IMPORTS.len() as u32);
+    for import in IMPORTS {
+        name(&mut imports, "sick");
+        name(&mut imports, import.name());
+        imports.extend([0, if import.takes_arg() { 0 } else { 1 }]);
+    }
+    section(&mut out, 2, imports);
+
+    // One function, `run`, of type 1
+    section(&mut out, 3, vec![1, 1]);
+    // One memory, at least a page
+    section(&mut out, 5, vec![1, 0, 1]);
+
+    let mut exports = vec![2];
+    name(&mut exports, "run");
+    exports.push(0);
+    unsigned(&mut exports, IMPORTS.len() as u32);
+    name(&mut exports, "memory");
+    exports.extend([2, 0]);
+    section(&mut out, 7, exports);
+
+    // No locals
+    let mut code = vec![0];
+    let mut labels = Vec::new();
+    for op in body(program) {
+        op.encode(&mut code, &mut labels);
+    }
+    code.push(0x0b);
+    let mut bodies = vec![1];
+    unsigned(&mut bodies, code.len() as u32);
+    bodies.extend(code);
+    section(&mut out, 10, bodies);
+
+    out
+}
+
+/// Turns the WAT into a module with whichever of `wat2wasm` or `wasm-tools`
+/// is around, so `--test` runs the text that was actually generated.
+fn assemble_wat(dir: &Path) -> Result<(), String> {
+    for (tool, args) in [
+        ("wat2wasm", &["program.wat", "-o", "program.wasm"][..]),
+        (
+            "wasm-tools",
+            &["parse", "program.wat", "-o", "program.wasm"][..],
+        ),
+    ] {
+        match Command::new(tool).args(args).current_dir(dir).status() {
+            Ok(status) if status.success() => return Ok(()),
+            Ok(_) => return Err("The generated code didn't assemble".to_owned()),
+            Err(_) => {}
+        }
+    }
+    Err("Testing WAT needs wat2wasm or wasm-tools to assemble it".to_owned())
+}
+
+/// Runs the module under node against the interpreter on every input. With
+/// `text` it's the WAT that gets run, assembled by something else, otherwise
+/// it's the binary. Gives back how many inputs were actually compared.
+pub fn test(program: &Program, text: bool, inputs: &[Vec<u8>]) -> Result<usize, String> {
+    let dir = super::scratch_dir()?;
+
+    if text {
+        std::fs::write(dir.join("program.wat"), wat(program))
+            .map_err(|_| "Could not write program.wat")?;
+        assemble_wat(&dir)?;
+    } else {
+        std::fs::write(dir.join("program.wasm"), wasm(program))
+            .map_err(|_| "Could not write program.wasm")?;
+    }
+    std::fs::write(dir.join("run.cjs"), NODE_HARNESS).map_err(|_| "Could not write run.cjs")?;
+
+    let compared = super::compare(program, inputs, || {
+        let mut command = Command::new("node");
+        command
+            .arg(dir.join("run.cjs"))
+            .arg(dir.join("program.wasm"));
+        command
+    })?;
+
+    let _ = std::fs::remove_dir_all(&dir);
+    Ok(compared)
+}
@@ -0,0 +1,784 @@
+use crate::parser::{Instruction, Value};
+use crate::program::Program;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::process::Command;
+
+/*
+* Register use:
+* rbx  always points at memory. m0..m255 are at 0..255(%rbx), and a little
+*      scratch space for input/output comes right after at BUF(%rbx).
+* eax, ecx  scratch for values.
+* ebp  the number being built up by `nin`.
+*
+* Everything's a plain static binary talking to Linux through syscalls, no
+* libc. Jumps by a memory value and writes to m0 go through `dispatch`, which
+* looks the new IP up in a table of instruction addresses.
+*/
+
+/// Where the ELF gets loaded. Memory goes in its own segment at BSS_ADDR.
+const LOAD_ADDR: u32 = 0x40_0000;
+const BSS_ADDR: u32 = 0x60_0000;
+
+/// Scratch space for `out`, `num` and reading input, after the 256 cells.
+const BUF: u32 = 256;
+/// End of the scratch space, `num` writes digits backwards from here.
+const BUF_END: u32 = 320;
+/// Flags `nin` uses while it's reading.
+const NIN_DIGITS: u32 = 328;
+const NIN_OK: u32 = 329;
+const BSS_SIZE: u32 = 336;
+
+const MSG_EOF: &str = "Error: \"EOF while reading input\"\n";
+const MSG_NIN: &str = "Error: \"Invalid unsigned 8-bit integer\"\n";
+const MSG_OOB: &str = "Error: \"Instruction pointer out of bounds: ";
+const MSG_END: &str = "\"\n";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reg {
+    Eax,
+    Ecx,
+    Edx,
+    Ebx,
+    Ebp,
+    Esi,
+    Edi,
+}
+
+impl Reg {
+    fn code(self) -> u8 {
+        match self {
+            Reg::Eax => 0,
+            Reg::Ecx => 1,
+            Reg::Edx => 2,
+            Reg::Ebx => 3,
+            Reg::Ebp => 5,
+            Reg::Esi => 6,
+            Reg::Edi => 7,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Reg::Eax => "eax",
+            Reg::Ecx => "ecx",
+            Reg::Edx => "edx",
+            Reg::Ebx => "ebx",
+            Reg::Ebp => "ebp",
+            Reg::Esi => "esi",
+            Reg::Edi => "edi",
+        }
+    }
+
+    /// The 64-bit name, for `lea`.
+    fn wide(self) -> String {
+        format!("r{}", &self.name()[1..])
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Imm {
+    Num(u32),
+    /// The address of a label.
+    Sym(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cond {
+    Always,
+    E,
+    Ne,
+    B,
+    Ae,
+    Be,
+    A,
+    Le,
+}
+
+impl Cond {
+    fn name(self) -> &'static str {
+        match self {
+            Cond::Always => "jmp",
+            Cond::E => "je",
+            Cond::Ne => "jne",
+            Cond::B => "jb",
+            Cond::Ae => "jae",
+            Cond::Be => "jbe",
+            Cond::A => "ja",
+            Cond::Le => "jle",
+        }
+    }
+}
+
+/// A tiny subset of x86-64, just enough for this. Each one knows how to
+/// print itself for GNU as and how to encode itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Op {
+    Label(String),
+    /// movl $imm, %reg
+    MovImm(Reg, Imm),
+    /// movzbl disp(%rbx), %reg
+    Load(Reg, u32),
+    /// movb %al, disp(%rbx)
+    Store(u32),
+    /// movb $imm, disp(%rbx)
+    StoreImm(u32, u8),
+    /// cmpb $imm, disp(%rbx)
+    CmpMem(u32, u8),
+    /// cmpl $imm, %eax
+    CmpEax(u32),
+    /// leaq disp(%rbx), %reg
+    Lea(Reg, u32),
+    Jump(Cond, String),
+    Call(String),
+    /// jmp *table(,%rax,8)
+    JumpTable(String),
+    /// Anything without operands worth abstracting, as text and bytes.
+    Raw(&'static str, &'static [u8]),
+    /// Data: the address of a label.
+    Quad(String),
+    /// Data: a string.
+    Ascii(String),
+}
+
+const RET: Op = Op::Raw("ret", &[0xc3]);
+const SYSCALL: Op = Op::Raw("syscall", &[0x0f, 0x05]);
+
+impl Op {
+    /// How many bytes this takes up. Every jump uses a 32-bit offset, so this
+    /// never depends on where labels end up.
+    fn size(&self) -> u32 {
+        match self {
+            Op::Label(_) => 0,
+            Op::MovImm(..) => 5,
+            Op::Load(..) => 7,
+            Op::Store(_) => 6,
+            Op::StoreImm(..) => 7,
+            Op::CmpMem(..) => 7,
+            Op::CmpEax(_) => 5,
+            Op::Lea(..) => 7,
+            Op::Jump(Cond::Always, _) => 5,
+            Op::Jump(..) => 6,
+            Op::Call(_) => 5,
+            Op::JumpTable(_) => 7,
+            Op::Raw(_, bytes) => bytes.len() as u32,
+            Op::Quad(_) => 8,
+            Op::Ascii(text) => text.len() as u32,
+        }
+    }
+
+    fn text(&self) -> String {
+        let imm = |imm: &Imm| match imm {
+            Imm::Num(n) => format!("{}", n),
+            Imm::Sym(sym) => sym.clone(),
+        };
+
+        match self {
+            Op::Label(name) => format!("{}:", name),
+            Op::MovImm(reg, val) => format!("    movl ${}, %{}", imm(val), reg.name()),
+            Op::Load(reg, disp) => format!("    movzbl {}(%rbx), %{}", disp, reg.name()),
+            Op::Store(disp) => format!("    movb %al, {}(%rbx)", disp),
+            Op::StoreImm(disp, val) => format!("    movb ${}, {}(%rbx)", val, disp),
+            Op::CmpMem(disp, val) => format!("    cmpb ${}, {}(%rbx)", val, disp),
+            Op::CmpEax(val) => format!("    cmpl ${}, %eax", val),
+            Op::Lea(reg, disp) => format!("    leaq {}(%rbx), %{}", disp, reg.wide()),
+            Op::Jump(cond, label) => format!("    {} {}", cond.name(), label),
+            Op::Call(label) => format!("    call {}", label),
+            Op::JumpTable(table) => format!("    jmp *{}(,%rax,8)", table),
+            Op::Raw(text, _) => format!("    {}", text),
+            Op::Quad(label) => format!("    .quad {}", label),
+            Op::Ascii(text) => format!("    .ascii {:?}", text),
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>, here: u32, labels: &HashMap<String, u32>) {
+        let addr = |label: &str| *labels.get(label).expect("Every label is defined");
+        let rel = |label: &str| (addr(label) as i64 - (here + self.size()) as i64) as i32;
+        // mod=10 r/m=011, so disp32(%rbx)
+        let rbx = |reg: u8| 0x83 | (reg << 3);
+
+        match self {
+            Op::Label(_) => {}
+            Op::MovImm(reg, val) => {
+                out.push(0xb8 + reg.code());
+                out.extend(
+                    match val {
+                        Imm::Num(n) => *n,
+                        Imm::Sym(sym) => addr(sym),
+                    }
+                    .to_le_bytes(),
+                );
+            }
+            Op::Load(reg, disp) => {
+                out.extend([0x0f, 0xb6, rbx(reg.code())]);
+                out.extend(disp.to_le_bytes());
+            }
+            Op::Store(disp) => {
+                out.extend([0x88, rbx(0)]);
+                out.extend(disp.to_le_bytes());
+            }
+            Op::StoreImm(disp, val) => {
+                out.extend([0xc6, rbx(0)]);
+                out.extend(disp.to_le_bytes());
+                out.push(*val);
+            }
+            Op::CmpMem(disp, val) => {
+                out.extend([0x80, rbx(7)]);
+                out.extend(disp.to_le_bytes());
+                out.push(*val);
+            }
+            Op::CmpEax(val) => {
+                out.push(0x3d);
+                out.extend(val.to_le_bytes());
+            }
+            Op::Lea(reg, disp) => {
+                out.extend([0x48, 0x8d, rbx(reg.code())]);
+                out.extend(disp.to_le_bytes());
+            }
+            Op::Jump(cond, label) => {
+                match cond {
+                    Cond::Always => out.push(0xe9),
+                    Cond::E => out.extend([0x0f, 0x84]),
+                    Cond::Ne => out.extend([0x0f, 0x85]),
+                    Cond::B => out.extend([0x0f, 0x82]),
+                    Cond::Ae => out.extend([0x0f, 0x83]),
+                    Cond::Be => out.extend([0x0f, 0x86]),
+                    Cond::A => out.extend([0x0f, 0x87]),
+                    Cond::Le => out.extend([0x0f, 0x8e]),
+                }
+                out.extend(rel(label).to_le_bytes());
+            }
+            Op::Call(label) => {
+                out.push(0xe8);
+                out.extend(rel(label).to_le_bytes());
+            }
+            Op::JumpTable(table) => {
+                // mod=00 r/m=100 for a SIB, then scale=8 index=rax and no base
+                out.extend([0xff, 0x24, 0xc5]);
+                out.extend(addr(table).to_le_bytes());
+            }
+            Op::Raw(_, bytes) => out.extend(*bytes),
+            Op::Quad(label) => out.extend((addr(label) as u64).to_le_bytes()),
+            Op::Ascii(text) => out.extend(text.as_bytes()),
+        }
+    }
+}
+
+/// Loads a value into a register.
+fn load(reg: Reg, val: &Value) -> Op {
+    match val {
+        Value::Memory { addr } => Op::Load(reg, *addr as u32),
+        Value::Literal { val } => Op::MovImm(reg, Imm::Num(*val as u32)),
+    }
+}
+
+fn label(name: &str) -> Op {
+    Op::Label(name.to_owned())
+}
+
+fn jump(cond: Cond, to: &str) -> Op {
+    Op::Jump(cond, to.to_owned())
+}
+
+/// Puts a message on stderr and exits with 1, like the interpreter does.
+fn fail_with(name: &str, msg: &str) -> Vec<Op> {
+    vec![
+        Op::MovImm(Reg::Esi, Imm::Sym(name.to_owned())),
+        Op::MovImm(Reg::Edx, Imm::Num(msg.len() as u32)),
+        jump(Cond::Always, "fail"),
+    ]
+}
+
+/// Jumps back to `to` if eax is a space, tab or one of the other ASCII
+/// whitespace characters that aren't a newline.
+fn if_space(to: &str) -> Vec<Op> {
+    [32, 9, 11, 12, 13]
+        .iter()
+        .flat_map(|c| [Op::CmpEax(*c), jump(Cond::E, to)])
+        .collect()
+}
+
+/// The helpers every program gets.
+fn runtime() -> Vec<Op> {
+    let mut ops = vec![
+        // out: writes eax as a character, UTF-8 past 127 like the interpreter
+        label("out"),
+        Op::CmpEax(128),
+        jump(Cond::Ae, "out_wide"),
+        Op::Store(BUF),
+        Op::MovImm(Reg::Edx, Imm::Num(1)),
+        jump(Cond::Always, "out_write"),
+        label("out_wide"),
+        Op::Raw("movl %eax, %ecx", &[0x89, 0xc1]),
+        Op::Raw("shrb $6, %al", &[0xc0, 0xe8, 0x06]),
+        Op::Raw("orb $0xc0, %al", &[0x0c, 0xc0]),
+        Op::Store(BUF),
+        Op::Raw("movl %ecx, %eax", &[0x89, 0xc8]),
+        Op::Raw("andb $0x3f, %al", &[0x24, 0x3f]),
+        Op::Raw("orb $0x80, %al", &[0x0c, 0x80]),
+        Op::Store(BUF + 1),
+        Op::MovImm(Reg::Edx, Imm::Num(2)),
+        label("out_write"),
+        Op::MovImm(Reg::Eax, Imm::Num(1)),
+        Op::MovImm(Reg::Edi, Imm::Num(1)),
+        Op::Lea(Reg::Esi, BUF),
+        SYSCALL,
+        RET,
+        // num: writes eax as a number to the file in edi
+        label("num"),
+        Op::Lea(Reg::Esi, BUF_END),
+        label("num_digit"),
+        Op::Raw("decq %rsi", &[0x48, 0xff, 0xce]),
+        Op::Raw("xorl %edx, %edx", &[0x31, 0xd2]),
+        Op::MovImm(Reg::Ecx, Imm::Num(10)),
+        Op::Raw("divl %ecx", &[0xf7, 0xf1]),
+        Op::Raw("addb $48, %dl", &[0x80, 0xc2, 0x30]),
+        Op::Raw("movb %dl, (%rsi)", &[0x88, 0x16]),
+        Op::Raw("testl %eax, %eax", &[0x85, 0xc0]),
+        jump(Cond::Ne, "num_digit"),
+        Op::Lea(Reg::Edx, BUF_END),
+        Op::Raw("subq %rsi, %rdx", &[0x48, 0x29, 0xf2]),
+        Op::MovImm(Reg::Eax, Imm::Num(1)),
+        SYSCALL,
+        RET,
+        // getc: one byte from stdin into eax, or -1 at the end
+        label("getc"),
+        Op::Raw("xorl %eax, %eax", &[0x31, 0xc0]),
+        Op::Raw("xorl %edi, %edi", &[0x31, 0xff]),
+        Op::Lea(Reg::Esi, BUF),
+        Op::MovImm(Reg::Edx, Imm::Num(1)),
+        SYSCALL,
+        Op::Raw("testl %eax, %eax", &[0x85, 0xc0]),
+        jump(Cond::Le, "getc_eof"),
+        Op::Load(Reg::Eax, BUF),
+        RET,
+        label("getc_eof"),
+        Op::MovImm(Reg::Eax, Imm::Num(u32::MAX)),
+        RET,
+        // cin
+        label("cin"),
+        Op::Call("getc".to_owned()),
+        Op::CmpEax(u32::MAX),
+        jump(Cond::E, "cin_eof"),
+        RET,
+        label("cin_eof"),
+    ];
+    ops.extend(fail_with("msg_eof", MSG_EOF));
+
+    // nin: a whole line, which has to be a number from 0 to 255 once the
+    // whitespace is trimmed off
+    ops.extend([
+        label("nin"),
+        Op::Raw("xorl %ebp, %ebp", &[0x31, 0xed]),
+        Op::StoreImm(NIN_DIGITS, 0),
+        Op::StoreImm(NIN_OK, 1),
+        label("nin_lead"),
+        Op::Call("getc".to_owned()),
+    ]);
+    ops.extend(if_space("nin_lead"));
+    ops.extend([
+        Op::CmpEax(b'+' as u32),
+        jump(Cond::Ne, "nin_digit"),
+        Op::Call("getc".to_owned()),
+        label("nin_digit"),
+        Op::CmpEax(b'0' as u32),
+        jump(Cond::B, "nin_trail"),
+        Op::CmpEax(b'9' as u32),
+        jump(Cond::A, "nin_trail"),
+        Op::StoreImm(NIN_DIGITS, 1),
+        Op::Raw("imull $10, %ebp, %ebp", &[0x6b, 0xed, 0x0a]),
+        Op::Raw("subl $48, %eax", &[0x83, 0xe8, 0x30]),
+        Op::Raw("addl %eax, %ebp", &[0x01, 0xc5]),
+        Op::Raw("cmpl $255, %ebp", &[0x81, 0xfd, 0xff, 0x00, 0x00, 0x00]),
+        jump(Cond::Be, "nin_next"),
+        // Too big, and keep it from overflowing
+        Op::StoreImm(NIN_OK, 0),
+        Op::MovImm(Reg::Ebp, Imm::Num(256)),
+        label("nin_next"),
+        Op::Call("getc".to_owned()),
+        jump(Cond::Always, "nin_digit"),
+        label("nin_trail_next"),
+        Op::Call("getc".to_owned()),
+        label("nin_trail"),
+    ]);
+    ops.extend(if_space("nin_trail_next"));
+    ops.extend([
+        Op::CmpEax(b'\n' as u32),
+        jump(Cond::E, "nin_done"),
+        Op::CmpEax(u32::MAX),
+        jump(Cond::E, "nin_done"),
+        // Junk, eat the rest of the line
+        Op::StoreImm(NIN_OK, 0),
+        label("nin_junk"),
+        Op::Call("getc".to_owned()),
+        Op::CmpEax(b'\n' as u32),
+        jump(Cond::E, "nin_done"),
+        Op::CmpEax(u32::MAX),
+        jump(Cond::Ne, "nin_junk"),
+        label("nin_done"),
+        Op::CmpMem(NIN_OK, 0),
+        jump(Cond::E, "nin_bad"),
+        Op::CmpMem(NIN_DIGITS, 0),
+        jump(Cond::E, "nin_bad"),
+        Op::Raw("movl %ebp, %eax", &[0x89, 0xe8]),
+        RET,
+        label("nin_bad"),
+    ]);
+    ops.extend(fail_with("msg_nin", MSG_NIN));
+
+    ops.extend([
+        // fail: writes the message at esi, edx long, and exits with 1
+        label("fail"),
+        Op::MovImm(Reg::Eax, Imm::Num(1)),
+        Op::MovImm(Reg::Edi, Imm::Num(2)),
+        SYSCALL,
+        label("exit1"),
+        Op::MovImm(Reg::Eax, Imm::Num(60)),
+        Op::MovImm(Reg::Edi, Imm::Num(1)),
+        SYSCALL,
+        // oob: the IP went somewhere there's no instruction
+        label("oob"),
+        Op::MovImm(Reg::Esi, Imm::Sym("msg_oob".to_owned())),
+        Op::MovImm(Reg::Edx, Imm::Num(MSG_OOB.len() as u32)),
+        Op::MovImm(Reg::Eax, Imm::Num(1)),
+        Op::MovImm(Reg::Edi, Imm::Num(2)),
+        SYSCALL,
+        Op::Load(Reg::Eax, 0),
+        Op::MovImm(Reg::Edi, Imm::Num(2)),
+        Op::Call("num".to_owned()),
+        Op::MovImm(Reg::Esi, Imm::Sym("msg_end".to_owned())),
+        Op::MovImm(Reg::Edx, Imm::Num(MSG_END.len() as u32)),
+        jump(Cond::Always, "fail"),
+    ]);
+
+    ops
+}
+
+/// Sets the IP to the next instruction, like the interpreter does after
+/// everything that isn't a jump.
+fn next(idx: usize) -> Op {
+    Op::StoreImm(0, (idx + 1) as u8)
+}
+
+/// Saves eax to the target. Writing to `m0` moves the IP, so that goes
+/// through the dispatcher.
+fn store(ops: &mut Vec<Op>, idx: usize, tgt: &Value) {
+    match tgt {
+        Value::Memory { addr: 0 } => ops.extend([
+            Op::Store(0),
+            Op::Raw("incb 0(%rbx)", &[0xfe, 0x83, 0, 0, 0, 0]),
+            jump(Cond::Always, "dispatch"),
+        ]),
+        Value::Memory { addr } => ops.extend([Op::Store(*addr as u32), next(idx)]),
+        Value::Literal { .. } => unreachable!(),
+    }
+}
+
+/// Two operands into eax and ecx, then one operation on them.
+fn binary(ops: &mut Vec<Op>, idx: usize, left: &Value, right: &Value, tgt: &Value, op: Op) {
+    ops.extend([load(Reg::Eax, left), load(Reg::Ecx, right), op]);
+    store(ops, idx, tgt);
+}
+
+fn instruction(ops: &mut Vec<Op>, idx: usize, inst: &Instruction, len: usize) {
+    match inst {
+        Instruction::Set { src, tgt } => {
+            ops.push(load(Reg::Eax, src));
+            store(ops, idx, tgt);
+        }
+        Instruction::And { left, right, tgt } => binary(
+            ops,
+            idx,
+            left,
+            right,
+            tgt,
+            Op::Raw("andb %cl, %al", &[0x20, 0xc8]),
+        ),
+        Instruction::Xor { left, right, tgt } => binary(
+            ops,
+            idx,
+            left,
+            right,
+            tgt,
+            Op::Raw("xorb %cl, %al", &[0x30, 0xc8]),
+        ),
+        Instruction::Add { left, right, tgt } => binary(
+            ops,
+            idx,
+            left,
+            right,
+            tgt,
+            Op::Raw("addb %cl, %al", &[0x00, 0xc8]),
+        ),
+        Instruction::Sub { left, right, tgt } => binary(
+            ops,
+            idx,
+            left,
+            right,
+            tgt,
+            Op::Raw("subb %cl, %al", &[0x28, 0xc8]),
+        ),
+        Instruction::Not { src, tgt } => {
+            ops.extend([
+                load(Reg::Eax, src),
+                Op::Raw("testb %al, %al", &[0x84, 0xc0]),
+                Op::Raw("sete %al", &[0x0f, 0x94, 0xc0]),
+            ]);
+            store(ops, idx, tgt);
+        }
+        Instruction::Out { src } => {
+            ops.extend([load(Reg::Eax, src), Op::Call("out".to_owned()), next(idx)]);
+        }
+        Instruction::Num { src } => ops.extend([
+            load(Reg::Eax, src),
+            Op::MovImm(Reg::Edi, Imm::Num(1)),
+            Op::Call("num".to_owned()),
+            next(idx),
+        ]),
+        Instruction::Cin { tgt } => {
+            ops.push(Op::Call("cin".to_owned()));
+            store(ops, idx, tgt);
+        }
+        Instruction::Nin { tgt } => {
+            ops.push(Op::Call("nin".to_owned()));
+            store(ops, idx, tgt);
+        }
+        Instruction::Bak { count, check } | Instruction::Fwd { count, check } => {
+            let skip = format!("i{}_skip", idx);
+            match check {
+                Value::Literal { val: 0 } => {}
+                Value::Literal { .. } => {
+                    ops.push(next(idx));
+                    return;
+                }
+                Value::Memory { addr } => {
+                    ops.extend([Op::CmpMem(*addr as u32, 0), jump(Cond::Ne, &skip)]);
+                }
+            }
+
+            match (inst.jump_target(idx), count) {
+                (Some(target), _) => {
+                    ops.push(Op::StoreImm(0, target as u8));
+                    if target < len {
+                        ops.push(jump(Cond::Always, &format!("i{}", target)));
+                    } else {
+                        ops.push(jump(Cond::Always, "dispatch"));
+                    }
+                }
+                (None, Value::Memory { addr }) => {
+                    ops.push(Op::Load(Reg::Ecx, *addr as u32));
+                    ops.push(match inst {
+                        Instruction::Bak { .. } => {
+                            Op::Raw("subb %cl, 0(%rbx)", &[0x28, 0x8b, 0, 0, 0, 0])
+                        }
+                        _ => Op::Raw("addb %cl, 0(%rbx)", &[0x00, 0x8b, 0, 0, 0, 0]),
+                    });
+                    ops.push(jump(Cond::Always, "dispatch"));
+                }
+                (None, Value::Literal { .. }) => unreachable!(),
+            }
+
+            ops.extend([label(&skip), next(idx)]);
+        }
+        Instruction::Bye { code } => ops.extend([
+            load(Reg::Eax, code),
+            Op::Raw("movl %eax, %edi", &[0x89, 0xc7]),
+            Op::MovImm(Reg::Eax, Imm::Num(60)),
+            SYSCALL,
+        ]),
+        Instruction::Nop => ops.push(next(idx)),
+    }
+}
+
+/// The code and the read-only data that goes with it.
+fn lower(program: &Program) -> (Vec<Op>, Vec<Op>) {
+    let instructions = &program.instructions;
+    let mut code = vec![
+        label("_start"),
+        Op::MovImm(Reg::Ebx, Imm::Sym("mem".to_owned())),
+    ];
+    code.extend(
+        program
+            .data
+            .iter()
+            .map(|(addr, val)| Op::StoreImm(*addr as u32, *val)),
+    );
+
+    // Idk what the user is doing but whatever
+    if instructions.is_empty() {
+        code.extend([
+            Op::Raw("xorl %edi, %edi", &[0x31, 0xff]),
+            Op::MovImm(Reg::Eax, Imm::Num(60)),
+            SYSCALL,
+        ]);
+        return (code, vec![]);
+    }
+
+    let len = instructions.len().min(256);
+    for (idx, inst) in instructions.iter().enumerate().take(256) {
+        code.push(label(&format!("i{}", idx)));
+        instruction(&mut code, idx, inst, len);
+    }
+
+    // Falling off the end lands here too
+    code.extend([
+        label("dispatch"),
+        Op::Load(Reg::Eax, 0),
+        Op::CmpEax(len as u32),
+        jump(Cond::Ae, "oob"),
+        Op::JumpTable("table".to_owned()),
+    ]);
+    code.extend(runtime());
+
+    let mut data = vec![label("table")];
+    data.extend((0..len).map(|idx| Op::Quad(format!("i{}", idx))));
+    for (name, text) in [
+        ("msg_eof", MSG_EOF),
+        ("msg_nin", MSG_NIN),
+        ("msg_oob", MSG_OOB),
+        ("msg_end", MSG_END),
+    ] {
+        data.extend([label(name), Op::Ascii(text.to_owned())]);
+    }
+
+    (code, data)
+}
+
+/// GNU as source for a static Linux binary. Build it with
+/// `as out.s -o out.o && ld out.o -o out`.
+pub fn assembly(program: &Program) -> String {
+    let (code, data) = lower(program);
+    let mut out = String::new();
+
+    out.push_str("    .globl _start\n    .text\n");
+    for op in &code {
+        writeln!(out, "{}", op.text()).expect("Writing to a string");
+    }
+    out.push_str("\n    .section .rodata\n    .align 8\n");
+    for op in &data {
+        writeln!(out, "{}", op.text()).expect("Writing to a string");
+    }
+    writeln!(out, "\n    .bss\nmem:\n    .zero {}", BSS_SIZE).expect("Writing to a string");
+
+    out
+}
+
+/// The same thing, but straight to a static ELF executable.
+pub fn elf(program: &Program) -> Vec<u8> {
+    const EHDR_SIZE: u32 = 64;
+    const PHDR_SIZE: u32 = 56;
+    let code_start = LOAD_ADDR + EHDR_SIZE + 2 * PHDR_SIZE;
+
+    let (code, data) = lower(program);
+
+    // The table wants to be lined up on 8 bytes
+    let code_size: u32 = code.iter().map(Op::size).sum();
+    let padding = (8 - (code_start + code_size) % 8) % 8;
+
+    // Where everything goes
+    let mut labels = HashMap::new();
+    labels.insert("mem".to_owned(), BSS_ADDR);
+    let mut here = code_start;
+    for op in &code {
+        if let Op::Label(name) = op {
+            labels.insert(name.clone(), here);
+        }
+        here += op.size();
+    }
+    here += padding;
+    for op in &data {
+        if let Op::Label(name) = op {
+            labels.insert(name.clone(), here);
+        }
+        here += op.size();
+    }
+    let file_size = here - LOAD_ADDR;
+
+    let mut out = Vec::new();
+
+    // ELF header
+    out.extend(b"\x7fELF");
+    out.extend([2, 1, 1, 0]); // 64-bit, little endian, version 1, System V
+    out.extend([0; 8]);
+    out.extend(2u16.to_le_bytes()); // executable
+    out.extend(0x3eu16.to_le_bytes()); // x86-64
+    out.extend(1u32.to_le_bytes());
+    out.extend((labels["_start"] as u64).to_le_bytes());
+    out.extend((EHDR_SIZE as u64).to_le_bytes()); // program headers
+    out.extend(0u64.to_le_bytes()); // no section headers
+    out.extend(0u32.to_le_bytes());
+    out.extend((EHDR_SIZE as u16).to_le_bytes());
+    out.extend((PHDR_SIZE as u16).to_le_bytes());
+    out.extend(2u16.to_le_bytes());
+    out.extend([0; 6]);
+
+    // Code and data, read and execute
+    out.extend(1u32.to_le_bytes());
+    out.extend(5u32.to_le_bytes());
+    out.extend(0u64.to_le_bytes());
+    out.extend((LOAD_ADDR as u64).to_le_bytes());
+    out.extend((LOAD_ADDR as u64).to_le_bytes());
+    out.extend((file_size as u64).to_le_bytes());
+    out.extend((file_size as u64).to_le_bytes());
+    out.extend(0x1000u64.to_le_bytes());
+
+    // Memory, read and write, nothing in the file
+    out.extend(1u32.to_le_bytes());
+    out.extend(6u32.to_le_bytes());
+    out.extend(0u64.to_le_bytes());
+    out.extend((BSS_ADDR as u64).to_le_bytes());
+    out.extend((BSS_ADDR as u64).to_le_bytes());
+    out.extend(0u64.to_le_bytes());
+    out.extend((BSS_SIZE as u64).to_le_bytes());
+    out.extend(0x1000u64.to_le_bytes());
+
+    let mut here = code_start;
+    for op in &code {
+        op.encode(&mut out, here, &labels);
+        here += op.size();
+    }
+    out.extend(std::iter::repeat_n(0, padding as usize));
+    here += padding;
+    for op in &data {
+        op.encode(&mut out, here, &labels);
+        here += op.size();
+    }
+
+    out
+}
+
+/// Builds the program, with `as` and `ld` if `assemble` or straight from
+/// `elf` if not, and runs it against the interpreter on every input. Gives
+/// back how many inputs were actually compared.
+pub fn test(program: &Program, assemble: bool, inputs: &[Vec<u8>]) -> Result<usize, String> {
+    let dir = super::scratch_dir()?;
+    let binary = dir.join("program");
+
+    if assemble {
+        std::fs::write(dir.join("program.s"), assembly(program))
+            .map_err(|_| "Could not write program.s".to_owned())?;
+        for (tool, args) in [
+            ("as", ["program.s", "-o", "program.o"]),
+            ("ld", ["program.o", "-o", "program"]),
+        ] {
+            let built = Command::new(tool)
+                .args(args)
+                .current_dir(&dir)
+                .status()
+                .map_err(|_| format!("Could not run {}", tool))?;
+            if !built.success() {
+                return Err("The generated code didn't assemble".to_owned());
+            }
+        }
+    } else {
+        std::fs::write(&binary, elf(program)).map_err(|_| "Could not write program")?;
+        std::fs::set_permissions(&binary, std::fs::Permissions::from_mode(0o755))
+            .map_err(|_| "Could not make program executable")?;
+    }
+
+    let compared = super::compare(program, inputs, || Command::new(&binary))?;
+
+    let _ = std::fs::remove_dir_all(&dir);
+    Ok(compared)
+}
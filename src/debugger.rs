@@ -0,0 +1,173 @@
+use crate::machine::{Machine, MachineConfig, Status, MEMORY_SIZE};
+use crate::program::Program;
+use crate::source::strip_comment;
+use std::io::Write;
+
+/// How many steps the debugger remembers unless told otherwise.
+pub const DEFAULT_HISTORY: usize = 100_000;
+
+const HELP: &str = "\
+s [n]      step forward n instructions (default 1)
+b [n]      step back n instructions (default 1)
+c          continue until bye
+lw mN      run back to the last write of mN
+goto N     go to step N, backwards or forwards
+m A[..B]   show memory from A up to (not including) B
+i          show where we are
+h          this
+q          quit";
+
+/// Parses `5`, `m5`, `5..10` and `m5..m10` into a range of addresses.
+pub(crate) fn parse_range(arg: &str) -> Option<(usize, usize)> {
+    let addr = |s: &str| s.trim_start_matches('m').parse::<usize>().ok();
+
+    let (start, end) = match arg.split_once("..") {
+        Some((start, end)) => (addr(start)?, addr(end)?),
+        None => {
+            let start = addr(arg)?;
+            (start, start + 1)
+        }
+    };
+
+    if start >= end || end > MEMORY_SIZE {
+        return None;
+    }
+    Some((start, end))
+}
+
+/// The instruction under the IP, and the line it came from if we know.
+fn show_position(machine: &Machine, program: &Program) {
+    match machine.current(&program.instructions) {
+        Ok(inst) => {
+            print!("[step {}] {:>3}: {}", machine.steps(), machine.ip(), inst);
+            match program.source(machine.ip() as usize) {
+                Some(at) => println!(
+                    "    {}: {}{}",
+                    at.location(),
+                    strip_comment(&at.text),
+                    at.calls()
+                ),
+                None => println!(),
+            }
+        }
+        Err(msg) => println!("[step {}] {}", machine.steps(), msg),
+    }
+}
+
+pub(crate) fn show_memory(machine: &Machine, start: usize, end: usize) {
+    for (offset, chunk) in machine.memory[start..end].chunks(8).enumerate() {
+        print!("m{:<3}", start + offset * 8);
+        for cell in chunk {
+            print!(" {:>3}", cell.0);
+        }
+        println!();
+    }
+}
+
+/// Steps forward `count` times, stopping early on `bye` or an error.
+fn forward(machine: &mut Machine, program: &Program, count: u64) -> Option<u8> {
+    for _ in 0..count {
+        match machine.step(&program.instructions) {
+            Ok(Status::Running) => {}
+            Ok(Status::Exited(code)) => return Some(code),
+            Err(msg) => {
+                println!("\n{}", program.error_at(machine.ip() as usize, msg));
+                return None;
+            }
+        }
+    }
+    None
+}
+
+/// Interactive debugger that can go backwards as well as forwards. The
+/// program's own input shares stdin with the commands, so good luck.
+pub fn run(program: &Program, history_cap: usize) -> Result<(), String> {
+    let mut machine = Machine::new(MachineConfig {
+        history_cap,
+        ..Default::default()
+    });
+    machine.memory = program.memory();
+
+    println!("Type h for help");
+    show_position(&machine, program);
+
+    loop {
+        print!("(sick) ");
+        std::io::stdout().flush().expect("IO error");
+
+        let mut line = String::new();
+        if std::io::stdin().read_line(&mut line).expect("IO error") == 0 {
+            return Ok(());
+        }
+
+        let mut words = line.split_whitespace();
+        let cmd = match words.next() {
+            Some(cmd) => cmd,
+            None => continue,
+        };
+        let arg = words.next();
+        let count = match arg.map(str::parse::<u64>) {
+            Some(Ok(n)) => n,
+            _ => 1,
+        };
+
+        match cmd {
+            "s" => {
+                if let Some(code) = forward(&mut machine, program, count) {
+                    println!("\nbye {}", code);
+                }
+            }
+            "b" => {
+                for _ in 0..count {
+                    if !machine.step_back() {
+                        println!("Out of history");
+                        break;
+                    }
+                }
+            }
+            "c" => {
+                if let Some(code) = forward(&mut machine, program, u64::MAX) {
+                    println!("\nbye {}", code);
+                }
+            }
+            "lw" => match arg.and_then(parse_range) {
+                Some((addr, _)) => {
+                    if let Err(msg) = machine.back_to_write(addr as u8) {
+                        println!("{}", msg);
+                    }
+                }
+                None => println!("Usage: lw mN"),
+            },
+            "goto" => match arg.map(str::parse::<u64>) {
+                Some(Ok(step)) if step > machine.steps() => {
+                    let count = step - machine.steps();
+                    if let Some(code) = forward(&mut machine, program, count) {
+                        println!("\nbye {}", code);
+                    }
+                }
+                Some(Ok(step)) => {
+                    if let Err(msg) = machine.rewind_to(step) {
+                        println!("{}", msg);
+                    }
+                }
+                _ => println!("Usage: goto N"),
+            },
+            "m" => match arg.map_or(Some((0, MEMORY_SIZE)), parse_range) {
+                Some((start, end)) => show_memory(&machine, start, end),
+                None => println!("Usage: m A[..B]"),
+            },
+            "i" => {}
+            "h" => {
+                println!("{}", HELP);
+                continue;
+            }
+            "q" => return Ok(()),
+            _ => {
+                println!("Unknown command `{}`, type h for help", cmd);
+                continue;
+            }
+        }
+
+        show_position(&machine, program);
+    }
+}
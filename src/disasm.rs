@@ -0,0 +1,176 @@
+use crate::parser::{Instruction, Value};
+use crate::program::Program;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Literal operands worth showing as characters, leaving out jump counts.
+fn printable_literals(inst: &Instruction) -> Vec<char> {
+    let vals = match inst {
+        Instruction::Bak { check, .. } | Instruction::Fwd { check, .. } => vec![check],
+        _ => inst.operands(),
+    };
+
+    vals.into_iter()
+        .filter_map(|val| match val {
+            Value::Literal { val } if (32..127).contains(val) => Some(*val as char),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Turns a program back into source. Each line gets its index in a comment,
+/// and with `labels` every statically known jump target gets a `# Ln:` line
+/// above it so it's easy to see where things go. Any data goes at the top.
+pub fn disassemble(program: &Program, labels: bool) -> String {
+    let instructions = &program.instructions;
+    // Numbered in the order they show up
+    let mut names = BTreeMap::new();
+    if labels {
+        let mut targets: Vec<usize> = instructions
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, inst)| inst.jump_target(idx))
+            .filter(|tgt| *tgt < instructions.len())
+            .collect();
+        targets.sort_unstable();
+        targets.dedup();
+        for (n, tgt) in targets.into_iter().enumerate() {
+            names.insert(tgt, format!("L{}", n));
+        }
+    }
+
+    let texts: Vec<String> = instructions.iter().map(|inst| inst.to_string()).collect();
+    let width = texts.iter().map(String::len).max().unwrap_or(0);
+
+    let mut out = String::new();
+    for line in program.data_directives() {
+        writeln!(out, "{}", line).expect("Writing to a string");
+    }
+    if !program.data.is_empty() {
+        out.push('\n');
+    }
+
+    for (idx, (inst, text)) in instructions.iter().zip(&texts).enumerate() {
+        if let Some(name) = names.get(&idx) {
+            writeln!(out, "# {}:", name).expect("Writing to a string");
+        }
+
+        write!(out, "{:<width$}  # {:>3}", text, idx, width = width).expect("Writing to a string");
+
+        let chars = printable_literals(inst);
+        if !chars.is_empty() {
+            let chars: Vec<String> = chars.iter().map(|c| format!("{:?}", c)).collect();
+            write!(out, "  {}", chars.join(" ")).expect("Writing to a string");
+        }
+
+        if labels {
+            match inst.jump_target(idx) {
+                Some(tgt) if tgt < instructions.len() => {
+                    write!(out, "  -> {}", names[&tgt]).expect("Writing to a string")
+                }
+                Some(tgt) => {
+                    write!(out, "  -> {}, past the end", tgt).expect("Writing to a string")
+                }
+                None => {}
+            }
+        }
+
+        out.push('\n');
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::bytecode::{decode, encode};
+    use crate::parser::get_program;
+    use crate::source::{read_str, SourceConfig};
+
+    fn assemble_str(code: &str) -> Program {
+        assemble(read_str("test.sick", code, &SourceConfig::default()).unwrap()).unwrap()
+    }
+
+    const LOOP: &str = "\
+.data m20 = \"hi\"
+set 65 -> m1
+loop:
+out m1
+add m1, 1 -> m1
+sub m1, 68 -> m2
+fwd done, m2
+bak loop, 0
+done:
+bye 0
+";
+
+    #[test]
+    fn round_trip() {
+        let mut programs = vec![assemble_str(LOOP)];
+        for file in [
+            "example.sick",
+            "sick-examples/equality.sick",
+            "sick-examples/stuff.sick",
+        ] {
+            programs.push(get_program(file, &SourceConfig::default()).unwrap());
+        }
+
+        for program in programs {
+            let program = decode(&encode(&program)).unwrap();
+            for labels in [false, true] {
+                let again = assemble_str(&disassemble(&program, labels));
+                assert_eq!(again.instructions, program.instructions);
+                assert_eq!(again.data, program.data);
+            }
+        }
+    }
+
+    #[test]
+    fn labels() {
+        assert_eq!(
+            disassemble(&assemble_str(LOOP), true),
+            "\
+.data m20 = 104, 105
+
+set 65 -> m1      #   0  'A'
+# L0:
+out m1            #   1
+add m1, 1 -> m1   #   2
+sub m1, 68 -> m2  #   3  'D'
+fwd 2, m2         #   4  -> L1
+bak 4, 0          #   5  -> L0
+# L1:
+bye 0             #   6
+"
+        );
+    }
+
+    #[test]
+    fn jump_past_the_end() {
+        let program = assemble_str("fwd 5, m1\nbye 0\n");
+        assert_eq!(
+            disassemble(&program, true),
+            "fwd 5, m1  #   0  -> 5, past the end\nbye 0      #   1\n"
+        );
+        // No labels, no arrows
+        assert_eq!(
+            disassemble(&program, false),
+            "fwd 5, m1  #   0\nbye 0      #   1\n"
+        );
+    }
+
+    #[test]
+    fn ascii_literals() {
+        let program = assemble_str(
+            "out 104\nadd 72, 105 -> m1\nset 200 -> m2\nset 10 -> m3\nfwd 65, 66\nnot m1 -> m2\n",
+        );
+        let lines: Vec<String> = disassemble(&program, false)
+            .lines()
+            .map(|line| line.split('#').nth(1).unwrap().trim().to_owned())
+            .collect();
+        // Only printable ones, and never the count of a jump
+        assert_eq!(lines, ["0  'h'", "1  'H' 'i'", "2", "3", "4  'B'", "5"]);
+    }
+}
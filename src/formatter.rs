@@ -0,0 +1,185 @@
+use crate::parser::{parse_lines, Line};
+
+/// Knobs for the formatter.
+#[derive(Debug, Clone, Default)]
+pub struct FormatConfig {
+    /// How many spaces to put in front of every line.
+    pub indent: usize,
+}
+
+/// How far in macro and block bodies go for each level, on top of the indent.
+const BLOCK_INDENT: &str = "    ";
+
+/// How a line changes how deep in blocks we are: (before it, after it).
+fn depth_change(text: &str) -> (isize, isize) {
+    match text.split_whitespace().next() {
+        Some(".macro" | ".while" | ".ifz" | ".ifnz" | ".if" | ".rep") => (0, 1),
+        Some(".end" | ".endm" | ".endif" | ".endr") => (-1, 0),
+        // Sits with the `.ifz` it belongs to
+        Some(".else") => (-1, 1),
+        _ => (0, 0),
+    }
+}
+
+/// Prints the lines back out the canonical way. Runs of blank lines become one,
+/// and trailing comments line up with the others in the same paragraph.
+pub fn format_lines(lines: &[Line], config: &FormatConfig) -> String {
+    let indent = " ".repeat(config.indent);
+    let mut out = String::new();
+    let mut depth = 0usize;
+
+    // Paragraphs are separated by blank lines
+    let paragraphs = lines
+        .split(|line| matches!(line, Line::Blank))
+        .filter(|para| !para.is_empty());
+
+    for (n, para) in paragraphs.enumerate() {
+        if n > 0 {
+            out.push('\n');
+        }
+
+        // How deep in macros and blocks each line is, which is how far it gets
+        // indented. Labels stay where they are so they stand out.
+        let nested: Vec<usize> = para
+            .iter()
+            .map(|line| match line {
+                Line::Asm(text, _) => {
+                    let (before, after) = depth_change(text);
+                    depth = depth.saturating_add_signed(before);
+                    let this = if text.ends_with(':') { 0 } else { depth };
+                    depth = depth.saturating_add_signed(after);
+                    this
+                }
+                _ => depth,
+            })
+            .collect();
+
+        let texts: Vec<Option<String>> = para
+            .iter()
+            .zip(&nested)
+            .map(|(line, nested)| {
+                let pad = BLOCK_INDENT.repeat(*nested);
+                match line {
+                    Line::Instruction(inst, _) => Some(format!("{}{}", pad, inst)),
+                    Line::Asm(text, _) => Some(format!("{}{}", pad, text)),
+                    _ => None,
+                }
+            })
+            .collect();
+
+        // Only the lines with a trailing comment get a say in where they go
+        let width = para
+            .iter()
+            .zip(&texts)
+            .filter_map(|(line, text)| match (line, text) {
+                (Line::Instruction(_, Some(_)) | Line::Asm(_, Some(_)), Some(text)) => {
+                    Some(text.len())
+                }
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+
+        for ((line, text), nested) in para.iter().zip(&texts).zip(&nested) {
+            out.push_str(&indent);
+            match (line, text) {
+                (Line::Comment(comment), _) => {
+                    out.push_str(&BLOCK_INDENT.repeat(*nested));
+                    out.push('#');
+                    out.push_str(comment);
+                }
+                (Line::Instruction(_, Some(comment)) | Line::Asm(_, Some(comment)), Some(text)) => {
+                    out.push_str(&format!("{:<width$}  #{}", text, comment, width = width));
+                }
+                (_, Some(text)) => out.push_str(text),
+                _ => unreachable!(),
+            }
+            out.push('\n');
+        }
+    }
+
+    out
+}
+
+pub fn format_source(content: &str, config: &FormatConfig) -> Result<String, String> {
+    Ok(format_lines(&parse_lines(content)?, config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const MESSY: &str = "\
+set   1->m1 # one
+\tadd m1,2   ->  m2    # two and a bit
+
+
+
+loop:
+out m1
+  # alone
+.ifz m1
+out 65 # a
+.else
+  nop
+.end
+bak loop, 0#back
+";
+
+    const TIDY: &str = "\
+set 1 -> m1      # one
+add m1, 2 -> m2  # two and a bit
+
+loop:
+out m1
+# alone
+.ifz m1
+    out 65   # a
+.else
+    nop
+.end
+bak loop, 0  #back
+";
+
+    #[test]
+    fn mixed_spacing() {
+        assert_eq!(
+            format_source(MESSY, &FormatConfig::default()),
+            Ok(TIDY.to_owned())
+        );
+    }
+
+    #[test]
+    fn indent() {
+        let formatted = format_source(MESSY, &FormatConfig { indent: 2 }).unwrap();
+        let expected: String = TIDY
+            .lines()
+            .map(|line| match line {
+                "" => "\n".to_owned(),
+                _ => format!("  {}\n", line),
+            })
+            .collect();
+        assert_eq!(formatted, expected);
+    }
+
+    #[test]
+    fn idempotent() {
+        let mut sources = vec![MESSY.to_owned()];
+        for dir in ["sick-examples", "src/std"] {
+            for entry in fs::read_dir(dir).expect("Run from the repo") {
+                let path = entry.unwrap().path();
+                if path.extension().is_some_and(|ext| ext == "sick") {
+                    sources.push(fs::read_to_string(path).unwrap());
+                }
+            }
+        }
+
+        for config in [FormatConfig::default(), FormatConfig { indent: 4 }] {
+            for source in &sources {
+                let once = format_source(source, &config).unwrap();
+                assert_eq!(format_source(&once, &config), Ok(once.clone()));
+            }
+        }
+    }
+}
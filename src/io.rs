@@ -0,0 +1,95 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, Read, Write};
+
+/// Where `out`, `num`, `cin` and `nin` go.
+pub trait SickIo {
+    fn out(&mut self, val: u8);
+    fn num(&mut self, val: u8);
+    fn cin(&mut self) -> Result<u8, String>;
+    fn nin(&mut self) -> Result<u8, String>;
+}
+
+/// Turns a line into a number the way `nin` does.
+pub fn parse_nin(line: &str) -> Result<u8, String> {
+    line.trim()
+        .parse::<u8>()
+        .map_err(|_| "Invalid unsigned 8-bit integer".to_owned())
+}
+
+/// The terminal.
+#[derive(Debug, Default)]
+pub struct StdIo;
+
+impl SickIo for StdIo {
+    fn out(&mut self, val: u8) {
+        print!("{}", val as char);
+        std::io::stdout().flush().expect("IO errror");
+    }
+
+    fn num(&mut self, val: u8) {
+        print!("{}", val);
+        std::io::stdout().flush().expect("IO errror");
+    }
+
+    fn cin(&mut self) -> Result<u8, String> {
+        match std::io::stdin().lock().bytes().next() {
+            Some(val) => Ok(val.expect("IO error")),
+            None => Err("EOF while reading input".to_owned()),
+        }
+    }
+
+    fn nin(&mut self) -> Result<u8, String> {
+        let mut buf = String::new();
+        std::io::stdin()
+            .lock()
+            .read_line(&mut buf)
+            .expect("IO error");
+        parse_nin(&buf)
+    }
+}
+
+/// Input from a buffer, output into one. Handy for tests.
+#[derive(Debug, Default, Clone)]
+pub struct BufferIo {
+    pub input: VecDeque<u8>,
+    pub output: Vec<u8>,
+}
+
+impl BufferIo {
+    pub fn new(input: &[u8]) -> Self {
+        BufferIo {
+            input: input.iter().copied().collect(),
+            output: Vec::new(),
+        }
+    }
+}
+
+impl SickIo for BufferIo {
+    fn out(&mut self, val: u8) {
+        // Same bytes as printing a char
+        let mut buf = [0; 4];
+        self.output
+            .extend((val as char).encode_utf8(&mut buf).as_bytes());
+    }
+
+    fn num(&mut self, val: u8) {
+        self.output.extend(val.to_string().as_bytes());
+    }
+
+    fn cin(&mut self) -> Result<u8, String> {
+        self.input
+            .pop_front()
+            .ok_or_else(|| "EOF while reading input".to_owned())
+    }
+
+    fn nin(&mut self) -> Result<u8, String> {
+        let mut line = Vec::new();
+        while let Some(byte) = self.input.pop_front() {
+            line.push(byte);
+            if byte == b'\n' {
+                break;
+            }
+        }
+        parse_nin(&String::from_utf8_lossy(&line))
+    }
+}
@@ -0,0 +1,26 @@
+pub mod asm;
+pub mod bf;
+pub mod bytecode;
+pub mod cfg;
+pub mod compile;
+pub mod debugger;
+pub mod disasm;
+pub mod formatter;
+pub mod io;
+pub mod link;
+pub mod lint;
+pub mod machine;
+pub mod optimize;
+pub mod parser;
+pub mod program;
+pub mod repl;
+pub mod source;
+pub mod stdlib;
+pub mod threaded;
+
+pub use io::{BufferIo, SickIo, StdIo};
+pub use machine::{Machine, MachineConfig, RunError, Status};
+pub use parser::{get_instructions, get_program, parse_instruction, Instruction, Value};
+pub use program::Program;
+pub use source::{SourceConfig, SourceLine};
+pub use threaded::Threaded;
@@ -0,0 +1,358 @@
+use crate::asm;
+use crate::bytecode::{self, crc32, encode_data, encode_instruction, Reader};
+use crate::parser::{Instruction, Value};
+use crate::program::Program;
+use crate::source::{self, SourceConfig};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+
+/*
+* Layout of a .sbo file: (all multi-byte numbers are little endian)
+* magic     4 bytes  0x7f 'S' 'B' 'O'
+* version   1 byte   VERSION
+* count     4 bytes  number of instructions
+* body      count instructions, same as in a .sbc
+* cells     1 byte   number of memory cells set by `.data`
+* data      cells pairs of address and value
+* exports   4 bytes  number of exports, then for each:
+*                    1 byte name length, the name, 4 bytes instruction index
+* imports   4 bytes  number of jumps to imported labels, then for each:
+*                    4 bytes instruction index, 1 byte name length, the name
+* checksum  4 bytes  CRC-32 of everything before it
+*/
+
+pub const MAGIC: &[u8; 4] = b"\x7fSBO";
+pub const VERSION: u8 = 1;
+
+/// A program that isn't finished yet. Jumps to labels from other objects
+/// have a count of 0 until it's linked.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Object {
+    pub program: Program,
+    /// Labels other objects can jump to, and the instruction they're in front of.
+    pub exports: Vec<(String, usize)>,
+    /// The `bak`s and `fwd`s that jump to a label from another object.
+    pub imports: Vec<(usize, String)>,
+}
+
+fn encode_name(out: &mut Vec<u8>, name: &str) -> Result<(), String> {
+    let len = u8::try_from(name.len()).map_err(|_| {
+        format!(
+            "`{}...` is {} bytes long, but names in an object can only be {}",
+            name.chars().take(16).collect::<String>(),
+            name.len(),
+            u8::MAX
+        )
+    })?;
+    out.push(len);
+    out.extend(name.as_bytes());
+    Ok(())
+}
+
+/// Errors if a name is longer than the byte its length goes in.
+pub fn encode(object: &Object) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    out.extend(MAGIC);
+    out.push(VERSION);
+    out.extend((object.program.instructions.len() as u32).to_le_bytes());
+    for inst in &object.program.instructions {
+        out.extend(encode_instruction(inst));
+    }
+    encode_data(&mut out, &object.program.data);
+
+    out.extend((object.exports.len() as u32).to_le_bytes());
+    for (name, idx) in &object.exports {
+        encode_name(&mut out, name)?;
+        out.extend((*idx as u32).to_le_bytes());
+    }
+    out.extend((object.imports.len() as u32).to_le_bytes());
+    for (idx, name) in &object.imports {
+        out.extend((*idx as u32).to_le_bytes());
+        encode_name(&mut out, name)?;
+    }
+
+    let checksum = crc32(&out);
+    out.extend(checksum.to_le_bytes());
+    Ok(out)
+}
+
+impl Reader<'_> {
+    fn u32(&mut self) -> Result<usize, String> {
+        let bytes = [self.byte()?, self.byte()?, self.byte()?, self.byte()?];
+        Ok(u32::from_le_bytes(bytes) as usize)
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let len = self.byte()? as usize;
+        let name = self
+            .bytes
+            .get(self.pos..self.pos + len)
+            .ok_or("Object ends in the middle of a name")?;
+        self.pos += len;
+        String::from_utf8(name.to_vec()).map_err(|_| "A name in the object isn't UTF-8".to_owned())
+    }
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Object, String> {
+    // Magic, version, count and checksum
+    if bytes.len() < 13 || !bytes.starts_with(MAGIC) {
+        return Err("Not a sick object file".to_owned());
+    }
+    if bytes[4] != VERSION {
+        return Err(format!(
+            "Unsupported object version {}, expected {}",
+            bytes[4], VERSION
+        ));
+    }
+
+    let (body, checksum) = bytes.split_at(bytes.len() - 4);
+    let checksum = u32::from_le_bytes(checksum.try_into().expect("Split off 4 bytes"));
+    if crc32(body) != checksum {
+        return Err("Checksum mismatch, the object file is corrupted".to_owned());
+    }
+
+    let mut reader = Reader {
+        bytes: body,
+        pos: 5,
+    };
+    let count = reader.u32()?;
+    let mut instructions = Vec::new();
+    for _ in 0..count {
+        instructions.push(reader.instruction()?);
+    }
+    let data = reader.data()?;
+
+    let mut exports = Vec::new();
+    for _ in 0..reader.u32()? {
+        let name = reader.name()?;
+        let idx = reader.u32()?;
+        // Right at the end is fine, that's wherever the next object starts
+        if idx > instructions.len() {
+            return Err(format!("`{}` is exported from past the end", name));
+        }
+        exports.push((name, idx));
+    }
+
+    let mut imports = Vec::new();
+    for _ in 0..reader.u32()? {
+        let idx = reader.u32()?;
+        let name = reader.name()?;
+        if !matches!(
+            instructions.get(idx),
+            Some(Instruction::Bak { .. } | Instruction::Fwd { .. })
+        ) {
+            return Err(format!(
+                "The jump to `{}` at instruction {} isn't a `bak` or `fwd`",
+                name, idx
+            ));
+        }
+        imports.push((idx, name));
+    }
+
+    if reader.pos != body.len() {
+        return Err(format!(
+            "{} leftover bytes after the imports",
+            body.len() - reader.pos
+        ));
+    }
+
+    Ok(Object {
+        program: Program {
+            instructions,
+            data,
+            ..Default::default()
+        },
+        exports,
+        imports,
+    })
+}
+
+/// Loads an object, or assembles one from source, going by the magic bytes.
+pub fn load_with(path: &str, config: &SourceConfig) -> Result<Object, String> {
+    let bytes = fs::read(path).map_err(|_| format!("Could not read {}", path))?;
+
+    if bytes.starts_with(MAGIC) {
+        decode(&bytes).map_err(|msg| format!("{}: {}", path, msg))
+    } else if bytes.starts_with(bytecode::MAGIC) {
+        Err(format!(
+            "{} is already linked, it needs to be an object or source",
+            path
+        ))
+    } else {
+        asm::assemble_object(source::read(path, config)?)
+    }
+}
+
+/// Puts the objects one after the other, in order, and points every jump to
+/// an imported label at wherever it ended up. The names are what to call
+/// each object in errors.
+pub fn link(objects: &[(String, Object)]) -> Result<Program, String> {
+    // Name -> (where it is in the linked program, who exported it)
+    let mut exports: HashMap<&str, (usize, &str)> = HashMap::new();
+    let mut starts = Vec::with_capacity(objects.len());
+    let mut start = 0;
+
+    for (file, object) in objects {
+        for (name, idx) in &object.exports {
+            if let Some((_, other)) = exports.get(name.as_str()) {
+                return Err(format!(
+                    "`{}` is exported by both {} and {}",
+                    name, other, file
+                ));
+            }
+            exports.insert(name, (start + idx, file));
+        }
+        starts.push(start);
+        start += object.program.instructions.len();
+    }
+
+    let mut program = Program::default();
+    // Address -> who set it
+    let mut cells: BTreeMap<u8, (u8, &str)> = BTreeMap::new();
+
+    for ((file, object), start) in objects.iter().zip(starts) {
+        for (addr, val) in &object.program.data {
+            if let Some((_, other)) = cells.get(addr) {
+                return Err(format!(
+                    "m{} is set by the data in both {} and {}",
+                    addr, other, file
+                ));
+            }
+            cells.insert(*addr, (*val, file));
+        }
+
+        let mut instructions = object.program.instructions.clone();
+        for (idx, name) in &object.imports {
+            // Point at the line if we know it, otherwise the instruction
+            let error = |msg: String| match object.program.source(*idx) {
+                Some(at) => at.error(msg),
+                None => format!("{}: instruction {}: {}", file, idx, msg),
+            };
+            let (target, _) = exports
+                .get(name.as_str())
+                .ok_or_else(|| error(format!("Nothing exports `{}`", name)))?;
+            let here = start + idx;
+
+            let (count, distance) = match &mut instructions[*idx] {
+                Instruction::Bak { count, .. } => (count, here.checked_sub(*target)),
+                Instruction::Fwd { count, .. } => (count, target.checked_sub(here)),
+                // The assembler only ever records jumps, and so does decode
+                _ => unreachable!("Imports are always on a `bak` or `fwd`"),
+            };
+            let distance = distance.ok_or_else(|| {
+                error(format!(
+                    "`{}` ends up on the wrong side of this jump, it needs a `{}`",
+                    name,
+                    if here > *target { "bak" } else { "fwd" }
+                ))
+            })?;
+            if distance > u8::MAX as usize {
+                return Err(error(format!(
+                    "`{}` ends up {} instructions away, a jump can only go {}",
+                    name,
+                    distance,
+                    u8::MAX
+                )));
+            }
+            *count = Value::Literal {
+                val: distance as u8,
+            };
+        }
+
+        program.instructions.extend(instructions);
+        program
+            .source_map
+            .extend(object.program.source_map.iter().cloned());
+    }
+
+    // Only keep the source map if every object had one
+    if program.source_map.len() != program.instructions.len() {
+        program.source_map.clear();
+    }
+    program.data = cells
+        .into_iter()
+        .map(|(addr, (val, _))| (addr, val))
+        .collect();
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(file: &str, src: &str) -> (String, Object) {
+        let lines = source::read_str(file, src, &SourceConfig::default()).unwrap();
+        (file.to_owned(), asm::assemble_object(lines).unwrap())
+    }
+
+    #[test]
+    fn round_trip() {
+        let (_, mut object) = object(
+            "a.sick",
+            ".data m5 = 1, 2\n.import far\n.export here\nhere:\nout 1\nbak far, m1\nfwd far, 0\n",
+        );
+        let decoded = decode(&encode(&object).unwrap()).unwrap();
+        // There's no source in the file
+        object.program.source_map.clear();
+        assert_eq!(decoded, object);
+    }
+
+    #[test]
+    fn name_too_long() {
+        let name = "a".repeat(256);
+        let (_, object) = object("a.sick", &format!(".export {0}\n{0}:\nbye 0\n", name));
+        let err = encode(&object).unwrap_err();
+        assert!(err.contains("is 256 bytes long"), "{}", err);
+    }
+
+    #[test]
+    fn links_both_ways() {
+        let main = object(
+            "main.sick",
+            ".import print\n.export done\nfwd print, 0\ndone:\nbye 0\n",
+        );
+        let lib = object(
+            "lib.sick",
+            ".import done\n.export print\nprint:\nout 33\nbak done, 0\n",
+        );
+        let program = link(&[main, lib]).unwrap();
+        let text: Vec<String> = program
+            .instructions
+            .iter()
+            .map(|inst| inst.to_string())
+            .collect();
+        assert_eq!(text, ["fwd 2, 0", "bye 0", "out 33", "bak 2, 0"]);
+        assert_eq!(program.source(2).unwrap().file, "lib.sick");
+    }
+
+    #[test]
+    fn duplicate_export() {
+        let a = object("a.sick", ".export x\nx:\nbye 0\n");
+        let b = object("b.sick", ".export x\nx:\nbye 1\n");
+        assert_eq!(
+            link(&[a, b]),
+            Err("`x` is exported by both a.sick and b.sick".to_owned())
+        );
+    }
+
+    #[test]
+    fn missing_import() {
+        let a = object("a.sick", ".import x\nbak x, 0\n");
+        assert_eq!(link(&[a]), Err("a.sick:2: Nothing exports `x`".to_owned()));
+    }
+
+    #[test]
+    fn jump_too_far() {
+        let a = object("a.sick", ".import x\nfwd x, 0\n");
+        let b = object(
+            "b.sick",
+            &format!("{}.export x\nx:\nbye 0\n", "nop\n".repeat(300)),
+        );
+        let err = link(&[a, b]).unwrap_err();
+        assert!(
+            err.starts_with("a.sick:2: `x` ends up 301 instructions away"),
+            "{}",
+            err
+        );
+    }
+}
@@ -0,0 +1,239 @@
+use crate::machine::MEMORY_SIZE;
+use crate::parser::{Instruction, Value};
+use crate::program::Program;
+use crate::source::{split_comment, SourceLine};
+use crate::stdlib;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    /// The line the instruction came from, and the macro calls that got it
+    /// there if any.
+    pub at: SourceLine,
+    pub lint: &'static str,
+    pub message: String,
+}
+
+/// Pulls the names out of a `lint: allow(a, b)` comment.
+fn allowed(comment: &str) -> Vec<&str> {
+    comment
+        .trim()
+        .strip_prefix("lint:")
+        .map(str::trim)
+        .and_then(|rest| rest.strip_prefix("allow("))
+        .and_then(|rest| rest.strip_suffix(')'))
+        .map(|names| names.split(',').map(str::trim).collect())
+        .unwrap_or_default()
+}
+
+/// Which instructions can actually run, following every jump we can figure
+/// out. If something jumps somewhere we can't know, we give up and say
+/// everything can run.
+fn reachable(instructions: &[Instruction]) -> Vec<bool> {
+    let mut seen = vec![false; instructions.len()];
+    let mut todo = vec![0];
+
+    while let Some(idx) = todo.pop() {
+        if idx >= instructions.len() || seen[idx] {
+            continue;
+        }
+        seen[idx] = true;
+
+        let inst = &instructions[idx];
+        if inst.write() == Some(0) {
+            return vec![true; instructions.len()];
+        }
+
+        match inst {
+            Instruction::Bye { .. } => {}
+            Instruction::Bak { check, .. } | Instruction::Fwd { check, .. } => {
+                let target = match inst.jump_target(idx) {
+                    Some(target) => target,
+                    None => return vec![true; instructions.len()],
+                };
+                match check {
+                    Value::Literal { val: 0 } => todo.push(target),
+                    Value::Literal { .. } => todo.push(idx + 1),
+                    Value::Memory { .. } => todo.extend([target, idx + 1]),
+                }
+            }
+            _ => todo.push(idx + 1),
+        }
+    }
+
+    seen
+}
+
+/// The lines of a file, so comments above an instruction can be found.
+/// Library files come from the library.
+fn file_lines(file: &str) -> Vec<String> {
+    let content = match file.strip_prefix(stdlib::PREFIX) {
+        Some(lib) => stdlib::get(lib).map(str::to_owned),
+        None => fs::read_to_string(file).ok(),
+    };
+    content
+        .unwrap_or_default()
+        .lines()
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Whether a warning has been allowed on its line or the one above. For code
+/// from a macro, that's any of the lines that called it too.
+fn is_allowed(warning: &Warning, files: &mut HashMap<String, Vec<String>>) -> bool {
+    let mut at = Some(&warning.at);
+    while let Some(here) = at {
+        let own = split_comment(&here.text).1.map(allowed).unwrap_or_default();
+        let lines = files
+            .entry(here.file.clone())
+            .or_insert_with(|| file_lines(&here.file));
+        let above = here
+            .line
+            .checked_sub(2)
+            .and_then(|idx| lines.get(idx))
+            .and_then(|line| line.trim().strip_prefix('#'))
+            .map(allowed)
+            .unwrap_or_default();
+        if own.contains(&warning.lint) || above.contains(&warning.lint) {
+            return true;
+        }
+        at = here.expanded_from.as_ref().map(|(_, call)| &**call);
+    }
+    false
+}
+
+/// Looks through an assembled program for things that are probably
+/// mistakes, pointing each one back at its line with the source map. Anything
+/// with a `# lint: allow(name)` comment on it, or on the line just above it,
+/// is left out.
+pub fn lint(program: &Program) -> Vec<Warning> {
+    let instructions = &program.instructions;
+
+    // `.data` counts as writing
+    let mut written: HashSet<u8> = instructions.iter().filter_map(Instruction::write).collect();
+    written.extend(program.data.iter().map(|(addr, _)| *addr));
+    let read: HashSet<u8> = instructions.iter().flat_map(Instruction::reads).collect();
+    let reachable = reachable(instructions);
+
+    let mut warnings = Vec::new();
+    for (idx, inst) in instructions.iter().enumerate() {
+        // Everything from source has a line, there's nothing to point at
+        // otherwise
+        let at = match program.source(idx) {
+            Some(at) => at,
+            None => continue,
+        };
+        let mut warn = |lint, message| {
+            warnings.push(Warning {
+                at: at.clone(),
+                lint,
+                message,
+            })
+        };
+
+        for addr in inst.reads() {
+            if addr != 0 && !written.contains(&addr) {
+                warn(
+                    "uninit-read",
+                    format!("m{} is read but never written, so it's always 0", addr),
+                );
+            }
+        }
+
+        match inst.write() {
+            Some(0) => warn(
+                "ip-write",
+                "Writes to m0, which is the instruction pointer".to_owned(),
+            ),
+            Some(addr) if !read.contains(&addr) => warn(
+                "unused-result",
+                format!("The result in m{} is never read", addr),
+            ),
+            _ => {}
+        }
+
+        if let Some(target) = inst.jump_target(idx) {
+            if target >= instructions.len() {
+                warn(
+                    "jump-out-of-bounds",
+                    format!(
+                        "Jumps to instruction {}, but there's only {}",
+                        target,
+                        instructions.len()
+                    ),
+                );
+            }
+        }
+
+        // Only the start of each unreachable stretch, or it gets noisy
+        if !reachable[idx] && (idx == 0 || reachable[idx - 1]) {
+            warn("unreachable", "This can never run".to_owned());
+        }
+
+        let mut addrs = inst.reads();
+        addrs.extend(inst.write());
+        if addrs.iter().any(|addr| *addr as usize >= MEMORY_SIZE) {
+            warn(
+                "out-of-memory",
+                format!("Memory stops at m{}", MEMORY_SIZE - 1),
+            );
+        }
+    }
+
+    let mut files = HashMap::new();
+    warnings.retain(|warning| !is_allowed(warning, &mut files));
+
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::source::{self, SourceConfig};
+
+    /// (line, lint) for each warning.
+    fn lints(file: &str, src: &str) -> Vec<(usize, &'static str)> {
+        let lines = source::read_str(file, src, &SourceConfig::default()).unwrap();
+        let program = asm::assemble(lines).unwrap();
+        lint(&program)
+            .into_iter()
+            .map(|warning| (warning.at.line, warning.lint))
+            .collect()
+    }
+
+    #[test]
+    fn labels_dont_turn_checks_off() {
+        let src = "loop:\nnum m9\nbak loop, 0\nset 1 -> m254\n";
+        assert_eq!(
+            lints("test.sick", src),
+            [(2, "uninit-read"), (4, "unused-result"), (4, "unreachable")]
+        );
+    }
+
+    #[test]
+    fn data_counts_as_written() {
+        assert_eq!(lints("test.sick", ".data m3 = 1\nnum m3\nbye 0\n"), []);
+    }
+
+    #[test]
+    fn allowed_where_the_macro_is_called() {
+        let src = "
+.macro put x
+    num \\x
+.endm
+# lint: allow(uninit-read)
+put m7
+put m8 # lint: allow(uninit-read)
+put m9
+bye 0
+";
+        let path = std::env::temp_dir().join("sick-as-lint-allowed.sick");
+        fs::write(&path, src).unwrap();
+        let file = path.to_str().unwrap();
+        // Line 3 is the `num` in the macro
+        assert_eq!(lints(file, src), [(3, "uninit-read")]);
+        fs::remove_file(&path).unwrap();
+    }
+}
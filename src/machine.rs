@@ -0,0 +1,482 @@
+use crate::io::{SickIo, StdIo};
+use crate::parser::{Instruction, Value};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use std::{fmt::Display, num::Wrapping};
+
+/// Size of the memory. `m0` is the instruction pointer.
+pub const MEMORY_SIZE: usize = 255;
+
+// Little macro to grab a value from memory, or as a literal.
+// It means i can just use this on all the spots.
+macro_rules! get_val {
+    ($mem:expr, $val:expr) => {{
+        match $val {
+            Value::Literal { val } => *val,
+            Value::Memory { addr } => $mem[*addr as usize].0,
+        }
+    }};
+}
+
+macro_rules! get_addr {
+    ($val:expr) => {{
+        // The parser should ensure this
+        match $val {
+            Value::Memory { addr } => *addr,
+            _ => unreachable!(),
+        }
+    }};
+}
+
+/// Knobs for the machine. The default is what a plain run uses.
+#[derive(Debug, Clone, Default)]
+pub struct MachineConfig {
+    /// How many steps of undo history to keep around. 0 means none at all.
+    pub history_cap: usize,
+    /// Give up after this many steps.
+    pub max_steps: Option<u64>,
+    /// Give up after running for this long. Can't interrupt a blocking read.
+    pub timeout: Option<Duration>,
+}
+
+/// Why a run stopped without reaching a `bye`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunError {
+    /// The program itself went wrong, e.g. bad input or a wild IP.
+    Fault(String),
+    /// Went past `max_steps`.
+    StepLimit(u64),
+    /// Went past `timeout`.
+    Timeout(Duration),
+}
+
+impl Display for RunError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RunError::Fault(msg) => write!(f, "{}", msg),
+            RunError::StepLimit(max) => write!(f, "Step limit of {} reached", max),
+            RunError::Timeout(timeout) => write!(f, "Timed out after {:?}", timeout),
+        }
+    }
+}
+
+impl From<String> for RunError {
+    fn from(msg: String) -> Self {
+        RunError::Fault(msg)
+    }
+}
+
+/// What happened after a step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Running,
+    Exited(u8),
+}
+
+/// Enough to undo a single step: where the IP was and what got overwritten.
+#[derive(Debug, Clone, Copy)]
+struct Undo {
+    ip: u8,
+    write: Option<(u8, u8)>,
+}
+
+pub struct Machine<I: SickIo = StdIo> {
+    pub memory: [Wrapping<u8>; MEMORY_SIZE],
+    pub io: I,
+    pub(crate) config: MachineConfig,
+    pub(crate) steps: u64,
+    history: VecDeque<Undo>,
+}
+
+impl Machine {
+    /// A machine hooked up to the terminal.
+    pub fn new(config: MachineConfig) -> Self {
+        Machine::with_io(config, StdIo)
+    }
+}
+
+impl<I: SickIo> Machine<I> {
+    pub fn with_io(config: MachineConfig, io: I) -> Self {
+        Machine {
+            memory: [Wrapping(0u8); MEMORY_SIZE],
+            io,
+            config,
+            steps: 0,
+            history: VecDeque::new(),
+        }
+    }
+
+    pub fn ip(&self) -> u8 {
+        self.memory[0].0
+    }
+
+    /// Number of instructions executed so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// The earliest step we can still rewind to.
+    pub fn oldest_step(&self) -> u64 {
+        self.steps - self.history.len() as u64
+    }
+
+    /// The instruction the IP is sitting on.
+    pub fn current<'a>(&self, instructions: &'a [Instruction]) -> Result<&'a Instruction, String> {
+        instructions
+            .get(self.ip() as usize)
+            .ok_or_else(|| format!("Instruction pointer out of bounds: {}", self.ip()))
+    }
+
+    /// Runs the instruction under the IP. `bye` doesn't count as a step, it
+    /// just reports the exit code and leaves everything as is.
+    pub fn step(&mut self, instructions: &[Instruction]) -> Result<Status, String> {
+        let ip = self.ip();
+        let inst = self.current(instructions)?;
+        let memory = &mut self.memory;
+
+        // Where the result goes, if anywhere
+        let mut write = None;
+        let mut jumped = false;
+
+        match inst {
+            // Sets memory
+            Instruction::Set { src, tgt } => write = Some((get_addr!(tgt), get_val!(memory, src))),
+            // Bitwise and
+            Instruction::And { left, right, tgt } => {
+                write = Some((
+                    get_addr!(tgt),
+                    get_val!(memory, left) & get_val!(memory, right),
+                ))
+            }
+            // Bitwise xor
+            Instruction::Xor { left, right, tgt } => {
+                write = Some((
+                    get_addr!(tgt),
+                    get_val!(memory, left) ^ get_val!(memory, right),
+                ))
+            }
+            // Boolean not
+            Instruction::Not { src, tgt } => {
+                write = Some((get_addr!(tgt), (get_val!(memory, src) == 0) as u8))
+            }
+            // Add left + right
+            Instruction::Add { left, right, tgt } => {
+                write = Some((
+                    get_addr!(tgt),
+                    (Wrapping(get_val!(memory, left)) + Wrapping(get_val!(memory, right))).0,
+                ))
+            }
+            // Substract left - right
+            Instruction::Sub { left, right, tgt } => {
+                write = Some((
+                    get_addr!(tgt),
+                    (Wrapping(get_val!(memory, left)) - Wrapping(get_val!(memory, right))).0,
+                ))
+            }
+            // Print out as a character
+            Instruction::Out { src } => self.io.out(get_val!(memory, src)),
+            // Print out as a number
+            Instruction::Num { src } => self.io.num(get_val!(memory, src)),
+            // Take in a character
+            Instruction::Cin { tgt } => write = Some((get_addr!(tgt), self.io.cin()?)),
+            // Take in a number
+            Instruction::Nin { tgt } => write = Some((get_addr!(tgt), self.io.nin()?)),
+            // Skip backward
+            Instruction::Bak { count, check } => {
+                if get_val!(memory, check) == 0 {
+                    memory[0] -= get_val!(memory, count);
+                    jumped = true;
+                }
+            }
+            // Skip forward
+            Instruction::Fwd { count, check } => {
+                if get_val!(memory, check) == 0 {
+                    memory[0] += get_val!(memory, count);
+                    jumped = true;
+                }
+            }
+            Instruction::Bye { code } => return Ok(Status::Exited(get_val!(memory, code))),
+            // No operation
+            Instruction::Nop => {}
+        };
+
+        let write = write.map(|(addr, val)| {
+            let old = memory[addr as usize].0;
+            memory[addr as usize] = Wrapping(val);
+            (addr, old)
+        });
+
+        if !jumped {
+            memory[0] += 1;
+        }
+
+        self.steps += 1;
+        if self.config.history_cap > 0 {
+            if self.history.len() == self.config.history_cap {
+                self.history.pop_front();
+            }
+            self.history.push_back(Undo { ip, write });
+        }
+
+        Ok(Status::Running)
+    }
+
+    /// Keeps stepping until a `bye`, and gives back its code. Stops early if
+    /// the step or time limit from the config runs out.
+    pub fn run(&mut self, instructions: &[Instruction]) -> Result<u8, RunError> {
+        let started = Instant::now();
+        let mut ran = 0u64;
+
+        loop {
+            // `bye` doesn't count as a step, so it's allowed right at the limit
+            if let Some(max) = self.config.max_steps {
+                if ran >= max && !matches!(self.current(instructions)?, Instruction::Bye { .. }) {
+                    return Err(RunError::StepLimit(max));
+                }
+            }
+            // Checking the clock every step is a bit much
+            if let Some(timeout) = self.config.timeout {
+                if ran.is_multiple_of(1024) && started.elapsed() >= timeout {
+                    return Err(RunError::Timeout(timeout));
+                }
+            }
+            ran += 1;
+
+            if let Status::Exited(code) = self.step(instructions)? {
+                return Ok(code);
+            }
+        }
+    }
+
+    /// Undoes the last step. Returns false if there's no history left.
+    pub fn step_back(&mut self) -> bool {
+        match self.history.pop_back() {
+            Some(Undo { ip, write }) => {
+                if let Some((addr, old)) = write {
+                    self.memory[addr as usize] = Wrapping(old);
+                }
+                self.memory[0] = Wrapping(ip);
+                self.steps -= 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Goes back to just before the given step. Can't go past the history cap.
+    pub fn rewind_to(&mut self, step: u64) -> Result<(), String> {
+        if step > self.steps {
+            return Err(format!("Step {} hasn't happened yet", step));
+        }
+        if step < self.oldest_step() {
+            return Err(format!(
+                "Step {} is too far back, history starts at step {}",
+                step,
+                self.oldest_step()
+            ));
+        }
+
+        while self.steps > step {
+            self.step_back();
+        }
+
+        Ok(())
+    }
+
+    /// Runs backwards until just before the last instruction that wrote to
+    /// `addr`. Gives back the step it stopped at.
+    pub fn back_to_write(&mut self, addr: u8) -> Result<u64, String> {
+        let idx = self
+            .history
+            .iter()
+            .rposition(|undo| matches!(undo.write, Some((a, _)) if a == addr))
+            .ok_or_else(|| format!("No write to m{} in the history", addr))?;
+
+        let step = self.oldest_step() + idx as u64;
+        self.rewind_to(step)?;
+        Ok(step)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::BufferIo;
+    use crate::parser::parse_instruction;
+
+    type Memory = [Wrapping<u8>; MEMORY_SIZE];
+
+    fn parse(lines: &[&str]) -> Vec<Instruction> {
+        lines
+            .iter()
+            .map(|line| parse_instruction(line).unwrap())
+            .collect()
+    }
+
+    fn machine(history_cap: usize) -> Machine<BufferIo> {
+        let config = MachineConfig {
+            history_cap,
+            ..Default::default()
+        };
+        Machine::with_io(config, BufferIo::new(b""))
+    }
+
+    /// Counts m1 up to 5 and jumps around through m0 on the way.
+    fn program() -> Vec<Instruction> {
+        parse(&[
+            "add m1, 1 -> m1",
+            "xor m1, 5 -> m2",
+            "fwd 2, m2",
+            "set 4 -> m0",
+            "bye 0",
+            "set 0 -> m3",
+            "add m3, m1 -> m3",
+            "bak 7, 0",
+        ])
+    }
+
+    /// Runs `count` steps, giving back memory from before each one and after
+    /// the last.
+    fn forward(
+        machine: &mut Machine<BufferIo>,
+        program: &[Instruction],
+        count: usize,
+    ) -> Vec<Memory> {
+        let mut snapshots = vec![machine.memory];
+        for _ in 0..count {
+            assert_eq!(machine.step(program), Ok(Status::Running));
+            snapshots.push(machine.memory);
+        }
+        snapshots
+    }
+
+    #[test]
+    fn step_back_all_the_way() {
+        let program = program();
+        let mut machine = machine(1000);
+        let snapshots = forward(&mut machine, &program, 30);
+
+        for (step, snapshot) in snapshots.iter().enumerate().rev() {
+            assert_eq!(machine.steps(), step as u64);
+            assert_eq!(&machine.memory, snapshot, "step {}", step);
+            machine.step_back();
+        }
+        assert!(!machine.step_back());
+    }
+
+    #[test]
+    fn undo_a_write_to_m0() {
+        let program = parse(&["set 3 -> m0", "nop", "nop", "nop", "out 1"]);
+        let mut machine = machine(10);
+        machine.step(&program).unwrap();
+        assert_eq!(machine.ip(), 4);
+        assert!(machine.step_back());
+        assert_eq!(machine.ip(), 0);
+        assert_eq!(machine.memory, [Wrapping(0); MEMORY_SIZE]);
+    }
+
+    #[test]
+    fn history_cap_drops_the_oldest() {
+        let program = program();
+        let mut machine = machine(5);
+        let snapshots = forward(&mut machine, &program, 20);
+        assert_eq!(machine.oldest_step(), 15);
+
+        assert_eq!(
+            machine.rewind_to(14),
+            Err("Step 14 is too far back, history starts at step 15".to_owned())
+        );
+        // Nothing moved
+        assert_eq!(machine.memory, snapshots[20]);
+        assert_eq!(
+            machine.rewind_to(21),
+            Err("Step 21 hasn't happened yet".to_owned())
+        );
+
+        machine.rewind_to(15).unwrap();
+        assert_eq!(machine.memory, snapshots[15]);
+        assert!(!machine.step_back());
+    }
+
+    #[test]
+    fn rewind_then_run_again() {
+        let program = program();
+        let mut machine = machine(100);
+        let snapshots = forward(&mut machine, &program, 25);
+
+        machine.rewind_to(10).unwrap();
+        assert_eq!(machine.memory, snapshots[10]);
+        // Same way forward as the first time
+        assert_eq!(forward(&mut machine, &program, 15), snapshots[10..]);
+    }
+
+    #[test]
+    fn back_to_the_last_write() {
+        let program = program();
+        let mut machine = machine(100);
+        let snapshots = forward(&mut machine, &program, 12);
+
+        // Step 11 is the second `set 0 -> m3`, and before that the `add`
+        assert_eq!(machine.back_to_write(3), Ok(11));
+        assert_eq!(machine.memory, snapshots[11]);
+        assert_eq!(machine.back_to_write(3), Ok(5));
+        assert_eq!(machine.memory, snapshots[5]);
+        assert_eq!(
+            machine.back_to_write(9),
+            Err("No write to m9 in the history".to_owned())
+        );
+    }
+
+    fn limited(max_steps: Option<u64>, timeout: Option<Duration>) -> Machine<BufferIo> {
+        let config = MachineConfig {
+            max_steps,
+            timeout,
+            ..Default::default()
+        };
+        Machine::with_io(config, BufferIo::new(b""))
+    }
+
+    #[test]
+    fn bye_right_at_the_step_limit() {
+        let program = parse(&["nop", "nop", "nop", "bye 4"]);
+        assert_eq!(limited(Some(3), None).run(&program), Ok(4));
+
+        let mut machine = limited(Some(2), None);
+        assert_eq!(machine.run(&program), Err(RunError::StepLimit(2)));
+        assert_eq!(machine.steps(), 2);
+        assert_eq!(machine.ip(), 2);
+    }
+
+    #[test]
+    fn step_limit_of_0() {
+        let program = parse(&["bye 1"]);
+        assert_eq!(limited(Some(0), None).run(&program), Ok(1));
+        let program = parse(&["nop", "bye 1"]);
+        assert_eq!(
+            limited(Some(0), None).run(&program),
+            Err(RunError::StepLimit(0))
+        );
+    }
+
+    #[test]
+    fn timeout() {
+        let timeout = Duration::from_millis(20);
+        let mut machine = limited(None, Some(timeout));
+        assert_eq!(
+            machine.run(&parse(&["bak 0, 0"])),
+            Err(RunError::Timeout(timeout))
+        );
+        // The clock only gets looked at every 1024 steps
+        assert!(machine.steps() > 0);
+        assert_eq!(machine.steps() % 1024, 0);
+    }
+
+    #[test]
+    fn step_limit_goes_first() {
+        let mut machine = limited(Some(10), Some(Duration::from_secs(60)));
+        assert_eq!(
+            machine.run(&parse(&["bak 0, 0"])),
+            Err(RunError::StepLimit(10))
+        );
+    }
+}
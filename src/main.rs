@@ -1,794 +1,555 @@
-use nom::{
-    bytes::complete::{tag, take_while1},
-    character::{complete::char, is_alphabetic, is_digit},
-    combinator::opt,
-    sequence::tuple,
+use sick_as::{
+    asm, bf, bytecode, cfg, compile, debugger, disasm, formatter, link, lint, optimize, parser,
+    repl, source, stdlib, Machine, MachineConfig, RunError, SourceConfig, Threaded,
 };
-use std::{env, fmt::Display, fs};
-use std::{
-    io::{Read, Write},
-    num::Wrapping,
-};
-/*
-* Instructions: (where a is any and m is memory)
-* set a, -> m
-* add a, a -> m
-* sub a, a -> m
-* out a
-* num a
-* cin -> m
-* nin -> m
-* bak a, a
-* fwd a, a
-* bye a
-*/
-
-#[derive(Debug)]
-enum Value {
-    Memory { addr: u8 },
-    Literal { val: u8 },
+use std::os::unix::fs::PermissionsExt;
+use std::{env, fs, path::Path, str::FromStr, time::Duration};
+
+/// What we exit with when a step or time limit runs out, same as timeout(1).
+const LIMIT_EXIT_CODE: i32 = 124;
+
+/// Grabs the value after a flag and parses it.
+fn flag_value<'a, T: FromStr>(
+    flag: &str,
+    rest: &mut impl Iterator<Item = &'a String>,
+) -> Result<T, String> {
+    match rest.next().map(|val| val.parse()) {
+        Some(Ok(val)) => Ok(val),
+        _ => Err(format!("Missing or invalid value for {}", flag)),
+    }
 }
 
-impl Display for Value {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Value::Memory { addr } => write!(f, "m{}", addr),
-            Value::Literal { val } => write!(f, "{}", val),
+/// Takes every `-I dir` and `-D NAME=value` out of the args, wherever they
+/// are, since anything that reads source wants them.
+fn source_config(args: Vec<String>) -> Result<(Vec<String>, SourceConfig), String> {
+    let mut config = SourceConfig::default();
+    let mut rest = Vec::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.strip_prefix("-I") {
+            Some("") => config
+                .include_dirs
+                .push(args.next().ok_or("Missing value for -I")?.into()),
+            Some(dir) => config.include_dirs.push(dir.into()),
+            None => match arg.strip_prefix("-D") {
+                Some("") => define(&mut config, &args.next().ok_or("Missing value for -D")?),
+                Some(def) => define(&mut config, def),
+                None => rest.push(arg),
+            },
         }
     }
-}
 
-#[derive(Debug)]
-enum Instruction {
-    Set {
-        src: Value,
-        tgt: Value,
-    },
-    And {
-        left: Value,
-        right: Value,
-        tgt: Value,
-    },
-    Xor {
-        left: Value,
-        right: Value,
-        tgt: Value,
-    },
-    Not {
-        src: Value,
-        tgt: Value,
-    },
-    Add {
-        left: Value,
-        right: Value,
-        tgt: Value,
-    },
-    Sub {
-        left: Value,
-        right: Value,
-        tgt: Value,
-    },
-    Out {
-        src: Value,
-    },
-    Num {
-        src: Value,
-    },
-    Cin {
-        tgt: Value,
-    },
-    Nin {
-        tgt: Value,
-    },
-    Bak {
-        count: Value,
-        check: Value,
-    },
-    Fwd {
-        count: Value,
-        check: Value,
-    },
-    Bye {
-        code: Value,
-    },
-    Nop,
+    Ok((rest, config))
 }
 
-impl Display for Instruction {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Instruction::Set { src, tgt } => write!(f, "set {} -> {}", src, tgt),
-            Instruction::Add { left, right, tgt } => {
-                write!(f, "add {}, {} -> {}", left, right, tgt)
-            }
-            Instruction::Sub { left, right, tgt } => {
-                write!(f, "sub {}, {} -> {}", left, right, tgt)
-            }
-            Instruction::Out { src } => write!(f, "out {}", src),
-            Instruction::Num { src } => write!(f, "num {}", src),
-            Instruction::Cin { tgt } => write!(f, "cin -> {}", tgt),
-            Instruction::Nin { tgt } => write!(f, "nin -> {}", tgt),
-            Instruction::Bak { count, check } => write!(f, "bak {}, {}", count, check),
-            Instruction::Fwd { count, check } => write!(f, "fwd {}, {}", count, check),
-            Instruction::Bye { code } => write!(f, "bye {}", code),
-            Instruction::And { left, right, tgt } => {
-                write!(f, "and {}, {} -> {}", left, right, tgt)
-            }
-            Instruction::Xor { left, right, tgt } => {
-                write!(f, "xor {}, {} -> {}", left, right, tgt)
-            }
-            Instruction::Not { src, tgt } => write!(f, "not {} -> {}", src, tgt),
-            Instruction::Nop => write!(f, "nop"),
-        }
-    }
+/// A plain `-D NAME` is the same as `-D NAME=1`.
+fn define(config: &mut SourceConfig, def: &str) {
+    let (name, val) = def.split_once('=').unwrap_or((def, "1"));
+    config.defines.insert(name.to_owned(), val.to_owned());
 }
 
-/// Little convenience function for converting strs into u8s and being able to use a ?
-#[inline]
-fn str_to_u8(s: &str) -> Result<u8, &'static str> {
-    match s.parse::<u8>() {
-        Ok(val) => Ok(val),
-        Err(_) => Err("Error parsing integer"),
-    }
-}
+fn main() -> Result<(), String> {
+    let (args, source) = source_config(env::args().skip(1).collect())?;
+
+    match args.first().map(String::as_str) {
+        Some("debug") => {
+            let mut history_cap = debugger::DEFAULT_HISTORY;
+            let mut file = None;
+
+            let mut rest = args[1..].iter();
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "--history" => history_cap = flag_value(arg, &mut rest)?,
+                    _ => file = Some(arg),
+                }
+            }
 
-fn parse_instruction(input: &str) -> Result<Instruction, String> {
-    // Matches any alpha word
-    let word = take_while1(|c: char| is_alphabetic(c as u8));
-    // Matches any series of numbers
-    let num = take_while1(|c: char| is_digit(c as u8));
-    // Matches just 'm'
-    let mem = char::<&str, nom::error::Error<&str>>('m');
-    // Matches any number of spaces
-    let space = take_while1(|c| c == ' ');
-    // Matches a comma with any number of spaces on either side
-    let sep = tuple((opt(&space), char(','), opt(&space)));
-    // Matches an arrow with any number of spaces on either side
-    let arrow = tuple((opt(&space), tag("->"), opt(&space)));
-
-    let (input, (name, _)) = match tuple((word, opt(&space)))(input) {
-        Ok(val) => val,
-        Err(nom::Err::Error(nom::error::Error { input, .. }))
-        | Err(nom::Err::Failure(nom::error::Error { input, .. })) => {
-            return Err(format!("Not a valid instruction name: `{}`", input))
-        }
-        Err(nom::Err::Incomplete(_)) => {
-            return Err("Error while parsing instruction name, incomplete data.".to_owned())
+            let file = file.ok_or("Please provide a filename as an argument")?;
+            debugger::run(&bytecode::load_with(file, &source)?, history_cap)
         }
-    };
-
-    let inst = match name {
-        "set" => {
-            let (input, (is_mem, src, _, _, tgt, _)) =
-                match tuple((opt(&mem), &num, arrow, &mem, &num, opt(&space)))(input) {
-                    Ok(val) => val,
-                    Err(nom::Err::Error(nom::error::Error { input, .. }))
-                    | Err(nom::Err::Failure(nom::error::Error { input, .. })) => {
-                        return Err(format!(
-                            "Error while parsing `set` instruction near `{}`",
-                            input
-                        ))
-                    }
-                    Err(nom::Err::Incomplete(_)) => {
-                        return Err("Error while parsing `set`, incomplete data.".to_owned())
-                    }
-                };
-            if !input.is_empty() {
-                return Err(format!("Unexpected characters: `{}`", input));
+        Some("assemble") => {
+            let mut output = None;
+            let mut listing = None;
+            let mut object = false;
+            let mut file = None;
+
+            let mut rest = args[1..].iter();
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "-o" => output = Some(flag_value::<String>(arg, &mut rest)?),
+                    "--listing" => listing = Some(flag_value::<String>(arg, &mut rest)?),
+                    "-c" => object = true,
+                    _ => file = Some(arg),
+                }
             }
-            let src = str_to_u8(src)?;
-            let tgt = str_to_u8(tgt)?;
 
-            Instruction::Set {
-                src: match is_mem {
-                    Some(_) => Value::Memory { addr: src },
-                    None => Value::Literal { val: src },
-                },
-                tgt: Value::Memory { addr: tgt },
+            let file = file.ok_or("Please provide a filename as an argument")?;
+            let output = output.unwrap_or_else(|| {
+                Path::new(file)
+                    .with_extension(if object { "sbo" } else { "sbc" })
+                    .to_string_lossy()
+                    .into_owned()
+            });
+
+            let lines = source::read(file, &source)?;
+            let (program, bytes) = if object {
+                let object = asm::assemble_object(lines)?;
+                let bytes = link::encode(&object).map_err(|msg| format!("{}: {}", file, msg))?;
+                (object.program, bytes)
+            } else {
+                let program = asm::assemble(lines)?;
+                let bytes = bytecode::encode(&program);
+                (program, bytes)
+            };
+
+            if let Some(listing) = listing {
+                fs::write(&listing, asm::listing(&program))
+                    .map_err(|_| format!("Could not write {}", listing))?;
             }
+            fs::write(&output, bytes).map_err(|_| format!("Could not write {}", output))
         }
-
-        "and" => {
-            let (input, (is_mem_l, src_l, _, is_mem_r, src_r, _, _, tgt, _)) = match tuple((
-                opt(&mem),
-                &num,
-                sep,
-                opt(&mem),
-                &num,
-                arrow,
-                &mem,
-                &num,
-                opt(&space),
-            ))(
-                input
-            ) {
-                Ok(val) => val,
-                Err(nom::Err::Error(nom::error::Error { input, .. }))
-                | Err(nom::Err::Failure(nom::error::Error { input, .. })) => {
-                    return Err(format!(
-                        "Error while parsing `and` instruction near `{}`",
-                        input
-                    ))
-                }
-                Err(nom::Err::Incomplete(_)) => {
-                    return Err("Error while parsing `and` incomplete data.".to_owned())
+        Some("link") => {
+            let mut output = None;
+            let mut files = Vec::new();
+
+            let mut rest = args[1..].iter();
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "-o" => output = Some(flag_value::<String>(arg, &mut rest)?),
+                    _ => files.push(arg),
                 }
-            };
-            if !input.is_empty() {
-                return Err(format!("Unexpected characters: `{}`", input));
-            }
-            let src_l = str_to_u8(src_l)?;
-            let src_r = str_to_u8(src_r)?;
-            let tgt = str_to_u8(tgt)?;
-
-            Instruction::And {
-                left: match is_mem_l {
-                    Some(_) => Value::Memory { addr: src_l },
-                    None => Value::Literal { val: src_l },
-                },
-                right: match is_mem_r {
-                    Some(_) => Value::Memory { addr: src_r },
-                    None => Value::Literal { val: src_r },
-                },
-                tgt: Value::Memory { addr: tgt },
             }
+
+            let first = files
+                .first()
+                .ok_or("Please provide the files to link as arguments")?;
+            let output = output.unwrap_or_else(|| {
+                Path::new(first)
+                    .with_extension("sbc")
+                    .to_string_lossy()
+                    .into_owned()
+            });
+
+            let objects = files
+                .iter()
+                .map(|file| Ok((file.to_string(), link::load_with(file, &source)?)))
+                .collect::<Result<Vec<_>, String>>()?;
+            let bytes = bytecode::encode(&link::link(&objects)?);
+            fs::write(&output, bytes).map_err(|_| format!("Could not write {}", output))
         }
-        "not" => {
-            let (input, (is_mem, src, _, _, tgt, _)) =
-                match tuple((opt(&mem), &num, arrow, &mem, &num, opt(&space)))(input) {
-                    Ok(val) => val,
-                    Err(nom::Err::Error(nom::error::Error { input, .. }))
-                    | Err(nom::Err::Failure(nom::error::Error { input, .. })) => {
-                        return Err(format!(
-                            "Error while parsing `not` instruction near `{}`",
-                            input
-                        ))
-                    }
-                    Err(nom::Err::Incomplete(_)) => {
-                        return Err("Error while parsing `not`, incomplete data.".to_owned())
-                    }
-                };
-            if !input.is_empty() {
-                return Err(format!("Unexpected characters: `{}`", input));
+        Some("expand") => {
+            let mut output = None;
+            let mut file = None;
+
+            let mut rest = args[1..].iter();
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "-o" => output = Some(flag_value::<String>(arg, &mut rest)?),
+                    _ => file = Some(arg),
+                }
             }
-            let src = str_to_u8(src)?;
-            let tgt = str_to_u8(tgt)?;
 
-            Instruction::Not {
-                src: match is_mem {
-                    Some(_) => Value::Memory { addr: src },
-                    None => Value::Literal { val: src },
-                },
-                tgt: Value::Memory { addr: tgt },
-            }
-        }
-        "xor" => {
-            let (input, (is_mem_l, src_l, _, is_mem_r, src_r, _, _, tgt, _)) = match tuple((
-                opt(&mem),
-                &num,
-                sep,
-                opt(&mem),
-                &num,
-                arrow,
-                &mem,
-                &num,
-                opt(&space),
-            ))(
-                input
-            ) {
-                Ok(val) => val,
-                Err(nom::Err::Error(nom::error::Error { input, .. }))
-                | Err(nom::Err::Failure(nom::error::Error { input, .. })) => {
-                    return Err(format!(
-                        "Error while parsing `xor` instruction near `{}`",
-                        input
-                    ))
-                }
-                Err(nom::Err::Incomplete(_)) => {
-                    return Err("Error while parsing `xor`, incomplete data.".to_owned())
+            let file = file.ok_or("Please provide a filename as an argument")?;
+            let program = asm::assemble(source::read(file, &source)?)?;
+            let text = formatter::format_lines(
+                &asm::to_lines(&program),
+                &formatter::FormatConfig::default(),
+            );
+
+            match output {
+                Some(output) => {
+                    fs::write(&output, text).map_err(|_| format!("Could not write {}", output))
+                }
+                None => {
+                    print!("{}", text);
+                    Ok(())
                 }
-            };
-            if !input.is_empty() {
-                return Err(format!("Unexpected characters: `{}`", input));
-            }
-            let src_l = str_to_u8(src_l)?;
-            let src_r = str_to_u8(src_r)?;
-            let tgt = str_to_u8(tgt)?;
-
-            Instruction::Xor {
-                left: match is_mem_l {
-                    Some(_) => Value::Memory { addr: src_l },
-                    None => Value::Literal { val: src_l },
-                },
-                right: match is_mem_r {
-                    Some(_) => Value::Memory { addr: src_r },
-                    None => Value::Literal { val: src_r },
-                },
-                tgt: Value::Memory { addr: tgt },
             }
         }
-        "add" => {
-            let (input, (is_mem_l, src_l, _, is_mem_r, src_r, _, _, tgt, _)) = match tuple((
-                opt(&mem),
-                &num,
-                sep,
-                opt(&mem),
-                &num,
-                arrow,
-                &mem,
-                &num,
-                opt(&space),
-            ))(
-                input
-            ) {
-                Ok(val) => val,
-                Err(nom::Err::Error(nom::error::Error { input, .. }))
-                | Err(nom::Err::Failure(nom::error::Error { input, .. })) => {
-                    return Err(format!(
-                        "Error while parsing `add` instruction near `{}`",
-                        input
-                    ))
-                }
-                Err(nom::Err::Incomplete(_)) => {
-                    return Err("Error while parsing `add`, incomplete data.".to_owned())
+        Some("disasm") => {
+            let mut labels = false;
+            let mut output = None;
+            let mut file = None;
+
+            let mut rest = args[1..].iter();
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "--labels" => labels = true,
+                    "-o" => output = Some(flag_value::<String>(arg, &mut rest)?),
+                    _ => file = Some(arg),
                 }
-            };
-            if !input.is_empty() {
-                return Err(format!("Unexpected characters: `{}`", input));
-            }
-            let src_l = str_to_u8(src_l)?;
-            let src_r = str_to_u8(src_r)?;
-            let tgt = str_to_u8(tgt)?;
-
-            Instruction::Add {
-                left: match is_mem_l {
-                    Some(_) => Value::Memory { addr: src_l },
-                    None => Value::Literal { val: src_l },
-                },
-                right: match is_mem_r {
-                    Some(_) => Value::Memory { addr: src_r },
-                    None => Value::Literal { val: src_r },
-                },
-                tgt: Value::Memory { addr: tgt },
             }
-        }
-        "sub" => {
-            let (input, (is_mem_l, src_l, _, is_mem_r, src_r, _, _, tgt, _)) = match tuple((
-                opt(&mem),
-                &num,
-                sep,
-                opt(&mem),
-                &num,
-                arrow,
-                &mem,
-                &num,
-                opt(&space),
-            ))(
-                input
-            ) {
-                Ok(val) => val,
-                Err(nom::Err::Error(nom::error::Error { input, .. }))
-                | Err(nom::Err::Failure(nom::error::Error { input, .. })) => {
-                    return Err(format!(
-                        "Error while parsing `sub` instruction near `{}`",
-                        input
-                    ))
-                }
-                Err(nom::Err::Incomplete(_)) => {
-                    return Err("Error while parsing `sub`, incomplete data.".to_owned())
+
+            let file = file.ok_or("Please provide a filename as an argument")?;
+            let text = disasm::disassemble(&bytecode::load_with(file, &source)?, labels);
+            match output {
+                Some(output) => {
+                    fs::write(&output, text).map_err(|_| format!("Could not write {}", output))
+                }
+                None => {
+                    print!("{}", text);
+                    Ok(())
                 }
-            };
-            if !input.is_empty() {
-                return Err(format!("Unexpected characters: `{}`", input));
-            }
-            let src_l = str_to_u8(src_l)?;
-            let src_r = str_to_u8(src_r)?;
-            let tgt = str_to_u8(tgt)?;
-
-            Instruction::Sub {
-                left: match is_mem_l {
-                    Some(_) => Value::Memory { addr: src_l },
-                    None => Value::Literal { val: src_l },
-                },
-                right: match is_mem_r {
-                    Some(_) => Value::Memory { addr: src_r },
-                    None => Value::Literal { val: src_r },
-                },
-                tgt: Value::Memory { addr: tgt },
             }
         }
-        "out" => {
-            let (input, (is_mem, src, _)) = match tuple((opt(&mem), &num, opt(&space)))(input) {
-                Ok(val) => val,
-                Err(nom::Err::Error(nom::error::Error { input, .. }))
-                | Err(nom::Err::Failure(nom::error::Error { input, .. })) => {
-                    return Err(format!(
-                        "Error while parsing `out` instruction near `{}`",
-                        input
-                    ))
-                }
-                Err(nom::Err::Incomplete(_)) => {
-                    return Err("Error while parsing `out`, incomplete data.".to_owned())
+        Some("fmt") => {
+            let mut config = formatter::FormatConfig::default();
+            let mut check = false;
+            let mut files = Vec::new();
+
+            let mut rest = args[1..].iter();
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "--check" => check = true,
+                    "--indent" => config.indent = flag_value(arg, &mut rest)?,
+                    _ => files.push(arg),
                 }
-            };
-            if !input.is_empty() {
-                return Err(format!("Unexpected characters: `{}`", input));
             }
 
-            let src = str_to_u8(src)?;
-
-            Instruction::Out {
-                src: match is_mem {
-                    Some(_) => Value::Memory { addr: src },
-                    None => Value::Literal { val: src },
-                },
+            if files.is_empty() {
+                return Err("Please provide a filename as an argument".to_owned());
             }
-        }
-        "num" => {
-            let (input, (is_mem, src, _)) = match tuple((opt(&mem), &num, opt(&space)))(input) {
-                Ok(val) => val,
-                Err(nom::Err::Error(nom::error::Error { input, .. }))
-                | Err(nom::Err::Failure(nom::error::Error { input, .. })) => {
-                    return Err(format!(
-                        "Error while parsing `num` instruction near `{}`",
-                        input
-                    ))
-                }
-                Err(nom::Err::Incomplete(_)) => {
-                    return Err("Error while parsing `num`, incomplete data.".to_owned())
+
+            let mut unformatted = false;
+            for file in files {
+                let content =
+                    fs::read_to_string(file).map_err(|_| format!("Could not read {}", file))?;
+                let formatted = formatter::format_source(&content, &config)
+                    .map_err(|msg| format!("{}: {}", file, msg))?;
+
+                if formatted == content {
+                    continue;
+                }
+                if check {
+                    println!("{} needs formatting", file);
+                    unformatted = true;
+                } else {
+                    fs::write(file, formatted).map_err(|_| format!("Could not write {}", file))?;
                 }
-            };
-            if !input.is_empty() {
-                return Err(format!("Unexpected characters: `{}`", input));
             }
 
-            let src = str_to_u8(src)?;
-
-            Instruction::Num {
-                src: match is_mem {
-                    Some(_) => Value::Memory { addr: src },
-                    None => Value::Literal { val: src },
-                },
+            if unformatted {
+                std::process::exit(1);
             }
+            Ok(())
         }
-        "cin" => {
-            let (input, (_, _, addr, _)) = match tuple((arrow, &mem, &num, opt(&space)))(input) {
-                Ok(val) => val,
-                Err(nom::Err::Error(nom::error::Error { input, .. }))
-                | Err(nom::Err::Failure(nom::error::Error { input, .. })) => {
-                    return Err(format!(
-                        "Error while parsing `cin` instruction near `{}`",
-                        input
-                    ))
-                }
-                Err(nom::Err::Incomplete(_)) => {
-                    return Err("Error while parsing `cin`, incomplete data.".to_owned())
+        Some("lint") => {
+            let files = &args[1..];
+            if files.is_empty() {
+                return Err("Please provide a filename as an argument".to_owned());
+            }
+
+            let mut any = false;
+            for file in files {
+                let program = parser::get_program(file, &source)?;
+
+                for warning in lint::lint(&program) {
+                    any = true;
+                    let at = &warning.at;
+                    println!(
+                        "{}",
+                        at.error(format!("{} [{}]", warning.message, warning.lint))
+                    );
+                    println!("{:>5} | {}", at.line, at.text.trim());
+                    println!(
+                        "      = silence it with `# lint: allow({})`\n",
+                        warning.lint
+                    );
                 }
-            };
-            if !input.is_empty() {
-                return Err(format!("Unexpected characters: `{}`", input));
             }
 
-            let addr = str_to_u8(addr)?;
-
-            Instruction::Cin {
-                tgt: Value::Memory { addr },
+            if any {
+                std::process::exit(1);
             }
+            Ok(())
         }
-        "nin" => {
-            let (input, (_, _, addr, _)) = match tuple((arrow, &mem, &num, opt(&space)))(input) {
-                Ok(val) => val,
-                Err(nom::Err::Error(nom::error::Error { input, .. }))
-                | Err(nom::Err::Failure(nom::error::Error { input, .. })) => {
-                    return Err(format!(
-                        "Error while parsing `nin` instruction near `{}`",
-                        input
-                    ))
-                }
-                Err(nom::Err::Incomplete(_)) => {
-                    return Err("Error while parsing `nin`, incomplete data.".to_owned())
+        Some("cfg") => {
+            let mut format = "dot".to_owned();
+            let mut output = None;
+            let mut file = None;
+
+            let mut rest = args[1..].iter();
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "--format" => format = flag_value(arg, &mut rest)?,
+                    "-o" => output = Some(flag_value::<String>(arg, &mut rest)?),
+                    _ => file = Some(arg),
                 }
-            };
-            if !input.is_empty() {
-                return Err(format!("Unexpected characters: `{}`", input));
             }
 
-            let addr = str_to_u8(addr)?;
+            let file = file.ok_or("Please provide a filename as an argument")?;
+            let instructions = bytecode::load_with(file, &source)?.instructions;
+            let graph = cfg::Cfg::build(&instructions);
+            let text = match format.as_str() {
+                "dot" => graph.to_dot(&instructions),
+                "mermaid" => graph.to_mermaid(&instructions),
+                _ => return Err(format!("Unknown format `{}`, try dot or mermaid", format)),
+            };
 
-            Instruction::Nin {
-                tgt: Value::Memory { addr },
+            match output {
+                Some(output) => {
+                    fs::write(&output, text).map_err(|_| format!("Could not write {}", output))
+                }
+                None => {
+                    print!("{}", text);
+                    Ok(())
+                }
             }
         }
-        "bak" => {
-            let (input, (is_mem_count, count, _, is_mem_check, check, _)) =
-                match tuple((opt(&mem), &num, sep, opt(&mem), &num, opt(&space)))(input) {
-                    Ok(val) => val,
-                    Err(nom::Err::Error(nom::error::Error { input, .. }))
-                    | Err(nom::Err::Failure(nom::error::Error { input, .. })) => {
-                        return Err(format!(
-                            "Error while parsing `bak` instruction near `{}`",
-                            input
-                        ))
-                    }
-                    Err(nom::Err::Incomplete(_)) => {
-                        return Err("Error while parsing `bak`, incomplete data.".to_owned())
-                    }
-                };
-            if !input.is_empty() {
-                return Err(format!("Unexpected characters: `{}`", input));
+        Some("optimize") => {
+            let mut output = None;
+            let mut file = None;
+
+            let mut rest = args[1..].iter();
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "-o" => output = Some(flag_value::<String>(arg, &mut rest)?),
+                    _ => file = Some(arg),
+                }
             }
 
-            let count = str_to_u8(count)?;
-            let check = str_to_u8(check)?;
-
-            Instruction::Bak {
-                count: match is_mem_count {
-                    Some(_) => Value::Memory { addr: count },
-                    None => Value::Literal { val: count },
-                },
-                check: match is_mem_check {
-                    Some(_) => Value::Memory { addr: check },
-                    None => Value::Literal { val: check },
-                },
+            let file = file.ok_or("Please provide a filename as an argument")?;
+            let program = bytecode::load_with(file, &source)?;
+            let (optimized, pinned) = optimize::optimize(&program);
+            if let Some(reason) = pinned {
+                eprintln!("Left some instructions where they were, {}", reason);
+            }
+            eprintln!(
+                "{} instructions down to {}",
+                program.instructions.len(),
+                optimized.instructions.len()
+            );
+
+            let mut text: String = optimized
+                .data_directives()
+                .into_iter()
+                .map(|line| format!("{}\n", line))
+                .collect();
+            text.extend(
+                optimized
+                    .instructions
+                    .iter()
+                    .map(|inst| format!("{}\n", inst)),
+            );
+            match output {
+                Some(output) => {
+                    fs::write(&output, text).map_err(|_| format!("Could not write {}", output))
+                }
+                None => {
+                    print!("{}", text);
+                    Ok(())
+                }
             }
         }
-
-        "fwd" => {
-            let (input, (is_mem_count, count, _, is_mem_check, check, _)) =
-                match tuple((opt(&mem), &num, sep, opt(&mem), &num, opt(&space)))(input) {
-                    Ok(val) => val,
-                    Err(nom::Err::Error(nom::error::Error { input, .. }))
-                    | Err(nom::Err::Failure(nom::error::Error { input, .. })) => {
-                        return Err(format!(
-                            "Error while parsing `fwd` instruction near `{}`",
-                            input
-                        ))
+        Some("compile") => {
+            let mut target = None;
+            let mut output = None;
+            let mut test = false;
+            let mut inputs = Vec::new();
+            let mut file = None;
+
+            let mut rest = args[1..].iter();
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "--target" => target = Some(flag_value::<compile::Target>(arg, &mut rest)?),
+                    "-o" => output = Some(flag_value::<String>(arg, &mut rest)?),
+                    "--test" => test = true,
+                    "--input" => {
+                        let path: String = flag_value(arg, &mut rest)?;
+                        inputs
+                            .push(fs::read(&path).map_err(|_| format!("Could not read {}", path))?)
                     }
-                    Err(nom::Err::Incomplete(_)) => {
-                        return Err("Error while parsing `fwd`, incomplete data.".to_owned())
-                    }
-                };
-            if !input.is_empty() {
-                return Err(format!("Unexpected characters: `{}`", input));
+                    _ => file = Some(arg),
+                }
             }
 
-            let count = str_to_u8(count)?;
-            let check = str_to_u8(check)?;
+            let file = file.ok_or("Please provide a filename as an argument")?;
+            let target = target.ok_or("Please pick a target with --target")?;
+            let program = bytecode::load_with(file, &source)?;
 
-            Instruction::Fwd {
-                count: match is_mem_count {
-                    Some(_) => Value::Memory { addr: count },
-                    None => Value::Literal { val: count },
-                },
-                check: match is_mem_check {
-                    Some(_) => Value::Memory { addr: check },
-                    None => Value::Literal { val: check },
-                },
-            }
-        }
-
-        "bye" => {
-            let (input, (is_mem, src, _)) = match tuple((opt(&mem), &num, opt(&space)))(input) {
-                Ok(val) => val,
-                Err(nom::Err::Error(nom::error::Error { input, .. }))
-                | Err(nom::Err::Failure(nom::error::Error { input, .. })) => {
-                    return Err(format!(
-                        "Error while parsing `bye` instruction near `{}`",
-                        input
-                    ))
+            if test {
+                if inputs.is_empty() {
+                    inputs.push(Vec::new());
                 }
-                Err(nom::Err::Incomplete(_)) => {
-                    return Err("Error while parsing `bye`, incomplete data.".to_owned())
+                let compared = compile::test(&program, target, &inputs)?;
+                println!("{} of {} inputs agree", compared, inputs.len());
+                return Ok(());
+            }
+
+            let bytes = compile::compile(&program, target)?;
+            match output {
+                Some(output) => {
+                    fs::write(&output, bytes).map_err(|_| format!("Could not write {}", output))?;
+                    if target == compile::Target::Elf {
+                        fs::set_permissions(&output, fs::Permissions::from_mode(0o755))
+                            .map_err(|_| format!("Could not make {} executable", output))?;
+                    }
+                    Ok(())
                 }
-            };
-            if !input.is_empty() {
-                return Err(format!("Unexpected characters: `{}`", input));
-            }
-
-            let src = str_to_u8(src)?;
-
-            Instruction::Bye {
-                code: match is_mem {
-                    Some(_) => Value::Memory { addr: src },
-                    None => Value::Literal { val: src },
-                },
-            }
-        }
-
-        "nop" => {
-            let (input, _) = match opt(&space)(input) {
-                Ok(val) => val,
-                Err(nom::Err::Error(nom::error::Error { input, .. }))
-                | Err(nom::Err::Failure(nom::error::Error { input, .. })) => {
-                    return Err(format!(
-                        "Error while parsing `nop` instruction near `{}`",
-                        input
-                    ))
+                None if matches!(target, compile::Target::Elf | compile::Target::Wasm) => {
+                    Err("Binary targets need an output file with -o".to_owned())
                 }
-                Err(nom::Err::Incomplete(_)) => {
-                    return Err("Error while parsing `nop`, incomplete data.".to_owned())
+                None => {
+                    print!("{}", String::from_utf8_lossy(&bytes));
+                    Ok(())
                 }
-            };
-            if !input.is_empty() {
-                return Err(format!("Unexpected characters: `{}`", input));
             }
-
-            Instruction::Nop
         }
-
-        _ => return Err(format!("Unknown instruction `{name}`")),
-    };
-
-    Ok(inst)
-}
-
-fn get_instructions(path: &str) -> Result<Vec<Instruction>, String> {
-    let mut instructions = Vec::new();
-
-    if let Ok(content) = fs::read_to_string(path) {
-        for (idx, line) in content.split('\n').enumerate() {
-            // Let's ignore comments
-            if let Some('#') = line.chars().next() {
-                continue;
-            }
-            if line.is_empty() {
-                continue;
-            };
-
-            match parse_instruction(line.split('#').next().expect("Should always be a string")) {
-                Ok(inst) => instructions.push(inst),
-                Err(val) => return Err(format!("Error on line {}: {}", idx + 1, val)),
+        Some("from-bf") => {
+            let mut output = None;
+            let mut check = false;
+            let mut files = Vec::new();
+
+            let mut rest = args[1..].iter();
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "-o" => output = Some(flag_value::<String>(arg, &mut rest)?),
+                    "--check" => check = true,
+                    _ => files.push(arg),
+                }
             }
-        }
-    } else {
-        return Err("Could not read file".to_owned());
-    }
-
-    Ok(instructions)
-}
 
-fn main() -> Result<(), String> {
-    // Get instructions first
-    let instructions = match env::args().nth(1) {
-        Some(file) => match get_instructions(&file) {
-            Ok(insts) => insts,
-            Err(msg) => return Err(msg),
-        },
-        None => return Err("Please provide a filename as an argument".to_owned()),
-    };
-
-    // Idk what the user is doing but whatever
-    if instructions.is_empty() {
-        return Ok(());
-    }
-
-    let mut memory: [Wrapping<u8>; 255] = [Wrapping(0u8); 255];
+            if files.is_empty() {
+                return Err("Please provide a filename as an argument".to_owned());
+            }
+
+            if check {
+                let mut failed = false;
+                for file in files {
+                    let source =
+                        fs::read_to_string(file).map_err(|_| format!("Could not read {}", file))?;
+                    let path = Path::new(file);
+                    // The input is optional, the expected output isn't
+                    let input = fs::read(path.with_extension("in")).unwrap_or_default();
+                    let expected = fs::read(path.with_extension("out")).map_err(|_| {
+                        format!("Could not read {}", path.with_extension("out").display())
+                    })?;
+
+                    match bf::run(&source, &input) {
+                        Ok(got) if got == expected => println!("{}: ok", file),
+                        Ok(got) => {
+                            failed = true;
+                            println!(
+                                "{}: expected {:?}, got {:?}",
+                                file,
+                                String::from_utf8_lossy(&expected),
+                                String::from_utf8_lossy(&got)
+                            );
+                        }
+                        Err(msg) => {
+                            failed = true;
+                            println!("{}: {}", file, msg);
+                        }
+                    }
+                }
 
-    // Little macro to grab a value from memory, or as a literal.
-    // It means i can just use this on all the spots.
-    macro_rules! get_val {
-        ($mem:expr, $val:expr) => {{
-            match $val {
-                Value::Literal { val } => *val,
-                Value::Memory { addr } => $mem[*addr as usize].0,
+                if failed {
+                    std::process::exit(1);
+                }
+                return Ok(());
             }
-        }};
-    }
 
-    macro_rules! get_addr {
-        ($val:expr) => {{
-            // The parser should ensure this
-            match $val {
-                Value::Memory { addr } => *addr,
-                _ => unreachable!(),
+            if files.len() > 1 {
+                return Err("Only one file at a time, unless it's --check".to_owned());
             }
-        }};
-    }
+            let file = files[0];
+            let source =
+                fs::read_to_string(file).map_err(|_| format!("Could not read {}", file))?;
+            let compiled = bf::compile(&source).map_err(|msg| format!("{}:{}", file, msg))?;
+            let text = formatter::format_lines(
+                &bf::to_lines(&compiled),
+                &formatter::FormatConfig::default(),
+            );
 
-    while match instructions
-        .get(memory[0].0 as usize)
-        .expect("Instruction pointer should remain valid")
-    {
-        Instruction::Bye { .. } => false,
-        _ => true,
-    } {
-        match instructions
-            .get(memory[0].0 as usize)
-            .expect("Instruction pointer should remain valid")
-        {
-            // Sets memory
-            Instruction::Set { src, tgt } => {
-                memory[get_addr!(tgt) as usize] = Wrapping(get_val!(memory, src))
-            }
-            // Bitwise and
-            Instruction::And { left, right, tgt } => {
-                memory[get_addr!(tgt) as usize] =
-                    Wrapping(get_val!(memory, left) & get_val!(memory, right))
-            }
-            // Bitwise xor
-            Instruction::Xor { left, right, tgt } => {
-                memory[get_addr!(tgt) as usize] =
-                    Wrapping(get_val!(memory, left) ^ get_val!(memory, right))
-            }
-            // Boolean not
-            Instruction::Not { src, tgt } => {
-                memory[get_addr!(tgt) as usize] = if get_val!(memory, src) == 0 {
-                    Wrapping(1)
-                } else {
-                    Wrapping(0)
-                }
-            }
-            // Add left + right
-            Instruction::Add { left, right, tgt } => {
-                memory[get_addr!(tgt) as usize] =
-                    Wrapping(get_val!(memory, left) + get_val!(memory, right))
-            }
-            // Substract left - right
-            Instruction::Sub { left, right, tgt } => {
-                memory[get_addr!(tgt) as usize] =
-                    Wrapping(get_val!(memory, left) - get_val!(memory, right))
-            }
-            // Print out as a character
-            Instruction::Out { src } => {
-                print!("{}", get_val!(memory, src) as char);
-                std::io::stdout().flush().expect("IO errror");
-            }
-            // Print out as a number
-            Instruction::Num { src } => {
-                print!("{}", get_val!(memory, src));
-                std::io::stdout().flush().expect("IO errror");
-            }
-            // Take in a character
-            Instruction::Cin { tgt } => {
-                memory[get_addr!(tgt) as usize] = if let Some(val) = std::io::stdin().bytes().next()
-                {
-                    Wrapping(val.expect("IO error"))
-                } else {
-                    return Err("EOF while reading input".to_owned());
+            match output {
+                Some(output) => {
+                    fs::write(&output, text).map_err(|_| format!("Could not write {}", output))
                 }
-            }
-            // Take in a number
-            Instruction::Nin { tgt } => {
-                memory[get_addr!(tgt) as usize] = match {
-                    let mut buf = String::new();
-                    std::io::stdin().read_line(&mut buf).expect("IO error");
-                    buf.trim().parse::<u8>()
-                } {
-                    Ok(val) => Wrapping(val),
-                    Err(_) => return Err("Invalid unsigned 8-bit integer".to_owned()),
+                None => {
+                    print!("{}", text);
+                    Ok(())
                 }
             }
-            // Skip backward
-            Instruction::Bak { count, check } => {
-                if get_val!(memory, check) == 0 {
-                    memory[0] -= get_val!(memory, count);
-                    continue;
+        }
+        Some("repl") => {
+            let code = repl::run(&source)?;
+            std::process::exit(code as i32)
+        }
+        Some("std") => match args.get(1) {
+            Some(name) => {
+                let content = stdlib::get(name)
+                    .ok_or_else(|| format!("There's no `{}` in the library", name))?;
+                print!("{}", content);
+                Ok(())
+            }
+            None => {
+                println!("Standard library version {}", stdlib::VERSION);
+                for (name, content) in stdlib::FILES {
+                    println!(
+                        "  {}{:<14}{}",
+                        stdlib::PREFIX,
+                        name,
+                        stdlib::summary(content)
+                    );
                 }
+                Ok(())
             }
-            // Skip forward
-            Instruction::Fwd { count, check } => {
-                if get_val!(memory, check) == 0 {
-                    memory[0] += get_val!(memory, count);
-                    continue;
+        },
+        Some(_) => {
+            let mut config = MachineConfig::default();
+            let mut threaded = false;
+            let mut file = None;
+
+            let mut rest = args.iter();
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "--max-steps" => config.max_steps = Some(flag_value(arg, &mut rest)?),
+                    "--threaded" => threaded = true,
+                    "--timeout" => {
+                        let secs: f64 = flag_value(arg, &mut rest)?;
+                        if secs.is_nan() || secs <= 0.0 {
+                            return Err("--timeout needs a positive number".to_owned());
+                        }
+                        // Anything left that doesn't fit is just too big
+                        config.timeout = Some(
+                            Duration::try_from_secs_f64(secs)
+                                .map_err(|_| "--timeout is too large".to_owned())?,
+                        );
+                    }
+                    _ => file = Some(arg),
                 }
             }
-            // Should be caught in the while's condition
-            Instruction::Bye { .. } => unreachable!(),
-            // No operation
-            Instruction::Nop => {}
-        };
 
-        memory[0] += 1;
-    }
+            // Get instructions first
+            let file = file.ok_or("Please provide a filename as an argument")?;
+            let program = bytecode::load_with(file, &source)?;
+            let instructions = &program.instructions;
 
-    match instructions
-        .get(memory[0].0 as usize)
-        .expect("Instruction pointer should remain valid")
-    {
-        Instruction::Bye { code } => std::process::exit(match code {
-            Value::Literal { val } => *val as i32,
-            Value::Memory { addr } => memory[*addr as usize].0 as i32,
-        }),
-        _ => unreachable!(),
+            // Idk what the user is doing but whatever
+            if instructions.is_empty() {
+                return Ok(());
+            }
+
+            let mut machine = Machine::new(config);
+            machine.memory = program.memory();
+            let result = if threaded {
+                machine.run_threaded(&Threaded::compile(instructions))
+            } else {
+                machine.run(instructions)
+            };
+
+            // The IP is still on whatever went wrong
+            let at = machine.ip() as usize;
+            match result {
+                Ok(code) => std::process::exit(code as i32),
+                Err(RunError::Fault(msg)) => Err(program.error_at(at, msg)),
+                Err(err) => {
+                    eprintln!("\n{}", program.error_at(at, err));
+                    std::process::exit(LIMIT_EXIT_CODE)
+                }
+            }
+        }
+        None => Err("Please provide a filename as an argument".to_owned()),
     }
 }
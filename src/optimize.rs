@@ -0,0 +1,592 @@
+use crate::cfg::{Cfg, Target};
+use crate::parser::{Instruction, Value};
+use crate::program::Program;
+use std::num::Wrapping;
+
+/// One flag per memory cell.
+type Cells = [bool; 256];
+
+/// Gives up after this many rounds, it should settle way before that.
+const MAX_ROUNDS: usize = 32;
+
+/// The part of a program that has to stay exactly where it is.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Pinned {
+    /// Nothing before this can go, so everything up to here keeps its index.
+    before: usize,
+    /// Whether something can land on any of those instructions, so nothing
+    /// is known about memory at any of them.
+    anywhere: bool,
+    /// Why, if anything is pinned.
+    reason: Option<String>,
+}
+
+/// Works out what can't move. Reading `m0` gives away where the instruction
+/// is, so nothing before it can go. A jump by a memory value or a write to
+/// `m0` could land on any of the first 256 instructions, since the IP wraps,
+/// so all of those stay put and get treated as if they were jumped to. Going
+/// from 255 back to 0 pins all of them too, just without the jumping.
+/// Only counts what can actually run.
+fn pinned(instructions: &[Instruction], cfg: &Cfg) -> Pinned {
+    let mut pinned = Pinned::default();
+    let mut seen = vec![false; cfg.blocks.len()];
+    let mut todo = vec![0];
+
+    while let Some(n) = todo.pop() {
+        if n >= cfg.blocks.len() || seen[n] {
+            continue;
+        }
+        seen[n] = true;
+
+        let block = cfg.blocks[n];
+        if let Some(idx) =
+            (block.start..block.end).rfind(|idx| instructions[*idx].reads().contains(&0))
+        {
+            if idx + 1 > pinned.before && !pinned.anywhere {
+                pinned.before = idx + 1;
+                pinned.reason = Some(format!("instruction {} reads m0", idx));
+            }
+        }
+
+        for edge in cfg.edges.iter().filter(|edge| edge.from == n) {
+            match edge.to {
+                // Wrapping back around only works while 255 is still 255
+                Target::Block(0) if block.end == 256 && !pinned.anywhere => {
+                    pinned.before = 256;
+                    pinned.reason = Some("instruction 255 wraps back around to 0".to_owned());
+                    todo.push(0);
+                }
+                Target::Block(to) => todo.push(to),
+                Target::Unknown if !pinned.anywhere => {
+                    pinned.before = 256;
+                    pinned.anywhere = true;
+                    pinned.reason = Some(format!(
+                        "instruction {} jumps somewhere that can't be known ahead of time",
+                        block.end - 1
+                    ));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pinned.before = pinned.before.min(instructions.len());
+    pinned
+}
+
+/// Swaps in the known value of any cell that gets read.
+fn substitute(inst: &Instruction, known: &[Option<u8>; 256]) -> Instruction {
+    let sub = |val: &Value| match val {
+        Value::Memory { addr } => match known[*addr as usize] {
+            Some(val) if *addr != 0 => Value::Literal { val },
+            _ => *val,
+        },
+        Value::Literal { .. } => *val,
+    };
+
+    match inst {
+        Instruction::Set { src, tgt } => Instruction::Set {
+            src: sub(src),
+            tgt: *tgt,
+        },
+        Instruction::Not { src, tgt } => Instruction::Not {
+            src: sub(src),
+            tgt: *tgt,
+        },
+        Instruction::And { left, right, tgt } => Instruction::And {
+            left: sub(left),
+            right: sub(right),
+            tgt: *tgt,
+        },
+        Instruction::Xor { left, right, tgt } => Instruction::Xor {
+            left: sub(left),
+            right: sub(right),
+            tgt: *tgt,
+        },
+        Instruction::Add { left, right, tgt } => Instruction::Add {
+            left: sub(left),
+            right: sub(right),
+            tgt: *tgt,
+        },
+        Instruction::Sub { left, right, tgt } => Instruction::Sub {
+            left: sub(left),
+            right: sub(right),
+            tgt: *tgt,
+        },
+        Instruction::Out { src } => Instruction::Out { src: sub(src) },
+        Instruction::Num { src } => Instruction::Num { src: sub(src) },
+        Instruction::Bak { count, check } => Instruction::Bak {
+            count: sub(count),
+            check: sub(check),
+        },
+        Instruction::Fwd { count, check } => Instruction::Fwd {
+            count: sub(count),
+            check: sub(check),
+        },
+        Instruction::Bye { code } => Instruction::Bye { code: sub(code) },
+        Instruction::Cin { .. } | Instruction::Nin { .. } | Instruction::Nop => *inst,
+    }
+}
+
+/// Turns arithmetic on literals into a plain `set`.
+fn fold(inst: &Instruction) -> Instruction {
+    use Value::Literal as L;
+
+    let val = match inst {
+        Instruction::Not { src: L { val }, .. } => (*val == 0) as u8,
+        Instruction::And {
+            left: L { val: l },
+            right: L { val: r },
+            ..
+        } => l & r,
+        Instruction::Xor {
+            left: L { val: l },
+            right: L { val: r },
+            ..
+        } => l ^ r,
+        Instruction::Add {
+            left: L { val: l },
+            right: L { val: r },
+            ..
+        } => (Wrapping(*l) + Wrapping(*r)).0,
+        Instruction::Sub {
+            left: L { val: l },
+            right: L { val: r },
+            ..
+        } => (Wrapping(*l) - Wrapping(*r)).0,
+        _ => return *inst,
+    };
+
+    Instruction::Set {
+        src: L { val },
+        tgt: Value::Memory {
+            addr: inst.write().expect("Arithmetic always saves somewhere"),
+        },
+    }
+}
+
+/// Instructions that can't possibly do anything, whatever's in memory.
+fn is_noop(inst: &Instruction) -> bool {
+    use Value::{Literal as L, Memory as M};
+
+    match *inst {
+        Instruction::Nop => true,
+        Instruction::Set {
+            src: M { addr: a },
+            tgt: M { addr: t },
+        } => a == t,
+        Instruction::Add {
+            left: M { addr: a },
+            right: L { val: 0 },
+            tgt: M { addr: t },
+        }
+        | Instruction::Add {
+            left: L { val: 0 },
+            right: M { addr: a },
+            tgt: M { addr: t },
+        }
+        | Instruction::Sub {
+            left: M { addr: a },
+            right: L { val: 0 },
+            tgt: M { addr: t },
+        }
+        | Instruction::Xor {
+            left: M { addr: a },
+            right: L { val: 0 },
+            tgt: M { addr: t },
+        }
+        | Instruction::Xor {
+            left: L { val: 0 },
+            right: M { addr: a },
+            tgt: M { addr: t },
+        }
+        | Instruction::And {
+            left: M { addr: a },
+            right: L { val: 255 },
+            tgt: M { addr: t },
+        }
+        | Instruction::And {
+            left: L { val: 255 },
+            right: M { addr: a },
+            tgt: M { addr: t },
+        } => a == t,
+        // Never taken. Careful, a taken `fwd 0` or `bak 0` sits there forever,
+        // so those only go if the check is known to not be 0.
+        Instruction::Bak {
+            check: L { val }, ..
+        }
+        | Instruction::Fwd {
+            check: L { val }, ..
+        } if val != 0 => true,
+        // Lands on the next instruction either way
+        Instruction::Fwd {
+            count: L { val: 1 },
+            ..
+        } => true,
+        _ => false,
+    }
+}
+
+/// Only these can go if nobody reads what they save. `cin` and `nin` eat
+/// input, so they have to stay.
+fn is_pure(inst: &Instruction) -> bool {
+    matches!(
+        inst,
+        Instruction::Set { .. }
+            | Instruction::And { .. }
+            | Instruction::Xor { .. }
+            | Instruction::Not { .. }
+            | Instruction::Add { .. }
+            | Instruction::Sub { .. }
+    )
+}
+
+/// Folds constants within each block and finds the instructions that do
+/// nothing. Marks the ones to delete in `dead`. `landing` is how many
+/// instructions at the start could be jumped to from anywhere.
+fn fold_blocks(
+    instructions: &mut [Instruction],
+    data: &[(u8, u8)],
+    cfg: &Cfg,
+    landing: usize,
+    dead: &mut [bool],
+) {
+    let entered = landing > 0 || cfg.edges.iter().any(|edge| edge.to == Target::Block(0));
+
+    for (n, block) in cfg.blocks.iter().enumerate() {
+        // Memory starts zeroed apart from the data, but a block that can be
+        // jumped to could have come from anywhere
+        let mut known = if n == 0 && !entered {
+            let mut known = [Some(0); 256];
+            for (addr, val) in data {
+                known[*addr as usize] = Some(*val);
+            }
+            known
+        } else {
+            [None; 256]
+        };
+
+        for idx in block.start..block.end {
+            if idx < landing {
+                known = [None; 256];
+            }
+            let inst = fold(&substitute(&instructions[idx], &known));
+
+            if let Some(addr) = inst.write() {
+                let val = match inst {
+                    Instruction::Set {
+                        src: Value::Literal { val },
+                        ..
+                    } => Some(val),
+                    _ => None,
+                };
+                // Saving what's already there
+                if val.is_some() && known[addr as usize] == val {
+                    dead[idx] = true;
+                }
+                known[addr as usize] = val;
+            }
+
+            if is_noop(&inst) {
+                dead[idx] = true;
+            }
+            instructions[idx] = inst;
+        }
+    }
+}
+
+/// Marks stores that get overwritten or forgotten before anything reads them.
+fn dead_stores(instructions: &[Instruction], cfg: &Cfg, dead: &mut [bool]) {
+    // Already on their way out, so they don't count
+    let gone = dead.to_vec();
+    let mut live_in = vec![[false; 256]; cfg.blocks.len()];
+
+    // Goes backwards through a block, marking dead stores if asked to
+    let walk = |n: usize, live_in: &[Cells], dead: Option<&mut [bool]>| -> Cells {
+        let mut live = [false; 256];
+        for edge in cfg.edges.iter().filter(|edge| edge.from == n) {
+            match edge.to {
+                Target::Block(to) => {
+                    for (cell, from) in live.iter_mut().zip(&live_in[to]) {
+                        *cell |= from;
+                    }
+                }
+                // Whatever it lands on could read anything
+                Target::Unknown => live = [true; 256],
+                _ => {}
+            }
+        }
+
+        let mut dead = dead;
+        let block = cfg.blocks[n];
+        for idx in (block.start..block.end).rev() {
+            if gone[idx] {
+                continue;
+            }
+            let inst = &instructions[idx];
+            if let Some(addr) = inst.write() {
+                if is_pure(inst) && !live[addr as usize] {
+                    if let Some(dead) = dead.as_deref_mut() {
+                        dead[idx] = true;
+                    }
+                    continue;
+                }
+                live[addr as usize] = false;
+            }
+            for addr in inst.reads() {
+                live[addr as usize] = true;
+            }
+        }
+        live
+    };
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for n in (0..cfg.blocks.len()).rev() {
+            let live = walk(n, &live_in, None);
+            if live != live_in[n] {
+                live_in[n] = live;
+                changed = true;
+            }
+        }
+    }
+
+    for n in 0..cfg.blocks.len() {
+        walk(n, &live_in, Some(dead));
+    }
+}
+
+/// Takes out the dead instructions and fixes up every `bak`/`fwd` count so
+/// it lands in the same place it used to.
+fn remove(instructions: &[Instruction], dead: &[bool]) -> Vec<Instruction> {
+    // How many live instructions come before each spot. That's the new index
+    // for live ones, and the next live one for dead ones.
+    let mut before = Vec::with_capacity(instructions.len() + 1);
+    let mut count = 0usize;
+    for is_dead in dead {
+        before.push(count);
+        count += !is_dead as usize;
+    }
+    let new_pos = |idx: usize| match before.get(idx) {
+        Some(pos) => *pos,
+        // Past the end stays past the end, just by less
+        None => idx - (instructions.len() - count),
+    };
+
+    instructions
+        .iter()
+        .enumerate()
+        .filter(|(idx, _)| !dead[*idx])
+        .map(|(idx, inst)| {
+            let target = match inst.jump_target(idx) {
+                Some(target) => new_pos(target),
+                None => return *inst,
+            };
+            let here = new_pos(idx);
+            match *inst {
+                Instruction::Bak { check, .. } => Instruction::Bak {
+                    count: Value::Literal {
+                        val: (here as u8).wrapping_sub(target as u8),
+                    },
+                    check,
+                },
+                Instruction::Fwd { check, .. } => Instruction::Fwd {
+                    count: Value::Literal {
+                        val: (target as u8).wrapping_sub(here as u8),
+                    },
+                    check,
+                },
+                _ => unreachable!(),
+            }
+        })
+        .collect()
+}
+
+/// Folds constants and gets rid of instructions that don't do anything,
+/// keeping every jump pointing at the same place. Also gives back why some of
+/// it had to stay where it was, if any did.
+pub fn optimize(original: &Program) -> (Program, Option<String>) {
+    let mut program = original.instructions.clone();
+    let mut source_map = original.source_map.clone();
+
+    // Nothing before it goes, so it's the same every round
+    let pinned = pinned(&program, &Cfg::build(&program));
+    let landing = if pinned.anywhere { pinned.before } else { 0 };
+
+    for _ in 0..MAX_ROUNDS {
+        let cfg = Cfg::build(&program);
+        let mut dead = vec![false; program.len()];
+        let mut folded = program.clone();
+
+        fold_blocks(&mut folded, &original.data, &cfg, landing, &mut dead);
+        dead[..pinned.before].fill(false);
+        dead_stores(&folded, &cfg, &mut dead);
+        dead[..pinned.before].fill(false);
+
+        let next = remove(&folded, &dead);
+        if next == program {
+            break;
+        }
+        program = next;
+        // Folding leaves everything where it was, so only the dead ones go
+        if source_map.len() == dead.len() {
+            source_map = source_map
+                .into_iter()
+                .zip(&dead)
+                .filter(|(_, dead)| !**dead)
+                .map(|(at, _)| at)
+                .collect();
+        }
+    }
+
+    let optimized = Program {
+        instructions: program,
+        data: original.data.clone(),
+        source_map,
+    };
+    (optimized, pinned.reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_instruction;
+
+    fn parse(lines: &[&str]) -> Vec<Instruction> {
+        lines
+            .iter()
+            .map(|line| parse_instruction(line).unwrap())
+            .collect()
+    }
+
+    /// Takes out every `nop` and gives back what's left.
+    fn remove_nops(lines: &[&str]) -> Vec<Instruction> {
+        let instructions = parse(lines);
+        let dead: Vec<bool> = instructions
+            .iter()
+            .map(|inst| *inst == Instruction::Nop)
+            .collect();
+        remove(&instructions, &dead)
+    }
+
+    #[test]
+    fn jump_across_removed() {
+        assert_eq!(
+            remove_nops(&["fwd 3, m1", "nop", "nop", "out 1", "bak 3, m1"]),
+            parse(&["fwd 1, m1", "out 1", "bak 1, m1"])
+        );
+    }
+
+    #[test]
+    fn jump_onto_removed() {
+        // Lands on whatever came after it instead
+        assert_eq!(
+            remove_nops(&["fwd 2, m1", "out 1", "nop", "nop", "out 2"]),
+            parse(&["fwd 2, m1", "out 1", "out 2"])
+        );
+        assert_eq!(
+            remove_nops(&["nop", "out 1", "bak 2, m1"]),
+            parse(&["out 1", "bak 1, m1"])
+        );
+    }
+
+    #[test]
+    fn fwd_past_the_end() {
+        assert_eq!(
+            remove_nops(&["fwd 5, m1", "nop", "out 1"]),
+            parse(&["fwd 4, m1", "out 1"])
+        );
+    }
+
+    #[test]
+    fn bak_to_the_start() {
+        assert_eq!(
+            remove_nops(&["out 1", "nop", "nop", "bak 3, m1"]),
+            parse(&["out 1", "bak 1, m1"])
+        );
+    }
+
+    fn optimize_lines(lines: &[&str]) -> (Vec<Instruction>, Option<String>) {
+        let (program, reason) = optimize(&Program::from(parse(lines)));
+        (program.instructions, reason)
+    }
+
+    #[test]
+    fn nothing_pinned() {
+        assert_eq!(
+            optimize_lines(&["nop", "add 1, 2 -> m1", "num m1", "nop", "bye 0"]),
+            (parse(&["num 3", "bye 0"]), None)
+        );
+    }
+
+    #[test]
+    fn reading_m0_only_pins_what_comes_before() {
+        assert_eq!(
+            optimize_lines(&["nop", "num m0", "nop", "out 1"]),
+            (
+                parse(&["nop", "num m0", "out 1"]),
+                Some("instruction 1 reads m0".to_owned())
+            )
+        );
+    }
+
+    #[test]
+    fn dynamic_jump_still_folds() {
+        // Could land on any of them, so they all stay, but literals still fold
+        let (instructions, reason) =
+            optimize_lines(&["nin -> m1", "nop", "add 1, 2 -> m2", "num m2", "bak m1, 0"]);
+        assert_eq!(
+            instructions,
+            parse(&["nin -> m1", "nop", "set 3 -> m2", "num m2", "bak m1, 0"])
+        );
+        assert!(reason.unwrap().starts_with("instruction 4 jumps"));
+    }
+
+    #[test]
+    fn dynamic_jump_pins_first_256() {
+        let mut lines = vec!["nin -> m1", "fwd m1, 0"];
+        lines.resize(300, "nop");
+        lines.push("bye 0");
+        let (instructions, _) = optimize_lines(&lines);
+        assert_eq!(instructions.len(), 257);
+        assert_eq!(instructions[255], Instruction::Nop);
+        assert_eq!(instructions[256], parse_instruction("bye 0").unwrap());
+    }
+
+    #[test]
+    fn unreachable_dynamic_jump() {
+        assert_eq!(optimize_lines(&["nop", "bye 0", "bak m1, 0"]).1, None);
+    }
+
+    #[test]
+    fn ip_wraps_after_255() {
+        // Goes round twice, printing 1 and 2, and never gets to the `bye 9`
+        let mut lines = vec![
+            "add m1, 1 -> m1",
+            "num m1",
+            "sub m1, 2 -> m2",
+            "fwd 2, m2",
+            "fwd 2, 0",
+            "bye 7",
+        ];
+        lines.resize(256, "nop");
+        lines.push("bye 9");
+
+        let (instructions, reason) = optimize_lines(&lines);
+        assert_eq!(instructions, parse(&lines));
+        assert_eq!(
+            reason,
+            Some("instruction 255 wraps back around to 0".to_owned())
+        );
+    }
+
+    #[test]
+    fn ip_wraps_with_memory_from_last_time() {
+        // The second time round m1 isn't 0 any more, so the `num` can't fold
+        let mut lines = vec!["num m1", "fwd 2, m1", "set 1 -> m1"];
+        lines.resize(256, "nop");
+        let (instructions, _) = optimize_lines(&lines);
+        assert_eq!(instructions[0], parse_instruction("num m1").unwrap());
+    }
+}
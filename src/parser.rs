@@ -0,0 +1,752 @@
+use crate::asm;
+use crate::program::Program;
+use crate::source::{self, SourceConfig};
+use nom::{
+    bytes::complete::{tag, take_while1},
+    character::{complete::char, is_alphabetic, is_digit},
+    combinator::opt,
+    sequence::tuple,
+};
+use std::fmt::Display;
+
+/*
+* Instructions: (where a is any and m is memory)
+* set a, -> m
+* add a, a -> m
+* sub a, a -> m
+* out a
+* num a
+* cin -> m
+* nin -> m
+* bak a, a
+* fwd a, a
+* bye a
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value {
+    Memory { addr: u8 },
+    Literal { val: u8 },
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Memory { addr } => write!(f, "m{}", addr),
+            Value::Literal { val } => write!(f, "{}", val),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Set {
+        src: Value,
+        tgt: Value,
+    },
+    And {
+        left: Value,
+        right: Value,
+        tgt: Value,
+    },
+    Xor {
+        left: Value,
+        right: Value,
+        tgt: Value,
+    },
+    Not {
+        src: Value,
+        tgt: Value,
+    },
+    Add {
+        left: Value,
+        right: Value,
+        tgt: Value,
+    },
+    Sub {
+        left: Value,
+        right: Value,
+        tgt: Value,
+    },
+    Out {
+        src: Value,
+    },
+    Num {
+        src: Value,
+    },
+    Cin {
+        tgt: Value,
+    },
+    Nin {
+        tgt: Value,
+    },
+    Bak {
+        count: Value,
+        check: Value,
+    },
+    Fwd {
+        count: Value,
+        check: Value,
+    },
+    Bye {
+        code: Value,
+    },
+    Nop,
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Instruction::Set { src, tgt } => write!(f, "set {} -> {}", src, tgt),
+            Instruction::Add { left, right, tgt } => {
+                write!(f, "add {}, {} -> {}", left, right, tgt)
+            }
+            Instruction::Sub { left, right, tgt } => {
+                write!(f, "sub {}, {} -> {}", left, right, tgt)
+            }
+            Instruction::Out { src } => write!(f, "out {}", src),
+            Instruction::Num { src } => write!(f, "num {}", src),
+            Instruction::Cin { tgt } => write!(f, "cin -> {}", tgt),
+            Instruction::Nin { tgt } => write!(f, "nin -> {}", tgt),
+            Instruction::Bak { count, check } => write!(f, "bak {}, {}", count, check),
+            Instruction::Fwd { count, check } => write!(f, "fwd {}, {}", count, check),
+            Instruction::Bye { code } => write!(f, "bye {}", code),
+            Instruction::And { left, right, tgt } => {
+                write!(f, "and {}, {} -> {}", left, right, tgt)
+            }
+            Instruction::Xor { left, right, tgt } => {
+                write!(f, "xor {}, {} -> {}", left, right, tgt)
+            }
+            Instruction::Not { src, tgt } => write!(f, "not {} -> {}", src, tgt),
+            Instruction::Nop => write!(f, "nop"),
+        }
+    }
+}
+
+impl Instruction {
+    /// The operands that get read, in the order they're written.
+    pub fn operands(&self) -> Vec<&Value> {
+        match self {
+            Instruction::Set { src, .. }
+            | Instruction::Not { src, .. }
+            | Instruction::Out { src }
+            | Instruction::Num { src } => vec![src],
+            Instruction::And { left, right, .. }
+            | Instruction::Xor { left, right, .. }
+            | Instruction::Add { left, right, .. }
+            | Instruction::Sub { left, right, .. } => vec![left, right],
+            Instruction::Bak { count, check } | Instruction::Fwd { count, check } => {
+                vec![count, check]
+            }
+            Instruction::Bye { code } => vec![code],
+            Instruction::Cin { .. } | Instruction::Nin { .. } | Instruction::Nop => vec![],
+        }
+    }
+
+    /// Memory addresses this reads from.
+    pub fn reads(&self) -> Vec<u8> {
+        self.operands()
+            .into_iter()
+            .filter_map(|val| match val {
+                Value::Memory { addr } => Some(*addr),
+                Value::Literal { .. } => None,
+            })
+            .collect()
+    }
+
+    /// The memory address this saves to, if any.
+    pub fn write(&self) -> Option<u8> {
+        match self {
+            Instruction::Set { tgt, .. }
+            | Instruction::And { tgt, .. }
+            | Instruction::Xor { tgt, .. }
+            | Instruction::Not { tgt, .. }
+            | Instruction::Add { tgt, .. }
+            | Instruction::Sub { tgt, .. }
+            | Instruction::Cin { tgt }
+            | Instruction::Nin { tgt } => match tgt {
+                Value::Memory { addr } => Some(*addr),
+                Value::Literal { .. } => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Where a `bak`/`fwd` at `idx` lands, if the count is known ahead of
+    /// time. The IP is a byte, so this wraps the same way it does at runtime.
+    pub fn jump_target(&self, idx: usize) -> Option<usize> {
+        match self {
+            Instruction::Bak {
+                count: Value::Literal { val },
+                ..
+            } => Some((idx as u8).wrapping_sub(*val) as usize),
+            Instruction::Fwd {
+                count: Value::Literal { val },
+                ..
+            } => Some((idx as u8).wrapping_add(*val) as usize),
+            _ => None,
+        }
+    }
+}
+
+/// A line of source, comments and all.
+#[derive(Debug, Clone)]
+pub enum Line {
+    Blank,
+    /// A line that's only a comment, without the `#`.
+    Comment(String),
+    /// An instruction, and the comment after it (without the `#`) if any.
+    Instruction(Instruction, Option<String>),
+    /// Something the assembler deals with before the parser sees it, like a
+    /// directive, a label or a macro call. As written, and its comment.
+    Asm(String, Option<String>),
+}
+
+/// Little convenience function for converting strs into u8s and being able to use a ?
+#[inline]
+fn str_to_u8(s: &str) -> Result<u8, &'static str> {
+    match s.parse::<u8>() {
+        Ok(val) => Ok(val),
+        Err(_) => Err("Error parsing integer"),
+    }
+}
+
+pub fn parse_instruction(input: &str) -> Result<Instruction, String> {
+    // Matches any alpha word
+    let word = take_while1(|c: char| is_alphabetic(c as u8));
+    // Matches any series of numbers
+    let num = take_while1(|c: char| is_digit(c as u8));
+    // Matches just 'm'
+    let mem = char::<&str, nom::error::Error<&str>>('m');
+    // Matches any number of spaces
+    let space = take_while1(|c| c == ' ');
+    // Matches a comma with any number of spaces on either side
+    let sep = tuple((opt(&space), char(','), opt(&space)));
+    // Matches an arrow with any number of spaces on either side
+    let arrow = tuple((opt(&space), tag("->"), opt(&space)));
+
+    let (input, (name, _)) = match tuple((word, opt(&space)))(input) {
+        Ok(val) => val,
+        Err(nom::Err::Error(nom::error::Error { input, .. }))
+        | Err(nom::Err::Failure(nom::error::Error { input, .. })) => {
+            return Err(format!("Not a valid instruction name: `{}`", input))
+        }
+        Err(nom::Err::Incomplete(_)) => {
+            return Err("Error while parsing instruction name, incomplete data.".to_owned())
+        }
+    };
+
+    let inst = match name {
+        "set" => {
+            let (input, (is_mem, src, _, _, tgt, _)) =
+                match tuple((opt(&mem), &num, arrow, &mem, &num, opt(&space)))(input) {
+                    Ok(val) => val,
+                    Err(nom::Err::Error(nom::error::Error { input, .. }))
+                    | Err(nom::Err::Failure(nom::error::Error { input, .. })) => {
+                        return Err(format!(
+                            "Error while parsing `set` instruction near `{}`",
+                            input
+                        ))
+                    }
+                    Err(nom::Err::Incomplete(_)) => {
+                        return Err("Error while parsing `set`, incomplete data.".to_owned())
+                    }
+                };
+            if !input.is_empty() {
+                return Err(format!("Unexpected characters: `{}`", input));
+            }
+            let src = str_to_u8(src)?;
+            let tgt = str_to_u8(tgt)?;
+
+            Instruction::Set {
+                src: match is_mem {
+                    Some(_) => Value::Memory { addr: src },
+                    None => Value::Literal { val: src },
+                },
+                tgt: Value::Memory { addr: tgt },
+            }
+        }
+
+        "and" => {
+            let (input, (is_mem_l, src_l, _, is_mem_r, src_r, _, _, tgt, _)) = match tuple((
+                opt(&mem),
+                &num,
+                sep,
+                opt(&mem),
+                &num,
+                arrow,
+                &mem,
+                &num,
+                opt(&space),
+            ))(
+                input
+            ) {
+                Ok(val) => val,
+                Err(nom::Err::Error(nom::error::Error { input, .. }))
+                | Err(nom::Err::Failure(nom::error::Error { input, .. })) => {
+                    return Err(format!(
+                        "Error while parsing `and` instruction near `{}`",
+                        input
+                    ))
+                }
+                Err(nom::Err::Incomplete(_)) => {
+                    return Err("Error while parsing `and` incomplete data.".to_owned())
+                }
+            };
+            if !input.is_empty() {
+                return Err(format!("Unexpected characters: `{}`", input));
+            }
+            let src_l = str_to_u8(src_l)?;
+            let src_r = str_to_u8(src_r)?;
+            let tgt = str_to_u8(tgt)?;
+
+            Instruction::And {
+                left: match is_mem_l {
+                    Some(_) => Value::Memory { addr: src_l },
+                    None => Value::Literal { val: src_l },
+                },
+                right: match is_mem_r {
+                    Some(_) => Value::Memory { addr: src_r },
+                    None => Value::Literal { val: src_r },
+                },
+                tgt: Value::Memory { addr: tgt },
+            }
+        }
+        "not" => {
+            let (input, (is_mem, src, _, _, tgt, _)) =
+                match tuple((opt(&mem), &num, arrow, &mem, &num, opt(&space)))(input) {
+                    Ok(val) => val,
+                    Err(nom::Err::Error(nom::error::Error { input, .. }))
+                    | Err(nom::Err::Failure(nom::error::Error { input, .. })) => {
+                        return Err(format!(
+                            "Error while parsing `not` instruction near `{}`",
+                            input
+                        ))
+                    }
+                    Err(nom::Err::Incomplete(_)) => {
+                        return Err("Error while parsing `not`, incomplete data.".to_owned())
+                    }
+                };
+            if !input.is_empty() {
+                return Err(format!("Unexpected characters: `{}`", input));
+            }
+            let src = str_to_u8(src)?;
+            let tgt = str_to_u8(tgt)?;
+
+            Instruction::Not {
+                src: match is_mem {
+                    Some(_) => Value::Memory { addr: src },
+                    None => Value::Literal { val: src },
+                },
+                tgt: Value::Memory { addr: tgt },
+            }
+        }
+        "xor" => {
+            let (input, (is_mem_l, src_l, _, is_mem_r, src_r, _, _, tgt, _)) = match tuple((
+                opt(&mem),
+                &num,
+                sep,
+                opt(&mem),
+                &num,
+                arrow,
+                &mem,
+                &num,
+                opt(&space),
+            ))(
+                input
+            ) {
+                Ok(val) => val,
+                Err(nom::Err::Error(nom::error::Error { input, .. }))
+                | Err(nom::Err::Failure(nom::error::Error { input, .. })) => {
+                    return Err(format!(
+                        "Error while parsing `xor` instruction near `{}`",
+                        input
+                    ))
+                }
+                Err(nom::Err::Incomplete(_)) => {
+                    return Err("Error while parsing `xor`, incomplete data.".to_owned())
+                }
+            };
+            if !input.is_empty() {
+                return Err(format!("Unexpected characters: `{}`", input));
+            }
+            let src_l = str_to_u8(src_l)?;
+            let src_r = str_to_u8(src_r)?;
+            let tgt = str_to_u8(tgt)?;
+
+            Instruction::Xor {
+                left: match is_mem_l {
+                    Some(_) => Value::Memory { addr: src_l },
+                    None => Value::Literal { val: src_l },
+                },
+                right: match is_mem_r {
+                    Some(_) => Value::Memory { addr: src_r },
+                    None => Value::Literal { val: src_r },
+                },
+                tgt: Value::Memory { addr: tgt },
+            }
+        }
+        "add" => {
+            let (input, (is_mem_l, src_l, _, is_mem_r, src_r, _, _, tgt, _)) = match tuple((
+                opt(&mem),
+                &num,
+                sep,
+                opt(&mem),
+                &num,
+                arrow,
+                &mem,
+                &num,
+                opt(&space),
+            ))(
+                input
+            ) {
+                Ok(val) => val,
+                Err(nom::Err::Error(nom::error::Error { input, .. }))
+                | Err(nom::Err::Failure(nom::error::Error { input, .. })) => {
+                    return Err(format!(
+                        "Error while parsing `add` instruction near `{}`",
+                        input
+                    ))
+                }
+                Err(nom::Err::Incomplete(_)) => {
+                    return Err("Error while parsing `add`, incomplete data.".to_owned())
+                }
+            };
+            if !input.is_empty() {
+                return Err(format!("Unexpected characters: `{}`", input));
+            }
+            let src_l = str_to_u8(src_l)?;
+            let src_r = str_to_u8(src_r)?;
+            let tgt = str_to_u8(tgt)?;
+
+            Instruction::Add {
+                left: match is_mem_l {
+                    Some(_) => Value::Memory { addr: src_l },
+                    None => Value::Literal { val: src_l },
+                },
+                right: match is_mem_r {
+                    Some(_) => Value::Memory { addr: src_r },
+                    None => Value::Literal { val: src_r },
+                },
+                tgt: Value::Memory { addr: tgt },
+            }
+        }
+        "sub" => {
+            let (input, (is_mem_l, src_l, _, is_mem_r, src_r, _, _, tgt, _)) = match tuple((
+                opt(&mem),
+                &num,
+                sep,
+                opt(&mem),
+                &num,
+                arrow,
+                &mem,
+                &num,
+                opt(&space),
+            ))(
+                input
+            ) {
+                Ok(val) => val,
+                Err(nom::Err::Error(nom::error::Error { input, .. }))
+                | Err(nom::Err::Failure(nom::error::Error { input, .. })) => {
+                    return Err(format!(
+                        "Error while parsing `sub` instruction near `{}`",
+                        input
+                    ))
+                }
+                Err(nom::Err::Incomplete(_)) => {
+                    return Err("Error while parsing `sub`, incomplete data.".to_owned())
+                }
+            };
+            if !input.is_empty() {
+                return Err(format!("Unexpected characters: `{}`", input));
+            }
+            let src_l = str_to_u8(src_l)?;
+            let src_r = str_to_u8(src_r)?;
+            let tgt = str_to_u8(tgt)?;
+
+            Instruction::Sub {
+                left: match is_mem_l {
+                    Some(_) => Value::Memory { addr: src_l },
+                    None => Value::Literal { val: src_l },
+                },
+                right: match is_mem_r {
+                    Some(_) => Value::Memory { addr: src_r },
+                    None => Value::Literal { val: src_r },
+                },
+                tgt: Value::Memory { addr: tgt },
+            }
+        }
+        "out" => {
+            let (input, (is_mem, src, _)) = match tuple((opt(&mem), &num, opt(&space)))(input) {
+                Ok(val) => val,
+                Err(nom::Err::Error(nom::error::Error { input, .. }))
+                | Err(nom::Err::Failure(nom::error::Error { input, .. })) => {
+                    return Err(format!(
+                        "Error while parsing `out` instruction near `{}`",
+                        input
+                    ))
+                }
+                Err(nom::Err::Incomplete(_)) => {
+                    return Err("Error while parsing `out`, incomplete data.".to_owned())
+                }
+            };
+            if !input.is_empty() {
+                return Err(format!("Unexpected characters: `{}`", input));
+            }
+
+            let src = str_to_u8(src)?;
+
+            Instruction::Out {
+                src: match is_mem {
+                    Some(_) => Value::Memory { addr: src },
+                    None => Value::Literal { val: src },
+                },
+            }
+        }
+        "num" => {
+            let (input, (is_mem, src, _)) = match tuple((opt(&mem), &num, opt(&space)))(input) {
+                Ok(val) => val,
+                Err(nom::Err::Error(nom::error::Error { input, .. }))
+                | Err(nom::Err::Failure(nom::error::Error { input, .. })) => {
+                    return Err(format!(
+                        "Error while parsing `num` instruction near `{}`",
+                        input
+                    ))
+                }
+                Err(nom::Err::Incomplete(_)) => {
+                    return Err("Error while parsing `num`, incomplete data.".to_owned())
+                }
+            };
+            if !input.is_empty() {
+                return Err(format!("Unexpected characters: `{}`", input));
+            }
+
+            let src = str_to_u8(src)?;
+
+            Instruction::Num {
+                src: match is_mem {
+                    Some(_) => Value::Memory { addr: src },
+                    None => Value::Literal { val: src },
+                },
+            }
+        }
+        "cin" => {
+            let (input, (_, _, addr, _)) = match tuple((arrow, &mem, &num, opt(&space)))(input) {
+                Ok(val) => val,
+                Err(nom::Err::Error(nom::error::Error { input, .. }))
+                | Err(nom::Err::Failure(nom::error::Error { input, .. })) => {
+                    return Err(format!(
+                        "Error while parsing `cin` instruction near `{}`",
+                        input
+                    ))
+                }
+                Err(nom::Err::Incomplete(_)) => {
+                    return Err("Error while parsing `cin`, incomplete data.".to_owned())
+                }
+            };
+            if !input.is_empty() {
+                return Err(format!("Unexpected characters: `{}`", input));
+            }
+
+            let addr = str_to_u8(addr)?;
+
+            Instruction::Cin {
+                tgt: Value::Memory { addr },
+            }
+        }
+        "nin" => {
+            let (input, (_, _, addr, _)) = match tuple((arrow, &mem, &num, opt(&space)))(input) {
+                Ok(val) => val,
+                Err(nom::Err::Error(nom::error::Error { input, .. }))
+                | Err(nom::Err::Failure(nom::error::Error { input, .. })) => {
+                    return Err(format!(
+                        "Error while parsing `nin` instruction near `{}`",
+                        input
+                    ))
+                }
+                Err(nom::Err::Incomplete(_)) => {
+                    return Err("Error while parsing `nin`, incomplete data.".to_owned())
+                }
+            };
+            if !input.is_empty() {
+                return Err(format!("Unexpected characters: `{}`", input));
+            }
+
+            let addr = str_to_u8(addr)?;
+
+            Instruction::Nin {
+                tgt: Value::Memory { addr },
+            }
+        }
+        "bak" => {
+            let (input, (is_mem_count, count, _, is_mem_check, check, _)) =
+                match tuple((opt(&mem), &num, sep, opt(&mem), &num, opt(&space)))(input) {
+                    Ok(val) => val,
+                    Err(nom::Err::Error(nom::error::Error { input, .. }))
+                    | Err(nom::Err::Failure(nom::error::Error { input, .. })) => {
+                        return Err(format!(
+                            "Error while parsing `bak` instruction near `{}`",
+                            input
+                        ))
+                    }
+                    Err(nom::Err::Incomplete(_)) => {
+                        return Err("Error while parsing `bak`, incomplete data.".to_owned())
+                    }
+                };
+            if !input.is_empty() {
+                return Err(format!("Unexpected characters: `{}`", input));
+            }
+
+            let count = str_to_u8(count)?;
+            let check = str_to_u8(check)?;
+
+            Instruction::Bak {
+                count: match is_mem_count {
+                    Some(_) => Value::Memory { addr: count },
+                    None => Value::Literal { val: count },
+                },
+                check: match is_mem_check {
+                    Some(_) => Value::Memory { addr: check },
+                    None => Value::Literal { val: check },
+                },
+            }
+        }
+
+        "fwd" => {
+            let (input, (is_mem_count, count, _, is_mem_check, check, _)) =
+                match tuple((opt(&mem), &num, sep, opt(&mem), &num, opt(&space)))(input) {
+                    Ok(val) => val,
+                    Err(nom::Err::Error(nom::error::Error { input, .. }))
+                    | Err(nom::Err::Failure(nom::error::Error { input, .. })) => {
+                        return Err(format!(
+                            "Error while parsing `fwd` instruction near `{}`",
+                            input
+                        ))
+                    }
+                    Err(nom::Err::Incomplete(_)) => {
+                        return Err("Error while parsing `fwd`, incomplete data.".to_owned())
+                    }
+                };
+            if !input.is_empty() {
+                return Err(format!("Unexpected characters: `{}`", input));
+            }
+
+            let count = str_to_u8(count)?;
+            let check = str_to_u8(check)?;
+
+            Instruction::Fwd {
+                count: match is_mem_count {
+                    Some(_) => Value::Memory { addr: count },
+                    None => Value::Literal { val: count },
+                },
+                check: match is_mem_check {
+                    Some(_) => Value::Memory { addr: check },
+                    None => Value::Literal { val: check },
+                },
+            }
+        }
+
+        "bye" => {
+            let (input, (is_mem, src, _)) = match tuple((opt(&mem), &num, opt(&space)))(input) {
+                Ok(val) => val,
+                Err(nom::Err::Error(nom::error::Error { input, .. }))
+                | Err(nom::Err::Failure(nom::error::Error { input, .. })) => {
+                    return Err(format!(
+                        "Error while parsing `bye` instruction near `{}`",
+                        input
+                    ))
+                }
+                Err(nom::Err::Incomplete(_)) => {
+                    return Err("Error while parsing `bye`, incomplete data.".to_owned())
+                }
+            };
+            if !input.is_empty() {
+                return Err(format!("Unexpected characters: `{}`", input));
+            }
+
+            let src = str_to_u8(src)?;
+
+            Instruction::Bye {
+                code: match is_mem {
+                    Some(_) => Value::Memory { addr: src },
+                    None => Value::Literal { val: src },
+                },
+            }
+        }
+
+        "nop" => {
+            let (input, _) = match opt(&space)(input) {
+                Ok(val) => val,
+                Err(nom::Err::Error(nom::error::Error { input, .. }))
+                | Err(nom::Err::Failure(nom::error::Error { input, .. })) => {
+                    return Err(format!(
+                        "Error while parsing `nop` instruction near `{}`",
+                        input
+                    ))
+                }
+                Err(nom::Err::Incomplete(_)) => {
+                    return Err("Error while parsing `nop`, incomplete data.".to_owned())
+                }
+            };
+            if !input.is_empty() {
+                return Err(format!("Unexpected characters: `{}`", input));
+            }
+
+            Instruction::Nop
+        }
+
+        _ => return Err(format!("Unknown instruction `{name}`")),
+    };
+
+    Ok(inst)
+}
+
+/// Just the instructions from a file. Anything from `.data` is left out, see
+/// `get_program` for that.
+pub fn get_instructions(path: &str) -> Result<Vec<Instruction>, String> {
+    Ok(get_program(path, &SourceConfig::default())?.instructions)
+}
+
+/// The whole program from a file, with what memory starts as from any
+/// `.data`, and somewhere to look for includes.
+pub fn get_program(path: &str, config: &SourceConfig) -> Result<Program, String> {
+    asm::assemble(source::read(path, config)?)
+}
+
+/// Like `get_instructions`, but keeps the comments and blank lines around.
+/// There's one `Line` for every line in the source.
+pub fn parse_lines(content: &str) -> Result<Vec<Line>, String> {
+    let mut lines = Vec::new();
+    // Macro bodies are full of `\param`s, so they're left as they are
+    let mut in_macro = false;
+
+    for (idx, line) in content.lines().enumerate() {
+        let line = line.trim();
+
+        let (code, comment) = source::split_comment(line);
+        let comment = comment.map(|comment| comment.trim_end().to_owned());
+
+        if code.is_empty() {
+            lines.push(match comment {
+                Some(comment) => Line::Comment(comment),
+                None => Line::Blank,
+            });
+            continue;
+        }
+
+        if code.starts_with(".macro") || code.starts_with(".endm") {
+            in_macro = code.starts_with(".macro");
+        }
+        if in_macro || asm::is_asm(code) {
+            lines.push(Line::Asm(code.to_owned(), comment));
+            continue;
+        }
+
+        match parse_instruction(code) {
+            Ok(inst) => lines.push(Line::Instruction(inst, comment)),
+            Err(val) => return Err(format!("Error on line {}: {}", idx + 1, val)),
+        }
+    }
+
+    Ok(lines)
+}
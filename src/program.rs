@@ -0,0 +1,133 @@
+use crate::machine::MEMORY_SIZE;
+use crate::parser::Instruction;
+use crate::source::{strip_comment, SourceLine};
+use std::fmt::Display;
+use std::num::Wrapping;
+
+/// Cells that start out as something, as (address, value) in order of
+/// address. `m0` is never in here, it's the IP.
+pub type Data = Vec<(u8, u8)>;
+
+/// A whole program: the instructions, and what memory starts out as.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Program {
+    pub instructions: Vec<Instruction>,
+    pub data: Data,
+    /// The line each instruction came from, by index. Empty if nobody knows,
+    /// like for bytecode.
+    pub source_map: Vec<SourceLine>,
+}
+
+impl From<Vec<Instruction>> for Program {
+    fn from(instructions: Vec<Instruction>) -> Self {
+        Program {
+            instructions,
+            data: Vec::new(),
+            source_map: Vec::new(),
+        }
+    }
+}
+
+impl Program {
+    /// Where the instruction at `idx` came from, if we know.
+    pub fn source(&self, idx: usize) -> Option<&SourceLine> {
+        self.source_map.get(idx)
+    }
+
+    /// Tacks where the instruction at `idx` came from onto a message, for
+    /// when something goes wrong while it runs.
+    pub fn error_at(&self, idx: usize, msg: impl Display) -> String {
+        match self.source(idx) {
+            Some(at) => format!(
+                "{} at {}: {}{}",
+                msg,
+                at.location(),
+                strip_comment(&at.text),
+                at.calls()
+            ),
+            None => msg.to_string(),
+        }
+    }
+
+    /// Memory the way it is before the first instruction runs.
+    pub fn memory(&self) -> [Wrapping<u8>; MEMORY_SIZE] {
+        let mut memory = [Wrapping(0u8); MEMORY_SIZE];
+        for (addr, val) in &self.data {
+            memory[*addr as usize] = Wrapping(*val);
+        }
+        memory
+    }
+
+    /// `.data` lines that set memory up the same way, one for every run of
+    /// cells next to each other.
+    pub fn data_directives(&self) -> Vec<String> {
+        let mut lines: Vec<(u8, Vec<String>)> = Vec::new();
+
+        for (addr, val) in &self.data {
+            match lines.last_mut() {
+                Some((start, vals)) if *start as usize + vals.len() == *addr as usize => {
+                    vals.push(val.to_string())
+                }
+                _ => lines.push((*addr, vec![val.to_string()])),
+            }
+        }
+
+        lines
+            .into_iter()
+            .map(|(start, vals)| format!(".data m{} = {}", start, vals.join(", ")))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::io::BufferIo;
+    use crate::machine::{Machine, MachineConfig, RunError};
+    use crate::parser::get_program;
+    use crate::source::SourceConfig;
+    use crate::threaded::Threaded;
+    use std::fs;
+
+    #[test]
+    fn fault_in_an_include() {
+        let dir = std::env::temp_dir().join(format!("sick-as-program-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("main.sick"),
+            "out 65\n.include \"lib.sick\"\nbye 0\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("lib.sick"),
+            "# reads one too many\n    cin -> m1\n    cin -> m2\n",
+        )
+        .unwrap();
+
+        let main = dir.join("main.sick");
+        let program = get_program(main.to_str().unwrap(), &SourceConfig::default()).unwrap();
+        let lib = dir.join("lib.sick");
+
+        for threaded in [false, true] {
+            let mut machine = Machine::with_io(MachineConfig::default(), BufferIo::new(b"x"));
+            machine.memory = program.memory();
+            let result = match threaded {
+                true => machine.run_threaded(&Threaded::compile(&program.instructions)),
+                false => machine.run(&program.instructions),
+            };
+            assert_eq!(
+                result,
+                Err(RunError::Fault("EOF while reading input".to_owned()))
+            );
+            assert_eq!(machine.ip(), 2);
+            assert_eq!(
+                program.error_at(machine.ip() as usize, "EOF while reading input"),
+                format!(
+                    "EOF while reading input at {}:3:5: cin -> m2",
+                    lib.display()
+                )
+            );
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
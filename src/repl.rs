@@ -0,0 +1,297 @@
+use crate::bytecode::load_with;
+use crate::debugger::{parse_range, show_memory};
+use crate::machine::{Machine, MachineConfig, Status, MEMORY_SIZE};
+use crate::parser::parse_instruction;
+use crate::program::Program;
+use crate::source::{SourceConfig, SourceLine};
+use std::io::Write;
+use std::num::Wrapping;
+
+/// How long a single line gets to run before we assume it's stuck.
+const STEPS_PER_LINE: u64 = 1_000_000;
+
+/// A line is done once the IP goes past the end, and a u8 can't go past
+/// 255 without wrapping back to line 0, so that's as long as it gets.
+const MAX_LINES: usize = 255;
+
+const HELP: &str = "\
+Type instructions to run them. Jumps work, they just go back over what you
+typed before. bye quits, and so does :load-ing a file that
+ends in one.
+
+:mem A[..B]      show memory from A up to (not including) B
+:ip              show the instruction pointer
+:undo            take back the last line
+:reset           start again from scratch (there's room for 255 instructions)
+:load file.sick  run a whole file as if it was typed in
+:help            this
+:q               quit";
+
+/// Where things were before a line ran, so `:undo` can go back there. A
+/// line can run for way longer than any undo log would keep, so it's all of
+/// memory (IP included) rather than the steps to get back.
+struct Checkpoint {
+    memory: [Wrapping<u8>; MEMORY_SIZE],
+    len: usize,
+}
+
+struct Repl {
+    machine: Machine,
+    /// Everything typed or loaded so far. Typed lines are from `repl`, and
+    /// the line number is how many have been typed.
+    program: Program,
+    checkpoints: Vec<Checkpoint>,
+    typed: usize,
+    /// For `:load`.
+    source: SourceConfig,
+}
+
+impl Repl {
+    fn new(source: SourceConfig) -> Self {
+        Repl {
+            machine: Machine::new(MachineConfig::default()),
+            program: Program::default(),
+            checkpoints: Vec::new(),
+            typed: 0,
+            source,
+        }
+    }
+
+    /// Adds a program to the end of what's there and runs until the IP falls
+    /// off the end again. Its data goes into memory first. Gives back the
+    /// code if it hit a `bye`.
+    fn push(&mut self, program: Program) -> Option<u8> {
+        let len = self.program.instructions.len() + program.instructions.len();
+        if len > MAX_LINES {
+            println!(
+                "That would make {} instructions and only {} fit, :undo or :reset to make room",
+                len, MAX_LINES
+            );
+            return None;
+        }
+
+        self.checkpoints.push(Checkpoint {
+            memory: self.machine.memory,
+            len: self.program.instructions.len(),
+        });
+
+        // Same as if it had been there all along
+        for (addr, val) in &program.data {
+            self.machine.memory[*addr as usize] = Wrapping(*val);
+        }
+        self.program.instructions.extend(program.instructions);
+        self.program.source_map.extend(program.source_map);
+
+        for _ in 0..STEPS_PER_LINE {
+            let ip = self.machine.ip() as usize;
+            if ip >= self.program.instructions.len() {
+                return None;
+            }
+            match self.machine.step(&self.program.instructions) {
+                Ok(Status::Running) => {}
+                Ok(Status::Exited(code)) => return Some(code),
+                Err(msg) => {
+                    println!("{}", self.program.error_at(ip, msg));
+                    self.undo();
+                    return None;
+                }
+            }
+        }
+
+        println!("Still going after {} steps, giving up", STEPS_PER_LINE);
+        self.undo();
+        None
+    }
+
+    /// Runs a typed line.
+    fn push_line(&mut self, line: &str) -> Option<u8> {
+        self.typed += 1;
+        match parse_instruction(line) {
+            Ok(inst) => self.push(Program {
+                instructions: vec![inst],
+                source_map: vec![SourceLine {
+                    file: "repl".to_owned(),
+                    line: self.typed,
+                    text: line.to_owned(),
+                    expanded_from: None,
+                }],
+                ..Default::default()
+            }),
+            Err(msg) => {
+                println!("{}", msg);
+                None
+            }
+        }
+    }
+
+    fn undo(&mut self) {
+        match self.checkpoints.pop() {
+            Some(Checkpoint { memory, len }) => {
+                self.machine.memory = memory;
+                self.program.instructions.truncate(len);
+                self.program.source_map.truncate(len);
+            }
+            None => println!("Nothing to undo"),
+        }
+    }
+
+    fn show_ip(&self) {
+        match self.program.instructions.get(self.machine.ip() as usize) {
+            Some(inst) => println!("m0 = {} ({})", self.machine.ip(), inst),
+            None => println!("m0 = {}", self.machine.ip()),
+        }
+    }
+
+    /// Runs a `:command`. Gives back the exit code when it's time to quit.
+    fn meta(&mut self, line: &str) -> Option<u8> {
+        let mut words = line.split_whitespace();
+        let cmd = words.next().unwrap_or("");
+        let arg = words.next();
+
+        match cmd {
+            ":mem" => match arg.map_or(Some((0, MEMORY_SIZE)), parse_range) {
+                Some((start, end)) => show_memory(&self.machine, start, end),
+                None => println!("Usage: :mem A[..B]"),
+            },
+            ":ip" => self.show_ip(),
+            ":undo" => self.undo(),
+            ":reset" => *self = Repl::new(self.source.clone()),
+            ":load" => match arg.map(|file| load_with(file, &self.source)) {
+                Some(Ok(mut program)) => {
+                    // Bytecode has no lines, so go by instruction number
+                    // instead to keep the source map lined up
+                    if program.source_map.is_empty() {
+                        program.source_map = program
+                            .instructions
+                            .iter()
+                            .enumerate()
+                            .map(|(idx, inst)| SourceLine {
+                                file: arg.unwrap_or_default().to_owned(),
+                                line: idx + 1,
+                                text: inst.to_string(),
+                                expanded_from: None,
+                            })
+                            .collect();
+                    }
+                    return self.push(program);
+                }
+                Some(Err(msg)) => println!("{}", msg),
+                None => println!("Usage: :load file.sick"),
+            },
+            ":help" => println!("{}", HELP),
+            ":q" => return Some(0),
+            _ => println!("Unknown command `{}`, try :help", cmd),
+        }
+
+        None
+    }
+}
+
+/// Reads instructions one line at a time and runs them straight away. Gives
+/// back the exit code once it hits a `bye`. `source` is for `:load`.
+pub fn run(source: &SourceConfig) -> Result<u8, String> {
+    let mut repl = Repl::new(source.clone());
+
+    println!("Type :help for help");
+
+    loop {
+        print!("sick> ");
+        std::io::stdout().flush().expect("IO error");
+
+        let mut line = String::new();
+        if std::io::stdin().read_line(&mut line).expect("IO error") == 0 {
+            return Ok(0);
+        }
+
+        // Same as in files, comments get ignored
+        let line = line.split('#').next().expect("Should always be a string");
+        let line = line.trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        let exited = if line.starts_with(':') {
+            repl.meta(line)
+        } else {
+            repl.push_line(line)
+        };
+
+        if let Some(code) = exited {
+            return Ok(code);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn undo_after_a_runaway_line() {
+        let mut repl = Repl::new(SourceConfig::default());
+        repl.push_line("add m1, 1 -> m1");
+        // Never gets out, so it gets undone after STEPS_PER_LINE steps
+        repl.push_line("bak 1, 0");
+        assert_eq!(repl.program.instructions.len(), 1);
+        assert_eq!(repl.machine.ip(), 1);
+        assert_eq!(repl.machine.memory[1], Wrapping(1));
+
+        // And the next line runs like the loop was never there
+        repl.push_line("set 5 -> m3");
+        assert_eq!(repl.machine.ip(), 2);
+        assert_eq!(repl.machine.memory[3], Wrapping(5));
+    }
+
+    #[test]
+    fn undo_a_load() {
+        let path = std::env::temp_dir().join("sick-as-repl-undo-a-load.sick");
+        fs::write(&path, ".data m10 = 1, 2\nset 3 -> m12\n").unwrap();
+        let path = path.to_str().unwrap();
+
+        let mut repl = Repl::new(SourceConfig::default());
+        repl.meta(&format!(":load {}", path));
+        assert_eq!(
+            repl.machine.memory[10..13],
+            [Wrapping(1), Wrapping(2), Wrapping(3)]
+        );
+        assert_eq!(repl.program.source(0).map(|at| at.line), Some(2));
+
+        repl.meta(":undo");
+        assert_eq!(repl.machine.memory, [Wrapping(0); MEMORY_SIZE]);
+        assert!(repl.program.instructions.is_empty());
+        assert!(repl.program.source_map.is_empty());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn typed_lines_have_a_source() {
+        let mut repl = Repl::new(SourceConfig::default());
+        repl.push_line("set 1 -> m1");
+        repl.push_line("set 2 -> m2");
+        let at = repl.program.source(1).unwrap();
+        assert_eq!((at.file.as_str(), at.line), ("repl", 2));
+    }
+
+    #[test]
+    fn full_program() {
+        let mut repl = Repl::new(SourceConfig::default());
+        for _ in 0..MAX_LINES {
+            repl.push_line("add m1, 1 -> m1");
+        }
+        assert_eq!(repl.machine.ip(), 255);
+
+        // Would run at 255 and wrap back around to the first line
+        repl.push_line("add m2, 1 -> m2");
+        assert_eq!(repl.program.instructions.len(), MAX_LINES);
+        assert_eq!(repl.machine.memory[1], Wrapping(255));
+        assert_eq!(repl.machine.memory[2], Wrapping(0));
+
+        // Undoing the last line makes room again
+        repl.meta(":undo");
+        repl.push_line("add m2, 1 -> m2");
+        assert_eq!(repl.machine.memory[1], Wrapping(254));
+        assert_eq!(repl.machine.memory[2], Wrapping(1));
+    }
+}
@@ -0,0 +1,685 @@
+use crate::stdlib;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Knobs for reading source. The default is what a plain run uses.
+#[derive(Debug, Clone, Default)]
+pub struct SourceConfig {
+    /// Where else to look for an `.include`, in order, if it's not next to the
+    /// file doing the including.
+    pub include_dirs: Vec<PathBuf>,
+    /// Names for `.if` and `.rep`, from `-D NAME=value`.
+    pub defines: HashMap<String, String>,
+}
+
+/// A line of the program once every `.include` is pasted in, and where it
+/// really came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLine {
+    pub file: String,
+    /// 1-based, in `file`.
+    pub line: usize,
+    pub text: String,
+    /// If it's from a macro, the macro's name and the line that called it.
+    pub expanded_from: Option<(String, Box<SourceLine>)>,
+}
+
+impl SourceLine {
+    /// 1-based, where the code starts once the indent is skipped.
+    pub fn column(&self) -> usize {
+        self.text.chars().take_while(|c| c.is_whitespace()).count() + 1
+    }
+
+    /// `file:line:column`
+    pub fn location(&self) -> String {
+        format!("{}:{}:{}", self.file, self.line, self.column())
+    }
+
+    /// Puts where the line is in front of a message, and which macro calls
+    /// got it there after.
+    pub fn error(&self, msg: impl Display) -> String {
+        format!("{}:{}: {}{}", self.file, self.line, msg, self.calls())
+    }
+
+    /// The macro calls that got the line here, like
+    /// ` (in \`foo\` from a.sick:3)`, or nothing if it wasn't from a macro.
+    pub fn calls(&self) -> String {
+        let mut calls = Vec::new();
+        let mut at = self;
+        while let Some((name, call)) = &at.expanded_from {
+            calls.push(format!("in `{}` from {}:{}", name, call.file, call.line));
+            at = call;
+        }
+
+        match calls.is_empty() {
+            true => String::new(),
+            false => format!(" ({})", calls.join(", ")),
+        }
+    }
+}
+
+/// Splits a line into the code, trimmed, and the comment after the `#` if
+/// there is one. A `#` inside quotes doesn't start a comment.
+pub fn split_comment(text: &str) -> (&str, Option<&str>) {
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for (idx, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '#' if !quoted => return (text[..idx].trim(), Some(&text[idx + 1..])),
+            _ => {}
+        }
+    }
+
+    (text.trim(), None)
+}
+
+/// Just the code part of a line, trimmed.
+pub fn strip_comment(text: &str) -> &str {
+    split_comment(text).0
+}
+
+/// Pulls the path out of an `.include "path"` line. `None` if it's some other
+/// kind of line.
+fn include(text: &str) -> Option<Result<&str, String>> {
+    let rest = strip_comment(text).strip_prefix(".include")?;
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+
+    let path = rest
+        .trim()
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+        .filter(|path| !path.is_empty() && !path.contains('"'));
+    Some(path.ok_or_else(|| {
+        "`.include` needs a path in quotes, like `.include \"lib.sick\"`".to_owned()
+    }))
+}
+
+/// Next to the including file first, then the include dirs.
+fn resolve(from: &Path, name: &str, config: &SourceConfig) -> Option<PathBuf> {
+    let here = from.parent().unwrap_or(Path::new(""));
+    std::iter::once(here)
+        .chain(config.include_dirs.iter().map(PathBuf::as_path))
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// The most lines a `.rep` can make, nested ones included. Way more than
+/// fits in a program, but it stops a typo'd count from eating all the memory.
+const MAX_REP_LINES: usize = 1 << 16;
+
+/// A `.if` or `.rep` that hasn't hit its end yet.
+enum Open {
+    If {
+        at: SourceLine,
+        keep: bool,
+    },
+    Rep {
+        at: SourceLine,
+        times: usize,
+        body: Vec<SourceLine>,
+    },
+}
+
+/// Whether `.if name` keeps its lines. Anything defined counts, unless it's
+/// defined as 0.
+fn is_set(name: &str, config: &SourceConfig) -> bool {
+    config
+        .defines
+        .get(name)
+        .is_some_and(|val| val.trim() != "0")
+}
+
+/// How many times a `.rep` goes, from a number or a define.
+fn times(arg: &str, config: &SourceConfig) -> Result<usize, String> {
+    if arg.is_empty() {
+        return Err("`.rep` needs how many times to go, like `.rep 8`".to_owned());
+    }
+
+    let val = match arg.parse() {
+        Ok(times) => return Ok(times),
+        Err(_) if arg.bytes().all(|b| b.is_ascii_digit()) => {
+            return Err(format!("`{}` is way too many times", arg))
+        }
+        Err(_) => config.defines.get(arg).ok_or_else(|| {
+            format!(
+                "`{}` isn't defined, give it a value with `-D {}=8`",
+                arg, arg
+            )
+        })?,
+    };
+    val.trim()
+        .parse()
+        .map_err(|_| format!("`{}` is `{}`, which isn't a number", arg, val))
+}
+
+/// Where a finished line goes: the innermost `.rep`, or the output.
+fn sink<'a>(open: &'a mut [Open], out: &'a mut Vec<SourceLine>) -> &'a mut Vec<SourceLine> {
+    open.iter_mut()
+        .rev()
+        .find_map(|block| match block {
+            Open::Rep { body, .. } => Some(body),
+            Open::If { .. } => None,
+        })
+        .unwrap_or(out)
+}
+
+/// Drops the lines from `.if`s that aren't set and copies out `.rep`s, for
+/// one file. Blocks have to end in the file they start in.
+fn conditions(lines: Vec<SourceLine>, config: &SourceConfig) -> Result<Vec<SourceLine>, String> {
+    let mut out = Vec::with_capacity(lines.len());
+    let mut open: Vec<Open> = Vec::new();
+
+    for here in lines {
+        let code = strip_comment(&here.text);
+        let (word, arg) = match code.split_once(char::is_whitespace) {
+            Some((word, rest)) => (word, rest.trim()),
+            None => (code, ""),
+        };
+        // Lines in an `.if` that isn't set still have to be looked at, so the
+        // ends match up, but nothing in them counts
+        let live = open.iter().all(|block| match block {
+            Open::If { keep, .. } => *keep,
+            Open::Rep { .. } => true,
+        });
+
+        match word {
+            ".if" => {
+                let valid =
+                    !arg.is_empty() && arg.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_');
+                if !valid {
+                    return Err(here.error("`.if` needs a name to check, like `.if DEBUG`"));
+                }
+                let keep = is_set(arg, config);
+                open.push(Open::If { at: here, keep });
+            }
+            ".rep" => {
+                let times = match live {
+                    true => times(arg, config).map_err(|msg| here.error(msg))?,
+                    false => 0,
+                };
+                open.push(Open::Rep {
+                    at: here,
+                    times,
+                    body: Vec::new(),
+                });
+            }
+            ".endif" | ".endr" if !arg.is_empty() => {
+                return Err(here.error(format!("Unexpected characters: `{}`", arg)))
+            }
+            ".endif" => match open.pop() {
+                Some(Open::If { .. }) => {}
+                Some(Open::Rep { at, .. }) => {
+                    return Err(here.error(format!(
+                        "`.endif` can't end the `.rep` at {}:{}, it needs an `.endr`",
+                        at.file, at.line
+                    )))
+                }
+                None => return Err(here.error("`.endif` without an `.if`")),
+            },
+            ".endr" => match open.pop() {
+                Some(Open::Rep { at, times, body }) => {
+                    let sink = sink(&mut open, &mut out);
+                    let total = body.len().checked_mul(times);
+                    if total.is_none_or(|total| total > MAX_REP_LINES) {
+                        return Err(at.error(format!(
+                            "This `.rep` makes more than {} lines",
+                            MAX_REP_LINES
+                        )));
+                    }
+                    if !body.is_empty() {
+                        for _ in 0..times {
+                            sink.extend(body.iter().cloned());
+                        }
+                    }
+                }
+                Some(Open::If { at, .. }) => {
+                    return Err(here.error(format!(
+                        "`.endr` can't end the `.if` at {}:{}, it needs an `.endif`",
+                        at.file, at.line
+                    )))
+                }
+                None => return Err(here.error("`.endr` without a `.rep`")),
+            },
+            _ if live => sink(&mut open, &mut out).push(here),
+            _ => {}
+        }
+    }
+
+    match open.last() {
+        Some(Open::If { at, .. }) => Err(at.error("`.if` without an `.endif`")),
+        Some(Open::Rep { at, .. }) => Err(at.error("`.rep` without an `.endr`")),
+        None => Ok(out),
+    }
+}
+
+/// What `expand` keeps track of from one file to the next.
+#[derive(Default)]
+struct Included {
+    /// Every file we're in the middle of, so running into one of them again
+    /// means it's including itself somehow.
+    stack: Vec<(PathBuf, String)>,
+    /// Library files only get pasted in once, however many files want them.
+    std: HashSet<String>,
+}
+
+fn expand(
+    file: &Path,
+    content: &str,
+    config: &SourceConfig,
+    included: &mut Included,
+    out: &mut Vec<SourceLine>,
+) -> Result<(), String> {
+    let name = file.display().to_string();
+    let canonical = fs::canonicalize(file).unwrap_or_else(|_| file.to_owned());
+    included.stack.push((canonical, name.clone()));
+
+    let lines = content
+        .split('\n')
+        .enumerate()
+        .map(|(idx, text)| SourceLine {
+            file: name.clone(),
+            line: idx + 1,
+            text: text.to_owned(),
+            expanded_from: None,
+        })
+        .collect();
+
+    for here in conditions(lines, config)? {
+        let wanted = match include(&here.text) {
+            None => {
+                out.push(here);
+                continue;
+            }
+            Some(wanted) => wanted.map_err(|msg| here.error(msg))?,
+        };
+
+        if let Some(lib) = wanted.strip_prefix(stdlib::PREFIX) {
+            let content = stdlib::get(lib).ok_or_else(|| {
+                here.error(format!(
+                    "There's no `{}` in the library, `sick-as std` lists what there is",
+                    wanted
+                ))
+            })?;
+            if included.std.insert(lib.to_owned()) {
+                expand(Path::new(wanted), content, config, included, out)?;
+            }
+            continue;
+        }
+
+        let path = resolve(file, wanted, config)
+            .ok_or_else(|| here.error(format!("Could not find `{}`", wanted)))?;
+        let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+        if let Some(start) = included
+            .stack
+            .iter()
+            .position(|(seen, _)| *seen == canonical)
+        {
+            let chain: Vec<&str> = included.stack[start..]
+                .iter()
+                .map(|(_, name)| name.as_str())
+                .collect();
+            return Err(here.error(format!(
+                "Include cycle: {} -> {}",
+                chain.join(" -> "),
+                path.display()
+            )));
+        }
+
+        let content = fs::read_to_string(&path)
+            .map_err(|_| here.error(format!("Could not read {}", path.display())))?;
+        expand(&path, &content, config, included, out)?;
+    }
+
+    included.stack.pop();
+    Ok(())
+}
+
+/// Reads a file and everything it includes, as one long list of lines.
+/// Includes are looked for next to the file that has the `.include`, then in
+/// each of the config's include dirs. `.if`s and `.rep`s are already dealt
+/// with, using the config's defines.
+pub fn read(path: &str, config: &SourceConfig) -> Result<Vec<SourceLine>, String> {
+    let content = fs::read_to_string(path).map_err(|_| "Could not read file".to_owned())?;
+    read_str(path, &content, config)
+}
+
+/// `read`, but the file's already been read. `path` is what it's called in
+/// errors, and where its includes are looked for from.
+pub fn read_str(
+    path: &str,
+    content: &str,
+    config: &SourceConfig,
+) -> Result<Vec<SourceLine>, String> {
+    let mut out = Vec::new();
+    expand(
+        Path::new(path),
+        content,
+        config,
+        &mut Included::default(),
+        &mut out,
+    )?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    /// A fresh directory under the temp dir with these files in it.
+    fn dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("sick-as-source-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        for (path, content) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    fn read_in(dir: &Path, file: &str, config: &SourceConfig) -> Result<Vec<SourceLine>, String> {
+        read(dir.join(file).to_str().unwrap(), config)
+    }
+
+    /// Each line as `file:line text`, with the file relative to `dir`.
+    fn lines(dir: &Path, lines: &[SourceLine]) -> Vec<String> {
+        let prefix = format!("{}/", dir.display());
+        lines
+            .iter()
+            .map(|at| {
+                format!(
+                    "{}:{} {}",
+                    at.file.trim_start_matches(&prefix),
+                    at.line,
+                    at.text
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn relative_to_the_including_file() {
+        let dir = dir(
+            "relative",
+            &[
+                ("main.sick", "nop\n.include \"lib/a.sick\"\nbye 0"),
+                ("lib/a.sick", "out 1\n.include \"b.sick\""),
+                ("lib/b.sick", "out 2"),
+                // Next to main, not a.sick, so not this one
+                ("b.sick", "out 3"),
+            ],
+        );
+        let got = read_in(&dir, "main.sick", &SourceConfig::default()).unwrap();
+        assert_eq!(
+            lines(&dir, &got),
+            [
+                "main.sick:1 nop",
+                "lib/a.sick:1 out 1",
+                "lib/b.sick:1 out 2",
+                "main.sick:3 bye 0"
+            ]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn include_dirs_in_order() {
+        let dir = dir(
+            "search",
+            &[
+                ("src/main.sick", ".include \"x.sick\""),
+                ("one/x.sick", "out 1"),
+                ("two/x.sick", "out 2"),
+                ("two/y.sick", "out 3"),
+            ],
+        );
+        let config = |dirs: &[&str]| SourceConfig {
+            include_dirs: dirs.iter().map(|name| dir.join(name)).collect(),
+            ..Default::default()
+        };
+        let text = |config: &SourceConfig| {
+            read_in(&dir, "src/main.sick", config)
+                .map(|got| got.iter().map(|at| at.text.clone()).collect::<Vec<_>>())
+        };
+
+        assert_eq!(text(&config(&["one", "two"])), Ok(vec!["out 1".to_owned()]));
+        assert_eq!(text(&config(&["two", "one"])), Ok(vec!["out 2".to_owned()]));
+        let err = text(&config(&[])).unwrap_err();
+        assert!(
+            err.ends_with("main.sick:1: Could not find `x.sick`"),
+            "{}",
+            err
+        );
+
+        // Next to the file beats the include dirs
+        fs::write(dir.join("src/x.sick"), "out 4").unwrap();
+        assert_eq!(text(&config(&["one", "two"])), Ok(vec!["out 4".to_owned()]));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn include_cycle() {
+        let dir = dir(
+            "cycle",
+            &[
+                ("a.sick", "nop\n.include \"b.sick\""),
+                ("b.sick", "\n\n.include \"a.sick\""),
+                ("self.sick", ".include \"self.sick\""),
+                // The same file twice is fine as long as it's not inside itself
+                ("twice.sick", ".include \"c.sick\"\n.include \"c.sick\""),
+                ("c.sick", "nop"),
+            ],
+        );
+        let config = SourceConfig::default();
+        let a = dir.join("a.sick").display().to_string();
+        let b = dir.join("b.sick").display().to_string();
+
+        assert_eq!(
+            read_in(&dir, "a.sick", &config),
+            Err(format!("{}:3: Include cycle: {} -> {} -> {}", b, a, b, a))
+        );
+        let err = read_in(&dir, "self.sick", &config).unwrap_err();
+        assert!(err.contains(":1: Include cycle: "), "{}", err);
+        assert_eq!(
+            read_in(&dir, "twice.sick", &config).map(|got| got.len()),
+            Ok(2)
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn errors_point_at_the_included_file() {
+        let dir = dir(
+            "errors",
+            &[
+                ("main.sick", "nop\nnop\n.include \"lib.sick\"\nbye 0"),
+                ("lib.sick", "# comment\nout 1\nadd 1 -> m1"),
+            ],
+        );
+        let lib = dir.join("lib.sick").display().to_string();
+
+        let got = read_in(&dir, "main.sick", &SourceConfig::default()).unwrap();
+        let err = assemble(got).unwrap_err();
+        assert!(err.starts_with(&format!("{}:3: ", lib)), "{}", err);
+
+        // And for the include itself
+        fs::write(dir.join("lib.sick"), "nop\n.include lib2.sick").unwrap();
+        assert_eq!(
+            read_in(&dir, "main.sick", &SourceConfig::default()),
+            Err(format!(
+                "{}:2: `.include` needs a path in quotes, like `.include \"lib.sick\"`",
+                lib
+            ))
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn defines(pairs: &[(&str, &str)]) -> SourceConfig {
+        SourceConfig {
+            defines: pairs
+                .iter()
+                .map(|(name, val)| (name.to_string(), val.to_string()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Each line as `line text`.
+    fn read_with(src: &str, config: &SourceConfig) -> Result<Vec<String>, String> {
+        read_str("test.sick", src, config).map(|got| {
+            got.iter()
+                .map(|at| format!("{} {}", at.line, at.text.trim()))
+                .collect()
+        })
+    }
+
+    #[test]
+    fn rep() {
+        let src = ".rep 2\n    out 1\n    .rep N\n        out 2\n    .endr\n.endr\nbye 0";
+        assert_eq!(
+            read_with(src, &defines(&[("N", " 3 ")])),
+            Ok(vec![
+                "2 out 1".to_owned(),
+                "4 out 2".to_owned(),
+                "4 out 2".to_owned(),
+                "4 out 2".to_owned(),
+                "2 out 1".to_owned(),
+                "4 out 2".to_owned(),
+                "4 out 2".to_owned(),
+                "4 out 2".to_owned(),
+                "7 bye 0".to_owned(),
+            ])
+        );
+        assert_eq!(
+            read_with(".rep 0\nout 1\n.endr", &SourceConfig::default()),
+            Ok(vec![])
+        );
+    }
+
+    #[test]
+    fn rep_errors() {
+        let err = |src: &str, config: &SourceConfig| read_with(src, config).unwrap_err();
+        let none = SourceConfig::default();
+        assert_eq!(
+            err(".rep\n.endr", &none),
+            "test.sick:1: `.rep` needs how many times to go, like `.rep 8`"
+        );
+        assert_eq!(
+            err(".rep N\n.endr", &none),
+            "test.sick:1: `N` isn't defined, give it a value with `-D N=8`"
+        );
+        assert_eq!(
+            err(".rep N\n.endr", &defines(&[("N", "lots")])),
+            "test.sick:1: `N` is `lots`, which isn't a number"
+        );
+        assert_eq!(
+            err(".rep 99999999999999999999999\n.endr", &none),
+            "test.sick:1: `99999999999999999999999` is way too many times"
+        );
+        assert_eq!(
+            err("nop\n.endr", &none),
+            "test.sick:2: `.endr` without a `.rep`"
+        );
+        assert_eq!(
+            err(".rep 2\nnop", &none),
+            "test.sick:1: `.rep` without an `.endr`"
+        );
+        assert_eq!(
+            err(".rep 2\n.endif", &none),
+            "test.sick:2: `.endif` can't end the `.rep` at test.sick:1, it needs an `.endr`"
+        );
+        assert_eq!(
+            err(".rep 2\n.endr 2", &none),
+            "test.sick:2: Unexpected characters: `2`"
+        );
+    }
+
+    #[test]
+    fn rep_too_big() {
+        let none = SourceConfig::default();
+        assert_eq!(
+            read_with(".rep 100000\nnop\n.endr", &none),
+            Err("test.sick:1: This `.rep` makes more than 65536 lines".to_owned())
+        );
+        // Each one's fine on its own, but not all together
+        assert_eq!(
+            read_with(".rep 1000\n.rep 1000\nnop\n.endr\n.endr", &none),
+            Err("test.sick:1: This `.rep` makes more than 65536 lines".to_owned())
+        );
+        let huge = format!(".rep {}\nnop\n.endr", usize::MAX);
+        assert!(read_with(&huge, &none).is_err());
+        // Nothing to copy is nothing however many times, and doesn't take forever
+        let empty = format!(".rep {}\n.endr", usize::MAX);
+        assert_eq!(read_with(&empty, &none), Ok(vec![]));
+    }
+
+    #[test]
+    fn if_defined() {
+        let src = "out 1\n.if DEBUG\n    out 2\n.endif\nout 3";
+        let text = |config: &SourceConfig| read_with(src, config).unwrap().join("\n");
+        assert_eq!(text(&SourceConfig::default()), "1 out 1\n5 out 3");
+        assert_eq!(
+            text(&defines(&[("DEBUG", "1")])),
+            "1 out 1\n3 out 2\n5 out 3"
+        );
+        assert_eq!(
+            text(&defines(&[("DEBUG", "yes")])),
+            "1 out 1\n3 out 2\n5 out 3"
+        );
+        assert_eq!(text(&defines(&[("DEBUG", "0")])), "1 out 1\n5 out 3");
+    }
+
+    #[test]
+    fn if_skips_everything_inside() {
+        // The `.rep` count and the include never get looked at
+        let src =
+            ".if A\n.rep NOPE\n.include \"nope.sick\"\n.endr\n.if B\nout 1\n.endif\n.endif\nbye 0";
+        assert_eq!(
+            read_with(src, &defines(&[("B", "1")])),
+            Ok(vec!["9 bye 0".to_owned()])
+        );
+        assert_eq!(
+            read_with(
+                ".rep 2\n.if A\nout 1\n.endif\n.endr",
+                &defines(&[("A", "1")])
+            ),
+            Ok(vec!["3 out 1".to_owned(), "3 out 1".to_owned()])
+        );
+    }
+
+    #[test]
+    fn if_errors() {
+        let err = |src: &str| read_with(src, &SourceConfig::default()).unwrap_err();
+        assert_eq!(
+            err(".if\n.endif"),
+            "test.sick:1: `.if` needs a name to check, like `.if DEBUG`"
+        );
+        assert_eq!(
+            err(".if A-B\n.endif"),
+            "test.sick:1: `.if` needs a name to check, like `.if DEBUG`"
+        );
+        assert_eq!(err("nop\n.endif"), "test.sick:2: `.endif` without an `.if`");
+        assert_eq!(
+            err(".if A\n.if B\n.endif"),
+            "test.sick:1: `.if` without an `.endif`"
+        );
+        assert_eq!(
+            err(".if A\n.endr"),
+            "test.sick:2: `.endr` can't end the `.if` at test.sick:1, it needs an `.endif`"
+        );
+        assert_eq!(
+            err(".if A\n.endif A"),
+            "test.sick:2: Unexpected characters: `A`"
+        );
+    }
+}
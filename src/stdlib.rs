@@ -0,0 +1,31 @@
+/// Goes up whenever a routine changes what it does or which cells it uses.
+pub const VERSION: u32 = 1;
+
+/// What goes in front of a path in an `.include` to get one of these.
+pub const PREFIX: &str = "std:";
+
+/// Every file in the library, by name. They're all macros, so including one
+/// doesn't add any instructions until something uses them.
+pub const FILES: [(&str, &str); 4] = [
+    ("compare.sick", include_str!("std/compare.sick")),
+    ("input.sick", include_str!("std/input.sick")),
+    ("math.sick", include_str!("std/math.sick")),
+    ("print.sick", include_str!("std/print.sick")),
+];
+
+/// The source of a library file, from its name without the prefix.
+pub fn get(name: &str) -> Option<&'static str> {
+    FILES
+        .iter()
+        .find(|(file, _)| *file == name)
+        .map(|(_, content)| *content)
+}
+
+/// The first line of a file's comment, to say what's in it.
+pub fn summary(content: &str) -> &str {
+    content
+        .lines()
+        .next()
+        .and_then(|line| line.strip_prefix('#'))
+        .map_or("", str::trim)
+}
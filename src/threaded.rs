@@ -0,0 +1,498 @@
+use crate::io::SickIo;
+use crate::machine::{Machine, RunError, MEMORY_SIZE};
+use crate::parser::{Instruction, Value};
+use std::num::Wrapping;
+use std::time::Instant;
+
+type Memory = [Wrapping<u8>; MEMORY_SIZE];
+
+/// What to do after an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Flow {
+    Next,
+    Jump(u8),
+    Exit(u8),
+    /// Something went wrong, and the message is in the last argument.
+    Fault,
+}
+
+/// One instruction, ready to go.
+///
+/// Everything in here is about keeping the IP out of memory. Most
+/// instructions just say `Next` and the loop adds 1 to a register, where the
+/// CPU can see it coming, instead of having to wait on a store to `m0` and a
+/// load back. `m0` only gets the IP before an instruction that reads it, and
+/// once it's all done. It's also why errors go in a side slot, so `Flow`
+/// stays small enough to come back in a register.
+type Op<I> = Box<dyn Fn(&mut Memory, &mut I, &mut Option<String>) -> Flow>;
+
+/// The program turned into a closure per instruction, with every operand
+/// already worked out to be a literal or an address. Running it is just
+/// calling whatever the IP points at, no matching on `Instruction` or `Value`
+/// every step like `Machine::step` does.
+pub struct Threaded<I: SickIo> {
+    ops: Vec<Op<I>>,
+    /// Which ones are `bye`, which doesn't count towards the step limit.
+    byes: Vec<bool>,
+    /// Which ones read `m0`, so it has to be the IP first.
+    reads_ip: Vec<bool>,
+}
+
+/// Saves a result and moves on. Writing to `m0` moves the IP to just past
+/// the value, same as the interpreter.
+#[inline(always)]
+fn store(mem: &mut Memory, tgt: usize, val: u8) -> Flow {
+    mem[tgt] = Wrapping(val);
+    if tgt == 0 {
+        Flow::Jump(val.wrapping_add(1))
+    } else {
+        Flow::Next
+    }
+}
+
+/// Sets `tgt` to `f` of a single operand. Each kind of operand gets its own
+/// closure so nothing's left to check at runtime.
+fn unary<I: SickIo, F>(src: Value, tgt: u8, f: F) -> Op<I>
+where
+    F: Fn(u8) -> u8 + 'static,
+{
+    let tgt = tgt as usize;
+    match src {
+        Value::Memory { addr } => {
+            let addr = addr as usize;
+            Box::new(move |mem, _, _| store(mem, tgt, f(mem[addr].0)))
+        }
+        Value::Literal { val } => {
+            let val = f(val);
+            Box::new(move |mem, _, _| store(mem, tgt, val))
+        }
+    }
+}
+
+/// Same as `unary` but with two operands.
+fn binary<I: SickIo, F>(left: Value, right: Value, tgt: u8, f: F) -> Op<I>
+where
+    F: Fn(u8, u8) -> u8 + 'static,
+{
+    use Value::{Literal as L, Memory as M};
+
+    match (left, right) {
+        (M { addr: l }, M { addr: r }) => {
+            let (l, r, tgt) = (l as usize, r as usize, tgt as usize);
+            Box::new(move |mem, _, _| store(mem, tgt, f(mem[l].0, mem[r].0)))
+        }
+        (M { addr: l }, L { val: r }) => unary(M { addr: l }, tgt, move |l| f(l, r)),
+        (L { val: l }, M { addr: r }) => unary(M { addr: r }, tgt, move |r| f(l, r)),
+        (L { val: l }, L { val: r }) => unary(L { val: f(l, r) }, tgt, |val| val),
+    }
+}
+
+/// `bak` and `fwd`. A taken jump with a literal count always lands in the
+/// same spot, so that gets worked out here.
+fn jump<I: SickIo>(inst: &Instruction, idx: usize, count: Value, check: Value) -> Op<I> {
+    let back = matches!(inst, Instruction::Bak { .. });
+
+    match (inst.jump_target(idx), count, check) {
+        // Never taken
+        (_, _, Value::Literal { val }) if val != 0 => Box::new(|_, _, _| Flow::Next),
+        (Some(target), _, Value::Literal { .. }) => {
+            let target = target as u8;
+            Box::new(move |_, _, _| Flow::Jump(target))
+        }
+        (Some(target), _, Value::Memory { addr }) => {
+            let (target, addr) = (target as u8, addr as usize);
+            Box::new(move |mem, _, _| match mem[addr].0 {
+                0 => Flow::Jump(target),
+                _ => Flow::Next,
+            })
+        }
+        // The IP is always `idx` here, so there's no need for m0
+        (None, Value::Memory { addr: count }, check) => {
+            let (count, here) = (count as usize, Wrapping(idx as u8));
+            let taken = move |mem: &Memory| match back {
+                true => Flow::Jump((here - mem[count]).0),
+                false => Flow::Jump((here + mem[count]).0),
+            };
+            match check {
+                Value::Literal { .. } => Box::new(move |mem, _, _| taken(mem)),
+                Value::Memory { addr } => {
+                    let addr = addr as usize;
+                    Box::new(move |mem, _, _| match mem[addr].0 {
+                        0 => taken(mem),
+                        _ => Flow::Next,
+                    })
+                }
+            }
+        }
+        (None, Value::Literal { .. }, _) => unreachable!(),
+    }
+}
+
+/// Reads an operand. Only for the instructions that don't run often enough
+/// to be worth specialising.
+fn read(mem: &Memory, val: Value) -> u8 {
+    match val {
+        Value::Literal { val } => val,
+        Value::Memory { addr } => mem[addr as usize].0,
+    }
+}
+
+fn addr(val: Value) -> u8 {
+    // The parser should ensure this
+    match val {
+        Value::Memory { addr } => addr,
+        _ => unreachable!(),
+    }
+}
+
+impl<I: SickIo> Threaded<I> {
+    pub fn compile(instructions: &[Instruction]) -> Self {
+        let mut ops: Vec<Op<I>> = Vec::with_capacity(instructions.len());
+
+        for (idx, inst) in instructions.iter().enumerate() {
+            ops.push(match *inst {
+                Instruction::Set { src, tgt } => unary(src, addr(tgt), |val| val),
+                Instruction::Not { src, tgt } => unary(src, addr(tgt), |val| (val == 0) as u8),
+                Instruction::And { left, right, tgt } => {
+                    binary(left, right, addr(tgt), |l, r| l & r)
+                }
+                Instruction::Xor { left, right, tgt } => {
+                    binary(left, right, addr(tgt), |l, r| l ^ r)
+                }
+                Instruction::Add { left, right, tgt } => {
+                    binary(left, right, addr(tgt), |l, r| l.wrapping_add(r))
+                }
+                Instruction::Sub { left, right, tgt } => {
+                    binary(left, right, addr(tgt), |l, r| l.wrapping_sub(r))
+                }
+                Instruction::Out { src } => Box::new(move |mem, io, _| {
+                    io.out(read(mem, src));
+                    Flow::Next
+                }),
+                Instruction::Num { src } => Box::new(move |mem, io, _| {
+                    io.num(read(mem, src));
+                    Flow::Next
+                }),
+                Instruction::Cin { tgt } => {
+                    let tgt = addr(tgt) as usize;
+                    Box::new(move |mem, io, fault| match io.cin() {
+                        Ok(val) => store(mem, tgt, val),
+                        Err(msg) => {
+                            *fault = Some(msg);
+                            Flow::Fault
+                        }
+                    })
+                }
+                Instruction::Nin { tgt } => {
+                    let tgt = addr(tgt) as usize;
+                    Box::new(move |mem, io, fault| match io.nin() {
+                        Ok(val) => store(mem, tgt, val),
+                        Err(msg) => {
+                            *fault = Some(msg);
+                            Flow::Fault
+                        }
+                    })
+                }
+                Instruction::Bak { count, check } | Instruction::Fwd { count, check } => {
+                    jump(inst, idx, count, check)
+                }
+                Instruction::Bye { code } => Box::new(move |mem, _, _| Flow::Exit(read(mem, code))),
+                Instruction::Nop => Box::new(|_, _, _| Flow::Next),
+            });
+        }
+
+        Threaded {
+            ops,
+            byes: instructions
+                .iter()
+                .map(|inst| matches!(inst, Instruction::Bye { .. }))
+                .collect(),
+            reads_ip: instructions
+                .iter()
+                .map(|inst| inst.reads().contains(&0))
+                .collect(),
+        }
+    }
+}
+
+impl<I: SickIo> Machine<I> {
+    /// Same as `run`, but with a program from `Threaded::compile`. Doesn't
+    /// keep any undo history.
+    pub fn run_threaded(&mut self, program: &Threaded<I>) -> Result<u8, RunError> {
+        let started = Instant::now();
+        let limited = self.config.max_steps.is_some() || self.config.timeout.is_some();
+
+        let (memory, io) = (&mut self.memory, &mut self.io);
+        let mut fault = None;
+        let mut ip = memory[0].0;
+        let mut ran = 0u64;
+
+        let result = loop {
+            let op = match program.ops.get(ip as usize) {
+                Some(op) => op,
+                None => break Err(format!("Instruction pointer out of bounds: {}", ip).into()),
+            };
+            // Instructions that read m0 still need to see the IP
+            if program.reads_ip[ip as usize] {
+                memory[0] = Wrapping(ip);
+            }
+
+            // Same checks as `run`, skipped entirely when there's no limit
+            if limited {
+                if let Some(max) = self.config.max_steps {
+                    if ran >= max && !program.byes[ip as usize] {
+                        break Err(RunError::StepLimit(max));
+                    }
+                }
+                if let Some(timeout) = self.config.timeout {
+                    if ran.is_multiple_of(1024) && started.elapsed() >= timeout {
+                        break Err(RunError::Timeout(timeout));
+                    }
+                }
+            }
+
+            match op(memory, io, &mut fault) {
+                Flow::Next => ip = ip.wrapping_add(1),
+                Flow::Jump(to) => ip = to,
+                Flow::Exit(code) => break Ok(code),
+                Flow::Fault => break Err(RunError::Fault(fault.take().unwrap_or_default())),
+            }
+            ran += 1;
+        };
+
+        memory[0] = Wrapping(ip);
+        self.steps += ran;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::BufferIo;
+    use crate::machine::MachineConfig;
+    use crate::parser::{get_program, parse_instruction};
+    use crate::program::Program;
+    use crate::source::SourceConfig;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::time::Duration;
+
+    fn parse(lines: &[&str]) -> Vec<Instruction> {
+        lines
+            .iter()
+            .map(|line| parse_instruction(line).unwrap())
+            .collect()
+    }
+
+    fn run_limited(
+        lines: &[&str],
+        max_steps: Option<u64>,
+        timeout: Option<Duration>,
+    ) -> (Result<u8, RunError>, u64) {
+        let config = MachineConfig {
+            max_steps,
+            timeout,
+            ..Default::default()
+        };
+        let mut machine = Machine::with_io(config, BufferIo::new(b""));
+        let result = machine.run_threaded(&Threaded::compile(&parse(lines)));
+        (result, machine.steps())
+    }
+
+    #[test]
+    fn bye_right_at_the_step_limit() {
+        let program = ["nop", "nop", "nop", "bye 4"];
+        assert_eq!(run_limited(&program, Some(3), None), (Ok(4), 3));
+        assert_eq!(
+            run_limited(&program, Some(2), None),
+            (Err(RunError::StepLimit(2)), 2)
+        );
+        assert_eq!(
+            run_limited(&["nop", "bye 1"], Some(0), None),
+            (Err(RunError::StepLimit(0)), 0)
+        );
+    }
+
+    #[test]
+    fn timeout() {
+        let timeout = Duration::from_millis(20);
+        let (result, steps) = run_limited(&["bak 0, 0"], None, Some(timeout));
+        assert_eq!(result, Err(RunError::Timeout(timeout)));
+        // The clock only gets looked at every 1024 steps
+        assert!(steps > 0);
+        assert_eq!(steps % 1024, 0);
+    }
+
+    fn machine(program: &Program, input: &[u8]) -> Machine<BufferIo> {
+        let config = MachineConfig {
+            max_steps: Some(100_000),
+            ..Default::default()
+        };
+        let mut machine = Machine::with_io(config, BufferIo::new(input));
+        machine.memory = program.memory();
+        machine
+    }
+
+    /// Runs it both ways and checks they end up in the same place: same
+    /// output, memory, step count and way of stopping. Gives back how it
+    /// stopped.
+    fn agrees(program: &Program, input: &[u8]) -> Result<u8, RunError> {
+        let mut interpreted = machine(program, input);
+        let expected = interpreted.run(&program.instructions);
+
+        let mut threaded = machine(program, input);
+        let got = threaded.run_threaded(&Threaded::compile(&program.instructions));
+
+        assert_eq!(got, expected);
+        assert_eq!(
+            String::from_utf8_lossy(&threaded.io.output),
+            String::from_utf8_lossy(&interpreted.io.output)
+        );
+        assert_eq!(threaded.memory, interpreted.memory);
+        assert_eq!(threaded.steps(), interpreted.steps());
+        got
+    }
+
+    fn agrees_lines(lines: &[&str], input: &[u8]) -> Result<u8, RunError> {
+        agrees(&Program::from(parse(lines)), input)
+    }
+
+    #[test]
+    fn examples() {
+        let inputs: [&[u8]; 5] = [b"3\n3\n", b"3\n4\n", b"255\n0\n", b"x\n", b""];
+        for file in [
+            "example.sick",
+            "sick-examples/equality.sick",
+            "sick-examples/stuff.sick",
+        ] {
+            let program = get_program(file, &SourceConfig::default()).unwrap();
+            for input in inputs {
+                let _ = agrees(&program, input);
+            }
+        }
+    }
+
+    #[test]
+    fn reads_m0() {
+        let lines = [
+            "num m0",
+            "add m0, 10 -> m1",
+            "fwd 2, 0",
+            "nop",
+            "xor m0, m1 -> m2",
+            "num m2",
+            "bye m0",
+        ];
+        assert_eq!(agrees_lines(&lines, b""), Ok(6));
+    }
+
+    #[test]
+    fn writes_m0() {
+        let lines = [
+            "nin -> m1",
+            // Lands just past whatever's written
+            "add m1, 3 -> m0",
+            "bye 1",
+            "bye 2",
+            "out 65",
+            "out 66",
+            "add m0, 1 -> m0",
+            "bye 3",
+            "num m0",
+            "nin -> m0",
+            "bye 4",
+        ];
+        assert_eq!(agrees_lines(&lines, b"0\n6\n"), Ok(3));
+        assert_eq!(agrees_lines(&lines, b"1\n9\n"), Ok(4));
+        // Goes back round to the first `nin`, which has nothing left to read
+        assert!(matches!(
+            agrees_lines(&lines, b"1\n255\n"),
+            Err(RunError::Fault(_))
+        ));
+        assert_eq!(
+            agrees_lines(&lines, b"40\n"),
+            Err(RunError::Fault(
+                "Instruction pointer out of bounds: 44".to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn jumps_by_memory() {
+        let lines = [
+            "set 3 -> m1",
+            "num m1",
+            "sub m1, 1 -> m1",
+            "set 4 -> m2",
+            "not m1 -> m4",
+            "bak m2, m4",
+            "add m1, 2 -> m3",
+            "fwd m3, 0",
+            "bye 1",
+            "bye 2",
+            "bye 3",
+        ];
+        assert_eq!(agrees_lines(&lines, b""), Ok(2));
+
+        // Jumping by 0 goes nowhere, forever
+        assert_eq!(
+            agrees_lines(&["fwd m1, 0"], b""),
+            Err(RunError::StepLimit(100_000))
+        );
+    }
+
+    #[test]
+    fn ip_wraps() {
+        // Falling off 255
+        let mut lines = vec![
+            "add m1, 1 -> m1",
+            "num m1",
+            "sub m1, 3 -> m2",
+            "fwd 2, m2",
+            "bye 7",
+        ];
+        lines.resize(256, "nop");
+        lines.push("bye 9");
+        assert_eq!(agrees_lines(&lines, b""), Ok(7));
+
+        // Jumping past 0 and past 255
+        let mut lines = vec!["fwd 3, m1", "set 1 -> m1", "bak 3, 0", "bye 5"];
+        lines.resize(255, "nop");
+        lines.push("fwd 3, 0");
+        assert_eq!(agrees_lines(&lines, b""), Ok(5));
+    }
+
+    #[test]
+    fn faults() {
+        assert_eq!(
+            agrees_lines(&["out 65", "fwd 10, 0"], b""),
+            Err(RunError::Fault(
+                "Instruction pointer out of bounds: 11".to_owned()
+            ))
+        );
+        assert_eq!(
+            agrees_lines(&["cin -> m1", "cin -> m1"], b"a"),
+            Err(RunError::Fault("EOF while reading input".to_owned()))
+        );
+        assert_eq!(
+            agrees_lines(&["nin -> m1"], b"256\n"),
+            Err(RunError::Fault("Invalid unsigned 8-bit integer".to_owned()))
+        );
+    }
+
+    #[test]
+    fn m255() {
+        // There's no m255, and both of them fall over the same way
+        for lines in [["set 1 -> m255", "bye 0"], ["add m255, 1 -> m1", "bye 0"]] {
+            let instructions = parse(&lines);
+            let interpreted = catch_unwind(AssertUnwindSafe(|| {
+                machine(&Program::default(), b"").run(&instructions)
+            }));
+            let threaded = catch_unwind(AssertUnwindSafe(|| {
+                machine(&Program::default(), b"").run_threaded(&Threaded::compile(&instructions))
+            }));
+            assert!(interpreted.is_err());
+            assert!(threaded.is_err());
+        }
+    }
+}
@@ -0,0 +1,85 @@
+//! Compiles Brainfuck into sick and runs it.
+
+use sick_as::{bf, BufferIo, Instruction, Machine, MachineConfig};
+use std::fs;
+use std::path::Path;
+
+/// What the compiled program exits with, run on its own.
+fn exit_code(src: &str) -> u8 {
+    let instructions: Vec<Instruction> = bf::compile(src)
+        .unwrap()
+        .into_iter()
+        .map(|(inst, _)| inst)
+        .collect();
+    let mut machine = Machine::with_io(MachineConfig::default(), BufferIo::new(b""));
+    machine.run(&instructions).unwrap()
+}
+
+#[test]
+fn examples() {
+    let mut files: Vec<_> = fs::read_dir("bf-examples")
+        .expect("Run from the repo")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "bf"))
+        .collect();
+    files.sort();
+    assert!(files.iter().any(|path| path.ends_with("wikipedia.bf")));
+
+    for path in files {
+        let source = fs::read_to_string(&path).unwrap();
+        let input = fs::read(path.with_extension("in")).unwrap_or_default();
+        let expected = fs::read(path.with_extension("out")).unwrap();
+        let got = bf::run(&source, &input).unwrap_or_else(|msg| panic!("{:?}: {}", path, msg));
+        assert_eq!(
+            String::from_utf8_lossy(&got),
+            String::from_utf8_lossy(&expected),
+            "{:?}",
+            Path::new(&path)
+        );
+    }
+}
+
+#[test]
+fn scan_for_zero() {
+    // Three 1s after cell 0, then finds the 0 after them and uses it to add
+    // 65 to the last one, then goes back to cell 0 the same way
+    let src = ">+>+>+<<[>]++++++++[<++++++++>-]<+.[<]>.";
+    assert_eq!(bf::run(src, b"").unwrap(), b"B\x01");
+}
+
+#[test]
+fn read_while_moving() {
+    assert_eq!(bf::run(">,[.>,]<[<]>.", b"hi\0").unwrap(), b"hih");
+}
+
+#[test]
+fn off_the_left_of_the_tape() {
+    assert_eq!(
+        bf::run("+[<]", b""),
+        Err("The pointer went off the end of the tape".to_owned())
+    );
+    assert_eq!(exit_code("+[<]"), bf::OFF_THE_TAPE);
+}
+
+#[test]
+fn off_the_right_of_the_tape() {
+    assert_eq!(
+        bf::run("+[>+]", b""),
+        Err("The pointer went off the end of the tape".to_owned())
+    );
+    assert_eq!(exit_code("+[>+]"), bf::OFF_THE_TAPE);
+}
+
+#[test]
+fn moves_further_than_the_tape() {
+    let src = format!("+[>]{}", ">".repeat(200));
+    let err = bf::compile(&src).unwrap_err();
+    assert!(err.contains("moves 200 at once"), "{}", err);
+}
+
+#[test]
+fn balanced_loops_keep_the_pointer_at_compile_time() {
+    let compiled = bf::compile("+>+[<+>-]").unwrap();
+    let text: Vec<String> = compiled.iter().map(|(inst, _)| inst.to_string()).collect();
+    assert!(text.iter().all(|line| !line.contains("m254")), "{:?}", text);
+}
@@ -0,0 +1,198 @@
+//! Runs the sick-as binary itself.
+
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+fn write(name: &str, source: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("sick-as-cli-{}-{}", std::process::id(), name));
+    fs::write(&path, source).unwrap();
+    path
+}
+
+fn sick_as(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_sick-as"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn limits_exit_with_124() {
+    let path = write("forever.sick", "nop\nbak 1, 0\n");
+    let file = path.to_str().unwrap();
+
+    for engine in [None, Some("--threaded")] {
+        for limit in [["--max-steps", "10"], ["--timeout", "0.05"]] {
+            let mut args = vec![file];
+            args.extend(limit);
+            args.extend(engine);
+            let out = sick_as(&args);
+            assert_eq!(out.status.code(), Some(124), "{:?}", args);
+        }
+    }
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn bye_right_at_the_step_limit() {
+    let path = write("bye.sick", "nop\nnop\nbye 3\n");
+    let file = path.to_str().unwrap();
+    assert_eq!(sick_as(&[file, "--max-steps", "2"]).status.code(), Some(3));
+    assert_eq!(
+        sick_as(&[file, "--max-steps", "1"]).status.code(),
+        Some(124)
+    );
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn timeout_must_be_positive() {
+    let path = write("zero.sick", "bye 0\n");
+    let file = path.to_str().unwrap();
+    for secs in ["0", "-1", "NaN"] {
+        let out = sick_as(&[file, "--timeout", secs]);
+        assert_eq!(out.status.code(), Some(1), "{}", secs);
+        assert!(String::from_utf8_lossy(&out.stderr).contains("--timeout needs a positive number"));
+    }
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn fmt_check() {
+    let path = write(
+        "messy.sick",
+        "set   1->m1 # one\nadd m1,2   ->  m2    # two\n",
+    );
+    let file = path.to_str().unwrap();
+
+    let out = sick_as(&["fmt", "--check", file]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stdout).contains("needs formatting"));
+    // --check leaves it alone
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        "set   1->m1 # one\nadd m1,2   ->  m2    # two\n"
+    );
+
+    assert_eq!(sick_as(&["fmt", file]).status.code(), Some(0));
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        "set 1 -> m1      # one\nadd m1, 2 -> m2  # two\n"
+    );
+    let out = sick_as(&["fmt", "--check", file]);
+    assert_eq!(out.status.code(), Some(0));
+    assert!(out.stdout.is_empty());
+
+    // Already tidy, just not with this indent
+    assert_eq!(
+        sick_as(&["fmt", "--check", "--indent", "2", file])
+            .status
+            .code(),
+        Some(1)
+    );
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn listing() {
+    let dir = std::env::temp_dir().join(format!("sick-as-cli-{}-listing", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("main.sick"),
+        r#".macro twice x   # prints it twice
+    out \x
+    out \x
+.endm
+
+set 72 -> m1
+twice m1
+.include "lib.sick"
+loop:
+    prn "hi"
+    fwd 2, m1
+    bak loop, 0
+out 33
+bye 0
+"#,
+    )
+    .unwrap();
+    fs::write(dir.join("lib.sick"), "# lib\nadd m1, 200 -> m2\nnum m2\n").unwrap();
+
+    let out = Command::new(env!("CARGO_BIN_EXE_sick-as"))
+        .args(["assemble", "main.sick", "--listing", "main.lst"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+
+    // Index, bytecode, instruction, then where it came from, which is only
+    // there for the first instruction from each line
+    assert_eq!(
+        fs::read_to_string(dir.join("main.lst")).unwrap(),
+        r#" 0  00 01 48 00 01        set 72 -> m1       main.sick:6  set 72 -> m1
+ 1  06 00 01              out m1             main.sick:2  out \x (in `twice` from main.sick:7)
+ 2  06 00 01              out m1             main.sick:3  out \x (in `twice` from main.sick:7)
+ 3  04 00 01 01 c8 00 02  add m1, 200 -> m2  lib.sick:2  add m1, 200 -> m2
+ 4  07 00 02              num m2             lib.sick:3  num m2
+ 5  06 01 68              out 104            main.sick:10  prn "hi"
+ 6  06 01 69              out 105
+ 7  0b 01 02 00 01        fwd 2, m1          main.sick:11  fwd 2, m1
+ 8  0a 01 03 01 00        bak 3, 0           main.sick:12  bak loop, 0
+ 9  06 01 21              out 33             main.sick:13  out 33
+10  0c 01 00              bye 0              main.sick:14  bye 0
+"#
+    );
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn fault_in_an_include() {
+    let dir = std::env::temp_dir().join(format!("sick-as-cli-{}-fault", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("main.sick"),
+        "out 65\n.include \"lib.sick\"\nbye 0\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("lib.sick"),
+        "# reads one too many\n    cin -> m1\n    cin -> m2\n",
+    )
+    .unwrap();
+
+    for engine in [None, Some("--threaded")] {
+        let mut args = vec!["main.sick"];
+        args.extend(engine);
+        let out = Command::new(env!("CARGO_BIN_EXE_sick-as"))
+            .args(&args)
+            .current_dir(&dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .and_then(|mut child| {
+                child.stdin.take().unwrap().write_all(b"x")?;
+                child.wait_with_output()
+            })
+            .unwrap();
+        assert_eq!(out.status.code(), Some(1), "{:?}", args);
+        assert_eq!(String::from_utf8_lossy(&out.stdout), "A");
+        assert!(
+            String::from_utf8_lossy(&out.stderr)
+                .contains("EOF while reading input at lib.sick:3:5: cin -> m2"),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+    }
+
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,119 @@
+//! Builds programs with every backend that can run here and checks they do
+//! the same as the interpreter.
+
+use sick_as::compile::{self, Target};
+use sick_as::{asm, source, Program, SourceConfig};
+use std::fs;
+use std::process::Command;
+
+fn load(path: &str) -> Program {
+    sick_as::bytecode::load(path).unwrap_or_else(|msg| panic!("{}: {}", path, msg))
+}
+
+fn installed(tool: &str) -> bool {
+    Command::new(tool).arg("--version").output().is_ok()
+}
+
+/// The backends that can be run here. The x86-64 ones need x86-64 Linux, and
+/// its assembly needs `as` and `ld` on top of that. Wasm runs under node, and
+/// the text needs something to assemble it.
+fn targets() -> Vec<Target> {
+    let mut targets = vec![Target::C, Target::Rust];
+    if cfg!(all(target_arch = "x86_64", target_os = "linux")) {
+        targets.push(Target::Elf);
+        if installed("as") && installed("ld") {
+            targets.push(Target::X86);
+        }
+    }
+    if installed("node") {
+        targets.push(Target::Wasm);
+        if installed("wat2wasm") || installed("wasm-tools") {
+            targets.push(Target::Wat);
+        }
+    }
+    targets
+}
+
+/// Checks `program` on each backend, and that every input got compared.
+fn agrees(name: &str, program: &Program, inputs: &[Vec<u8>]) {
+    for target in targets() {
+        let compared = compile::test(program, target, inputs)
+            .unwrap_or_else(|msg| panic!("{} with {:?}: {}", name, target, msg));
+        assert_eq!(compared, inputs.len(), "{} with {:?}", name, target);
+    }
+}
+
+#[test]
+fn sick_examples() {
+    let inputs = [
+        b"3\n3\n".to_vec(),
+        b"3\n4\n".to_vec(),
+        b"255\n0\n".to_vec(),
+        b"x\n".to_vec(),
+        // Runs out of input halfway
+        b"".to_vec(),
+    ];
+
+    let mut files: Vec<_> = fs::read_dir("sick-examples")
+        .expect("Run from the repo")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "sick"))
+        .collect();
+    files.push("example.sick".into());
+    files.sort();
+
+    for path in files {
+        let path = path.to_str().unwrap();
+        agrees(path, &load(path), &inputs);
+    }
+}
+
+#[test]
+fn ip_wraps_after_255() {
+    // Counts to 2 by going round, and the `bye 9` past the end never runs
+    let mut src = "
+add m1, 1 -> m1
+num m1
+xor m1, 2 -> m2
+not m2 -> m3
+fwd 2, m3
+bye 7
+"
+    .to_owned();
+    src.push_str(&"nop\n".repeat(250));
+    src.push_str("bye 9\n");
+
+    let lines = source::read_str("wrap.sick", &src, &SourceConfig::default()).unwrap();
+    let program = asm::assemble(lines).unwrap();
+    assert_eq!(program.instructions.len(), 257);
+    agrees("wrap", &program, &[Vec::new()]);
+}
+
+#[test]
+fn writes_to_m0() {
+    // Lands on one of the `out`s depending on the number, then jumps by a
+    // memory value to one of the `bye`s. 252 wraps back round to the `nin`.
+    let src = "
+nin -> m1
+add m1, 3 -> m0
+bye 1
+bye 2
+out 65
+out 66
+out 67
+num m0
+add m1, 1 -> m2
+fwd m2, 0
+bye 3
+bye 4
+bye 5
+bye 6
+";
+    let lines = source::read_str("m0.sick", src, &SourceConfig::default()).unwrap();
+    let program = asm::assemble(lines).unwrap();
+    let inputs: Vec<Vec<u8>> = ["0", "1", "2", "3", "200", "252", "x"]
+        .iter()
+        .map(|input| format!("{}\n", input).into_bytes())
+        .collect();
+    agrees("m0", &program, &inputs);
+}
@@ -0,0 +1,184 @@
+//! Runs the routines in the standard library and checks what they do.
+
+use sick_as::{asm, source, stdlib, BufferIo, Machine, MachineConfig, SourceConfig};
+
+/// Values that cover the edges: 0, the ends of each digit count, 255, and
+/// both sides of 128.
+const EDGES: [u8; 11] = [0, 1, 2, 9, 10, 99, 100, 127, 128, 254, 255];
+
+/// Assembles `src` and runs it with `input`, giving back what it printed.
+fn run(src: &str, input: &[u8]) -> String {
+    let lines = source::read_str("test.sick", src, &SourceConfig::default())
+        .unwrap_or_else(|msg| panic!("{}\n{}", msg, src));
+    let program = asm::assemble(lines).unwrap_or_else(|msg| panic!("{}\n{}", msg, src));
+    // Past 255 instructions the IP wraps, and the test would be wrong
+    assert!(program.instructions.len() <= 255, "Too long:\n{}", src);
+
+    let config = MachineConfig {
+        max_steps: Some(1_000_000),
+        ..Default::default()
+    };
+    let mut machine = Machine::with_io(config, BufferIo::new(input));
+    machine.memory = program.memory();
+    let code = machine
+        .run(&program.instructions)
+        .unwrap_or_else(|err| panic!("{}\n{}", err, src));
+    assert_eq!(code, 0);
+
+    String::from_utf8(machine.io.output).expect("Tests only print ASCII")
+}
+
+#[test]
+fn every_file_assembles() {
+    for (name, _) in stdlib::FILES {
+        run(&format!(".include \"std:{}\"\nbye 0\n", name), b"");
+    }
+}
+
+#[test]
+fn files_are_only_included_once() {
+    // print.sick includes compare.sick too, which would define `lt` twice
+    let src = "
+.include \"std:compare.sick\"
+.include \"std:print.sick\"
+.include \"std:print.sick\"
+bye 0
+";
+    run(src, b"");
+}
+
+#[test]
+fn unknown_file() {
+    let err = source::read_str(
+        "test.sick",
+        ".include \"std:nope.sick\"",
+        &SourceConfig::default(),
+    )
+    .unwrap_err();
+    assert!(err.contains("There's no `std:nope.sick`"), "{}", err);
+}
+
+#[test]
+fn mul() {
+    for a in EDGES {
+        for b in [0, 1, 2, 3, 10, 255] {
+            let src = format!(
+                ".include \"std:math.sick\"\nmul {}, {} -> m1\nnum m1\nbye 0\n",
+                a, b
+            );
+            assert_eq!(
+                run(&src, b""),
+                a.wrapping_mul(b).to_string(),
+                "{} * {}",
+                a,
+                b
+            );
+        }
+    }
+}
+
+#[test]
+fn mul_into_an_argument() {
+    let src = "
+.include \"std:math.sick\"
+set 6 -> m1
+set 7 -> m2
+mul m1, m2 -> m1
+num m1
+out 32
+mul m2, m2 -> m2
+num m2
+bye 0
+";
+    assert_eq!(run(src, b""), "42 49");
+}
+
+#[test]
+fn lt() {
+    for a in EDGES {
+        for b in EDGES {
+            let src = format!(
+                ".include \"std:compare.sick\"\nset {} -> m1\nlt m1, {} -> m2\nnum m2\nbye 0\n",
+                a, b
+            );
+            let want = if a < b { "1" } else { "0" };
+            assert_eq!(run(&src, b""), want, "{} < {}", a, b);
+        }
+    }
+}
+
+#[test]
+fn print_pad() {
+    for a in EDGES {
+        for (pad, c) in [(48, '0'), (32, ' ')] {
+            let src = format!(
+                ".include \"std:print.sick\"\nprint_pad {}, {}\nbye 0\n",
+                a, pad
+            );
+            let want = format!("{:>3}", a).replace(' ', &c.to_string());
+            assert_eq!(run(&src, b""), want);
+        }
+    }
+}
+
+#[test]
+fn print_pad_from_memory() {
+    let src = "
+.include \"std:print.sick\"
+set 7 -> m1
+set 95 -> m2
+print_pad m1, m2
+bye 0
+";
+    assert_eq!(run(src, b""), "__7");
+}
+
+/// Reads a line, then prints how long it was and what's in the buffer.
+const READ_LINE: &str = "
+.include \"std:input.sick\"
+read_line m1
+num m1
+out 58
+out m200
+out m201
+out m202
+out m203
+out m204
+out m205
+out m206
+out m207
+out m208
+out m209
+out m210
+out m211
+out m212
+out m213
+out m214
+out m215
+bye 0
+";
+
+#[test]
+fn read_line() {
+    assert_eq!(run(READ_LINE, b"hello\n"), "5:hello\0\0\0\0\0\0\0\0\0\0\0");
+    assert_eq!(run(READ_LINE, b"\n"), format!("0:{}", "\0".repeat(16)));
+}
+
+#[test]
+fn read_line_stops_at_16() {
+    let src = "
+.include \"std:input.sick\"
+read_line m1
+num m1
+out 32
+out m200
+out m215
+read_line m1
+out 32
+num m1
+out 32
+out m200
+bye 0
+";
+    assert_eq!(run(src, b"abcdefghijklmnopqrstuvwxyz\n"), "16 ap 10 q");
+}